@@ -1,5 +1,254 @@
 #![deny(warnings, clippy::all)]
 
+use mqtt_rtic_common::config::{DIGITAL_OUTPUT_PINS, NTP_SERVERS_MAX};
+use mqtt_rtic_common::diagnostics::STATIC_ROUTES_MAX;
+use mqtt_rtic_common::message::{MQTT_MESSAGE_SIZE_MIN, TOPIC_SIZE_MAX};
+use mqtt_rtic_common::settings::{DIGITAL_OUTPUTS_MAX, OUTPUT_NAME_MAX};
+use mqtt_rtic_common::sparkplug::{self, SPARKPLUG_MESSAGE_SIZE_MIN};
+use mqtt_rtic_common::telemetry::ANALOG_INPUTS_MAX;
+use std::{env, fs, path::Path};
+
+/// A size that may be set at build time through the environment.
+struct Size {
+    /// The environment variable.
+    var: &'static str,
+    /// The exported constant.
+    name: &'static str,
+    doc: &'static str,
+    default: usize,
+    min: usize,
+    /// The default and minimum with `SPARKPLUG_GROUP_ID` set, if they differ.
+    sparkplug: Option<usize>,
+}
+
+impl Size {
+    /// Get the default and minimum of the size.
+    fn bounds(&self) -> (usize, usize) {
+        match self.sparkplug {
+            Some(size) if env::var_os("SPARKPLUG_GROUP_ID").is_some() => (size, size),
+            _ => (self.default, self.min),
+        }
+    }
+}
+
+const SIZES: [Size; 5] = [
+    // Messages must hold the telemetry and a document of its schema, or NBIRTH in
+    // Sparkplug mode, the common crate tests the worst cases against the minimums
+    Size {
+        var: "MQTT_MESSAGE_SIZE",
+        name: "MQTT_MESSAGE_SIZE_MAX",
+        doc: "The largest MQTT message.",
+        default: MQTT_MESSAGE_SIZE_MIN,
+        min: MQTT_MESSAGE_SIZE_MIN,
+        sparkplug: Some(SPARKPLUG_MESSAGE_SIZE_MIN),
+    },
+    // The default is an estimate, set it from the high-water mark measured on the target
+    Size {
+        var: "STACK_SIZE",
+        name: "STACK_SIZE",
+        doc: "RAM reserved for the stack, beyond the resources `init` builds on it.",
+        default: 16 * 1024,
+        min: 4 * 1024,
+        sparkplug: None,
+    },
+    Size {
+        var: "ETH_RX_DESCRIPTORS",
+        name: "RX_DESC_RING_COUNT",
+        doc: "Ethernet frames the MAC can receive before the IP stack is polled.",
+        default: 8,
+        min: 2,
+        sparkplug: None,
+    },
+    Size {
+        var: "ETH_TX_DESCRIPTORS",
+        name: "TX_DESC_RING_COUNT",
+        doc: "Ethernet frames the IP stack can queue for transmission.",
+        default: 4,
+        min: 2,
+        sparkplug: None,
+    },
+    // The gateway, broker and servers, over both protocols
+    Size {
+        var: "NEIGHBOR_CACHE_SIZE",
+        name: "NUM_NEIGHBOR_CACHE_ENTRIES",
+        doc: "Entries of the neighbor cache, shared by ARP and NDP.",
+        default: 16,
+        min: 4,
+        sparkplug: None,
+    },
+];
+
 fn main() {
-    built::write_built_file().expect("Failed to acquire build-time information")
+    built::write_built_file().expect("Failed to acquire build-time information");
+    write_memory_file();
+    write_sizes_file();
+    validate_config();
+
+    // The environment is only read for the sizes, the other settings use `option_env!`
+    for size in SIZES.iter() {
+        println!("cargo:rerun-if-env-changed={}", size.var);
+    }
+    println!("cargo:rerun-if-env-changed=SPARKPLUG_GROUP_ID");
+    println!("cargo:rerun-if-env-changed=NTP_SERVERS");
+    println!("cargo:rerun-if-env-changed=STATIC_ROUTES");
+    println!("cargo:rerun-if-env-changed=ADC_CHANNELS");
+    println!("cargo:rerun-if-env-changed=DIGITAL_OUTPUTS");
+    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Export the RAM size of `memory.x` for the static RAM budget check.
+fn write_memory_file() {
+    let memory = fs::read_to_string("memory.x").expect("Failed to read memory.x");
+    let ram_size = memory
+        .lines()
+        .find(|line| line.trim_start().starts_with("RAM"))
+        .and_then(|line| line.split("LENGTH").nth(1))
+        .map(|length| length.trim_start_matches(|c: char| c == '=' || c.is_whitespace()))
+        .and_then(parse_size)
+        .expect("Failed to parse the RAM LENGTH of memory.x");
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set");
+    fs::write(
+        Path::new(&out_dir).join("memory.rs"),
+        format!("pub const RAM_SIZE: usize = {};\n", ram_size),
+    )
+    .expect("Failed to write memory.rs");
+}
+
+/// Export the sizes that may be set at build time, e.g.
+///
+/// export MQTT_MESSAGE_SIZE="4096"
+/// export STACK_SIZE="24K"
+fn write_sizes_file() {
+    let mut sizes = String::new();
+    for size in SIZES.iter() {
+        let (default, min) = size.bounds();
+        let value = env::var(size.var)
+            .map(|value| {
+                parse_size(&value).unwrap_or_else(|| panic!("Failed to parse {}", size.var))
+            })
+            .unwrap_or(default);
+        assert!(value >= min, "{} must be at least {}", size.var, min);
+        sizes += &format!(
+            "/// {} `{}`, default {}.\npub const {}: usize = {};\n",
+            size.doc, size.var, default, size.name, value
+        );
+    }
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set");
+    fs::write(Path::new(&out_dir).join("sizes.rs"), sizes).expect("Failed to write sizes.rs");
+}
+
+/// Reject the configuration read by `Config::load_from_env` that would fail at boot.
+fn validate_config() {
+    validate_list_len("NTP_SERVERS", NTP_SERVERS_MAX);
+    validate_list_len("STATIC_ROUTES", STATIC_ROUTES_MAX);
+    validate_list_len("ADC_CHANNELS", ANALOG_INPUTS_MAX);
+    validate_list_len("DIGITAL_OUTPUTS", DIGITAL_OUTPUTS_MAX);
+    if let Ok(outputs) = env::var("DIGITAL_OUTPUTS") {
+        validate_outputs(&outputs);
+    }
+    if let Ok(group_id) = env::var("SPARKPLUG_GROUP_ID") {
+        assert!(
+            is_valid_level(&group_id),
+            "SPARKPLUG_GROUP_ID `{}` isn't a valid topic level",
+            group_id
+        );
+        // The edge node ID is the host name `<app>-<mac>`, the binary is named after the
+        // package
+        let app = env::var("CARGO_PKG_NAME").expect("CARGO_PKG_NAME not set");
+        let edge_node_id = app.len() + "-02-00-00-00-00-00".len();
+        let len_max = TOPIC_SIZE_MAX - sparkplug::topic_size(0, edge_node_id);
+        assert!(
+            group_id.len() <= len_max,
+            "SPARKPLUG_GROUP_ID must be at most {} bytes",
+            len_max
+        );
+    }
+}
+
+/// Check `<pin>=<name>:<polarity>:<default>:<fail-safe>` digital outputs, e.g.
+/// `PG0=relay1:high:off:off`, each with a pin and a name of its own.
+fn validate_outputs(outputs: &str) {
+    let mut pins = Vec::new();
+    let mut names = Vec::new();
+    for output in outputs.split(',').map(str::trim) {
+        let fields: Vec<_> = output.split(['=', ':']).collect();
+        let (pin, name, polarity, states) = match fields[..] {
+            [pin, name, polarity, default, fail_safe] => {
+                (pin, name, polarity, [default, fail_safe])
+            }
+            _ => panic!(
+                "Invalid output `{}`, expected `<pin>=<name>:<polarity>:<default>:<fail-safe>`",
+                output
+            ),
+        };
+        assert!(
+            DIGITAL_OUTPUT_PINS.contains(&pin),
+            "Invalid output pin `{}`, expected one of {:?}",
+            pin,
+            DIGITAL_OUTPUT_PINS
+        );
+        assert!(
+            is_valid_level(name) && name.len() <= OUTPUT_NAME_MAX,
+            "Invalid output name `{}`, at most {} bytes",
+            name,
+            OUTPUT_NAME_MAX
+        );
+        assert!(
+            polarity == "high" || polarity == "low",
+            "Invalid output polarity `{}`, expected `high` or `low`",
+            polarity
+        );
+        for state in states {
+            assert!(
+                state == "on" || state == "off",
+                "Invalid output state `{}`, expected `on` or `off`",
+                state
+            );
+        }
+        assert!(!pins.contains(&pin), "Output pin {} reused", pin);
+        assert!(!names.contains(&name), "Output name `{}` reused", name);
+        pins.push(pin);
+        names.push(name);
+    }
+}
+
+/// Check that a comma-separated list has at most `len_max` entries.
+fn validate_list_len(var: &str, len_max: usize) {
+    if let Ok(list) = env::var(var) {
+        let len = list.split(',').count();
+        assert!(
+            len <= len_max,
+            "{} has {} entries, at most {} are supported",
+            var,
+            len,
+            len_max
+        );
+    }
+}
+
+/// Whether a value is usable as a topic level.
+fn is_valid_level(level: &str) -> bool {
+    !level.is_empty() && !level.contains(['/', '+', '#'])
+}
+
+/// Parse a linker script size such as `192K`, `2M` or `0x30000`.
+fn parse_size(size: &str) -> Option<usize> {
+    let end = size
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(size.len());
+    let size = &size[..end];
+    let (digits, multiplier) = match size.chars().last()? {
+        'K' => (&size[..end - 1], 1024),
+        'M' => (&size[..end - 1], 1024 * 1024),
+        _ => (size, 1),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(value * multiplier)
 }
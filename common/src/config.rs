@@ -0,0 +1,12 @@
+//! Limits of the build-time configuration of the firmware.
+//!
+//! # Design
+//!  The firmware collects the configuration lists into fixed capacity at boot. The build
+//!  script rejects a list beyond these limits, so an oversized one fails the build
+//!  rather than the device.
+
+pub const NTP_SERVERS_MAX: usize = 4;
+
+/// The pins usable as digital outputs.
+pub const DIGITAL_OUTPUT_PINS: [&str; crate::settings::DIGITAL_OUTPUTS_MAX] =
+    ["PG0", "PG1", "PG2", "PG3"];
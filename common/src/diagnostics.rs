@@ -0,0 +1,27 @@
+//! Network diagnostics published on `<prefix>/diagnostics`.
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+/// IPv4 address, and two IPv6 addresses (link-local and SLAAC).
+pub const NUM_IP_ADDRESSES: usize = 3;
+
+/// The routing table also holds the default routes.
+pub const STATIC_ROUTES_MAX: usize = 7;
+
+/// The static routes and the IPv4 and IPv6 default routes.
+pub const NUM_ROUTING_TABLE_ENTRIES: usize = STATIC_ROUTES_MAX + 2;
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct RouteEntry {
+    pub destination: String<48>,
+    pub via: String<40>,
+}
+
+/// Interface addresses and routing table, published as a diagnostics message.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct NetworkDiagnostics {
+    pub addresses: Vec<String<48>, NUM_IP_ADDRESSES>,
+    pub routes: Vec<RouteEntry, NUM_ROUTING_TABLE_ENTRIES>,
+    /// Routes left out for the message to fit.
+    pub omitted_routes: u8,
+}
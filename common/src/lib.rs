@@ -0,0 +1,22 @@
+//! Telemetry types and hardware-independent logic of mqtt-rtic, shared by the firmware
+//! and the host-side decoder.
+//!
+//! # Design
+//!  Postcard isn't self-describing, so a decoder needs the exact types the firmware
+//!  encodes. Both use the types of this crate, which is `no_std` and free of hardware
+//!  dependencies, so they can't drift apart. The types serialize and deserialize, the
+//!  firmware only does the former. Logic without hardware dependencies, such as the
+//!  status indicator, lives here as well so it can be tested on the host.
+
+#![deny(warnings, clippy::all)]
+#![forbid(unsafe_code)]
+#![no_std]
+
+pub mod config;
+pub mod diagnostics;
+pub mod message;
+pub mod schema;
+pub mod settings;
+pub mod sparkplug;
+pub mod status;
+pub mod telemetry;
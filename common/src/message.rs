@@ -0,0 +1,60 @@
+//! Bounds of the MQTT messages carrying the shared types.
+//!
+//! # Design
+//!  The MQTT buffers of the firmware hold whole PUBLISH packets and are sized at build
+//!  time. The build script takes the smallest size from here, and the host tests check
+//!  the largest message of every kind against it, so a type that outgrows its messages
+//!  fails the tests rather than the device.
+
+/// Topics are built in `String<128>`.
+pub const TOPIC_SIZE_MAX: usize = 128;
+
+/// The longest suffix appended to the prefix, e.g. `/telemetry/schema`.
+pub const TOPIC_SUFFIX_MAX: usize = 32;
+
+pub const DEVICE_PREFIX_MAX: usize = TOPIC_SIZE_MAX - TOPIC_SUFFIX_MAX;
+
+/// The smallest MQTT message size, which holds the telemetry in every encoding and each
+/// document of its schema.
+pub const MQTT_MESSAGE_SIZE_MIN: usize = 1536;
+
+/// Get the size of a PUBLISH packet at QoS 0.
+///
+/// # Args
+/// * `topic` - The length of the topic.
+/// * `properties` - The size of the properties.
+/// * `payload` - The length of the payload.
+pub const fn publish_size(topic: usize, properties: usize, payload: usize) -> usize {
+    let remaining = 2 + topic + varint_size(properties) + properties + payload;
+    1 + varint_size(remaining) + remaining
+}
+
+/// Get the largest payload of a PUBLISH packet at QoS 0 in a message.
+///
+/// # Args
+/// * `message` - The message size.
+/// * `topic` - The length of the topic.
+/// * `properties` - The size of the properties.
+pub const fn payload_size_max(message: usize, topic: usize, properties: usize) -> usize {
+    // The remaining length is below the message size
+    message - 1 - varint_size(message) - 2 - topic - varint_size(properties) - properties
+}
+
+/// Get the size of a content type property.
+///
+/// # Args
+/// * `content_type` - The length of the content type.
+pub const fn content_type_size(content_type: usize) -> usize {
+    // The property identifier, the string length and the string
+    1 + 2 + content_type
+}
+
+/// The size of an MQTT variable byte integer.
+const fn varint_size(value: usize) -> usize {
+    match value {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    }
+}
@@ -0,0 +1,432 @@
+//! JSON Schema of the telemetry types.
+//!
+//! # Design
+//!  `telemetry_type!` defines a telemetry struct along with its `Schema` impl, so the
+//!  schema can't drift from the type. Field doc comments become descriptions, and an
+//!  optional `#[unit = "..."]` field attribute the `unit` annotation. Doc comments are
+//!  written into the schema verbatim, so they must not contain `"` or `\`. An optional
+//!  `#[class = "..."]` field attribute, after the unit, names the kind of quantity, e.g.
+//!  `temperature`. It isn't part of the schema, but reported with the scalar fields.
+//!
+//!  Every struct has a document of its own, with the struct name as `$id` and its doc
+//!  comment as description, so no document outgrows an MQTT message however the
+//!  telemetry is nested. Fields of a struct type refer to its document with `$ref`,
+//!  which describes them. The root document only declares the dialect and refers to the
+//!  document of the root type. The documents of a type are numbered depth first, a
+//!  struct used by several fields has a single document.
+//!
+//!  The scalar fields of a type can also be visited along with their annotations, e.g. to
+//!  describe them to consumers that don't read the schema. Their paths join field names
+//!  with `.` and array indices with `[n]`, e.g. `analog.values[0]`. The fields of an
+//!  `Option` are visited whether it is set or not, with the type they are serialized as.
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+/// The most documents in the schema of a type.
+pub const DOCUMENTS_MAX: usize = 32;
+
+/// The longest path of a scalar field, e.g. `reachability.gateway.rtt_min_ms`.
+pub const FIELD_PATH_MAX: usize = 64;
+
+pub type FieldPath = String<FIELD_PATH_MAX>;
+
+/// The value of a scalar field.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Kind {
+    Boolean,
+    Number,
+    /// A string, e.g. an enum variant.
+    Text,
+}
+
+/// The type a scalar field is serialized as.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scalar {
+    Boolean,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    /// A string, e.g. an enum variant.
+    Text,
+}
+
+impl Scalar {
+    /// The kind of the values of the type.
+    pub const fn kind(self) -> Kind {
+        match self {
+            Scalar::Boolean => Kind::Boolean,
+            Scalar::Text => Kind::Text,
+            _ => Kind::Number,
+        }
+    }
+}
+
+/// The annotations of a field, from its doc comment and attributes.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    pub description: &'static str,
+    pub unit: Option<&'static str>,
+    pub class: Option<&'static str>,
+}
+
+/// A scalar field, visited by a `FieldVisitor`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Field<'a> {
+    pub path: &'a str,
+    pub kind: Kind,
+    pub scalar: Scalar,
+    /// The annotations of the field, or of the struct field that holds it, e.g. for the
+    /// elements of an array.
+    pub annotations: Annotations,
+}
+
+/// Visits the scalar fields of a type.
+pub trait FieldVisitor {
+    fn visit(&mut self, field: &Field);
+}
+
+pub trait Schema {
+    /// Description of the type, from its doc comment.
+    const DESCRIPTION: &'static str = "";
+
+    /// `$id` of the document of the type, `None` if its schema is written inline.
+    const TITLE: Option<&'static str> = None;
+
+    /// The type the value of the type is serialized as, `None` if it isn't a scalar.
+    const SCALAR: Option<Scalar> = None;
+
+    /// Write the keywords of the JSON Schema of the type, without the enclosing braces.
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result;
+
+    /// Write the keywords of the schema of a field of the type: a `$ref` to the
+    /// document of the type if it has one, its keywords otherwise.
+    fn write_reference<W: Write>(w: &mut W) -> fmt::Result {
+        match Self::TITLE {
+            Some(title) => write!(w, "\"$ref\":\"{}\"", title),
+            None => Self::write_keywords(w),
+        }
+    }
+
+    /// Visit the type and the types of its fields that have a document, depth first.
+    fn visit_documents<V: DocumentVisitor>(_visitor: &mut V) {}
+
+    /// Visit the scalar fields of the type, depth first.
+    ///
+    /// # Args
+    /// * `path` - The path of the type, the paths of its fields are appended to it.
+    /// * `annotations` - The annotations of the field of the type.
+    /// * `visitor` - Visits every scalar field.
+    ///
+    /// # Returns
+    /// An error if a path exceeds `FIELD_PATH_MAX` bytes.
+    fn visit_fields<V: FieldVisitor>(
+        path: &mut FieldPath,
+        annotations: Annotations,
+        visitor: &mut V,
+    ) -> fmt::Result {
+        if let Some(scalar) = Self::SCALAR {
+            visitor.visit(&Field {
+                path,
+                kind: scalar.kind(),
+                scalar,
+                annotations,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Visits the types of a schema that have a document.
+pub trait DocumentVisitor {
+    /// Visit a type.
+    ///
+    /// # Returns
+    /// True if the types of the fields of the type should be visited.
+    fn visit<T: Schema>(&mut self) -> bool;
+}
+
+/// Write the schema of a field of a type, with the enclosing braces.
+pub fn write_schema<T: Schema, W: Write>(w: &mut W) -> fmt::Result {
+    w.write_char('{')?;
+    T::write_reference(w)?;
+    w.write_char('}')
+}
+
+/// Visit the scalar fields of a type.
+///
+/// # Returns
+/// An error if a path exceeds `FIELD_PATH_MAX` bytes.
+pub fn visit_fields<T: Schema, V: FieldVisitor>(visitor: &mut V) -> fmt::Result {
+    T::visit_fields(&mut FieldPath::new(), Annotations::default(), visitor)
+}
+
+/// Write the root JSON Schema document of a type.
+///
+/// # Args
+/// * `w` - The writer.
+/// * `comment` - Written as `$comment`, e.g. the version of the firmware.
+pub fn write_root<T: Schema, W: Write>(w: &mut W, comment: &str) -> fmt::Result {
+    write!(
+        w,
+        "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"$comment\":\"{}\",",
+        comment
+    )?;
+    T::write_reference(w)?;
+    w.write_char('}')
+}
+
+/// Write the document of a type, referred to by its `$id`.
+pub fn write_document<T: Schema, W: Write>(w: &mut W) -> fmt::Result {
+    if let Some(title) = T::TITLE {
+        write!(w, "{{\"$id\":\"{}\",", title)?;
+    } else {
+        w.write_char('{')?;
+    }
+    write!(w, "\"description\":\"{}\",", T::DESCRIPTION.trim())?;
+    T::write_keywords(w)?;
+    w.write_char('}')
+}
+
+/// Write one of the documents of the schema of a type, besides the root document.
+///
+/// # Args
+/// * `w` - The writer.
+/// * `index` - The number of the document, 0 is the document of `T` itself.
+///
+/// # Returns
+/// The `$id` of the written document, or `None` if the schema has fewer documents.
+pub fn write_nth_document<T: Schema, W: Write>(
+    w: &mut W,
+    index: usize,
+) -> Option<Result<&'static str, fmt::Error>> {
+    let mut visitor = NthDocument {
+        w,
+        index,
+        visited: Vec::new(),
+        result: None,
+    };
+    T::visit_documents(&mut visitor);
+    visitor.result
+}
+
+struct NthDocument<'a, W> {
+    w: &'a mut W,
+    index: usize,
+    visited: Vec<&'static str, DOCUMENTS_MAX>,
+    result: Option<Result<&'static str, fmt::Error>>,
+}
+
+impl<'a, W: Write> DocumentVisitor for NthDocument<'a, W> {
+    fn visit<T: Schema>(&mut self) -> bool {
+        let title = match T::TITLE {
+            Some(title) => title,
+            None => return true,
+        };
+        if self.result.is_some() || self.visited.contains(&title) {
+            return false;
+        }
+        if self.visited.len() == self.index {
+            self.result = Some(write_document::<T, W>(self.w).map(|_| title));
+        }
+        // The tests of this crate check that the telemetry has fewer documents
+        self.visited.push(title).ok();
+        true
+    }
+}
+
+macro_rules! integer_schema {
+    ($keywords:literal: $($ty:ty = $scalar:ident),*) => {
+        $(
+            impl Schema for $ty {
+                const SCALAR: Option<Scalar> = Some(Scalar::$scalar);
+
+                fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+                    w.write_str($keywords)
+                }
+            }
+        )*
+    };
+}
+
+integer_schema!("\"type\":\"integer\",\"minimum\":0": u8 = U8, u16 = U16, u32 = U32, u64 = U64);
+integer_schema!("\"type\":\"integer\"": i8 = I8, i16 = I16, i32 = I32, i64 = I64);
+
+impl Schema for bool {
+    const SCALAR: Option<Scalar> = Some(Scalar::Boolean);
+
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        w.write_str("\"type\":\"boolean\"")
+    }
+}
+
+impl Schema for f32 {
+    const SCALAR: Option<Scalar> = Some(Scalar::F32);
+
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        w.write_str("\"type\":\"number\"")
+    }
+}
+
+impl<T: Schema, const N: usize> Schema for [T; N] {
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        write!(
+            w,
+            "\"type\":\"array\",\"minItems\":{},\"maxItems\":{},\"items\":",
+            N, N
+        )?;
+        write_schema::<T, W>(w)
+    }
+
+    fn visit_documents<V: DocumentVisitor>(visitor: &mut V) {
+        T::visit_documents(visitor)
+    }
+
+    fn visit_fields<V: FieldVisitor>(
+        path: &mut FieldPath,
+        annotations: Annotations,
+        visitor: &mut V,
+    ) -> fmt::Result {
+        let len = path.len();
+        for i in 0..N {
+            write!(path, "[{}]", i)?;
+            T::visit_fields(path, annotations, visitor)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    const SCALAR: Option<Scalar> = T::SCALAR;
+
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        w.write_str("\"anyOf\":[{\"type\":\"null\"},")?;
+        write_schema::<T, W>(w)?;
+        w.write_char(']')
+    }
+
+    fn visit_documents<V: DocumentVisitor>(visitor: &mut V) {
+        T::visit_documents(visitor)
+    }
+    fn visit_fields<V: FieldVisitor>(
+        path: &mut FieldPath,
+        annotations: Annotations,
+        visitor: &mut V,
+    ) -> fmt::Result {
+        T::visit_fields(path, annotations, visitor)
+    }
+}
+
+/// Define a telemetry struct and its JSON Schema.
+#[macro_export]
+macro_rules! telemetry_type {
+    (
+        $(#[doc = $doc:literal])*
+        #[derive($($derive:path),* $(,)?)]
+        pub struct $name:ident {
+            $(
+                $(#[doc = $field_doc:literal])*
+                $(#[unit = $unit:literal])?
+                $(#[class = $class:literal])?
+                pub $field:ident: $ty:ty,
+            )*
+        }
+    ) => {
+        $(#[doc = $doc])*
+        #[derive($($derive),*)]
+        pub struct $name {
+            $(
+                $(#[doc = $field_doc])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl $crate::schema::Schema for $name {
+            const DESCRIPTION: &'static str = concat!($($doc),*);
+            const TITLE: Option<&'static str> = Some(stringify!($name));
+
+            fn write_keywords<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+                const REQUIRED: &[&str] = &[$(stringify!($field)),*];
+                let properties: &[fn(&mut W) -> core::fmt::Result] = &[$(
+                    |w: &mut W| {
+                        w.write_str(concat!("\"", stringify!($field), "\":{"))?;
+                        // A referenced document describes the field
+                        if <$ty as $crate::schema::Schema>::TITLE.is_none() {
+                            w.write_str("\"description\":\"")?;
+                            w.write_str(concat!($($field_doc),*).trim())?;
+                            w.write_str("\",")?;
+                        }
+                        w.write_str(concat!($("\"unit\":\"", $unit, "\",",)?))?;
+                        <$ty as $crate::schema::Schema>::write_reference(w)?;
+                        w.write_char('}')
+                    },
+                )*];
+
+                w.write_str("\"type\":\"object\",\"properties\":{")?;
+                for (i, property) in properties.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    property(w)?;
+                }
+                w.write_str("},\"required\":[")?;
+                for (i, field) in REQUIRED.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    write!(w, "\"{}\"", field)?;
+                }
+                w.write_str("],\"additionalProperties\":false")
+            }
+
+            fn visit_documents<V: $crate::schema::DocumentVisitor>(visitor: &mut V) {
+                if visitor.visit::<Self>() {
+                    $(<$ty as $crate::schema::Schema>::visit_documents(visitor);)*
+                }
+            }
+
+            fn visit_fields<V: $crate::schema::FieldVisitor>(
+                path: &mut $crate::schema::FieldPath,
+                _annotations: $crate::schema::Annotations,
+                visitor: &mut V,
+            ) -> core::fmt::Result {
+                let len = path.len();
+                $(
+                    if len != 0 {
+                        path.push('.').map_err(|_| core::fmt::Error)?;
+                    }
+                    path.push_str(stringify!($field)).map_err(|_| core::fmt::Error)?;
+                    <$ty as $crate::schema::Schema>::visit_fields(
+                        path,
+                        $crate::schema::Annotations {
+                            description: concat!($($field_doc),*).trim(),
+                            unit: $crate::optional_attribute!($($unit)?),
+                            class: $crate::optional_attribute!($($class)?),
+                        },
+                        visitor,
+                    )?;
+                    path.truncate(len);
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+
+/// The value of an optional attribute of `telemetry_type!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! optional_attribute {
+    () => {
+        None
+    };
+    ($value:literal) => {
+        Some($value)
+    };
+}
@@ -0,0 +1,19 @@
+//! Sizes of the settings tree of the firmware.
+//!
+//! # Design
+//!  The settings are defined by the firmware through miniconf, which doesn't build on
+//!  the host. Their sizes live here, so that the host tests can bound the Sparkplug
+//!  NBIRTH that declares every setting.
+
+/// Per-module log level overrides, `log/modules/<n>`.
+pub const MODULE_OVERRIDES_MAX: usize = 4;
+
+/// Longest module path of a log level override.
+pub const MODULE_NAME_MAX: usize = 32;
+
+pub const DIGITAL_OUTPUTS_MAX: usize = 4;
+
+/// Longest name of an output, the last level of its settings path.
+pub const OUTPUT_NAME_MAX: usize = 16;
+
+pub const PWM_CHANNELS: usize = 4;
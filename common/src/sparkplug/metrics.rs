@@ -0,0 +1,421 @@
+//! Flattening of serializable data into Sparkplug metrics.
+//!
+//! # Design
+//!  A serde serializer walks the data and reports every scalar leaf as a metric named
+//!  after its path, e.g. `time_sync/offset_ms`. Unit enum variants are reported as
+//!  strings. Elements of sequences are named after their index, e.g. `analog/values/0`.
+//!  Maps are not supported.
+//!
+//!  A `None` carries no type, so the scalar fields below it are taken from the `Schema`
+//!  of the data and reported as null metrics with the datatype of their values. Every
+//!  value of a type therefore has the same metrics, which NBIRTH declares once.
+use super::payload::Value;
+use crate::schema::{self, Field, FieldVisitor, Scalar, Schema};
+use core::fmt::{self, Write};
+use heapless::String;
+use serde::ser::{self, Impossible, Serialize};
+
+/// Metric names, including their path, must fit in this many bytes.
+pub const METRIC_NAME_SIZE_MAX: usize = 64;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The data contains a type that has no metric representation.
+    Unsupported,
+    NameTooLong,
+    Custom,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl ser::StdError for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error::Custom
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The names and datatypes of a sequence of metrics.
+///
+/// The sequence is hashed rather than stored, so that checking the metrics of every NDATA
+/// against NBIRTH needs no memory for the names.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MetricSet {
+    len: usize,
+    /// FNV-1a of the names and datatypes.
+    hash: u64,
+}
+
+impl Default for MetricSet {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl MetricSet {
+    /// Append a metric to the sequence.
+    pub fn add(&mut self, name: &str, value: &Value) {
+        self.len += 1;
+        let datatype = value.datatype().to_le_bytes();
+        // The separator keeps a name from running into the next one
+        for byte in name.bytes().chain([0]).chain(datatype) {
+            self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Report every scalar field of a value as a metric.
+///
+/// # Args
+/// * `value` - The data to flatten.
+/// * `f` - Called with the name and value of every metric, in field order.
+pub fn for_each_metric<T, F>(value: &T, mut f: F) -> Result<(), Error>
+where
+    T: Serialize + Schema,
+    F: FnMut(&str, Value),
+{
+    let mut path = String::new();
+    value.serialize(MetricSerializer {
+        path: &mut path,
+        f: &mut f,
+        declared: declare_nulls::<T>,
+    })
+}
+
+/// Visits the scalar fields of the flattened type.
+type Declared = fn(&mut Nulls) -> fmt::Result;
+
+fn declare_nulls<T: Schema>(nulls: &mut Nulls) -> fmt::Result {
+    schema::visit_fields::<T, _>(nulls)
+}
+
+/// Reports the scalar fields below a path as null metrics.
+struct Nulls<'a> {
+    path: &'a str,
+    f: &'a mut dyn FnMut(&str, Value),
+    result: Result<(), Error>,
+}
+
+impl<'a> FieldVisitor for Nulls<'a> {
+    fn visit(&mut self, field: &Field) {
+        // Field paths join names with `.` and array indices with `[n]`
+        let mut name: String<METRIC_NAME_SIZE_MAX> = String::new();
+        for c in field.path.chars().filter(|&c| c != ']') {
+            let c = if c == '.' || c == '[' { '/' } else { c };
+            if name.push(c).is_err() {
+                self.result = Err(Error::NameTooLong);
+                return;
+            }
+        }
+        let below = name
+            .strip_prefix(self.path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        if below {
+            (self.f)(&name, Value::Null(datatype(field.scalar)));
+        }
+    }
+}
+
+/// The Sparkplug datatype of the values of a scalar type.
+fn datatype(scalar: Scalar) -> u32 {
+    match scalar {
+        Scalar::Boolean => Value::Boolean(false),
+        Scalar::U8 => Value::UInt8(0),
+        Scalar::U16 => Value::UInt16(0),
+        Scalar::U32 => Value::UInt32(0),
+        Scalar::U64 => Value::UInt64(0),
+        Scalar::I8 => Value::Int8(0),
+        Scalar::I16 => Value::Int16(0),
+        Scalar::I32 => Value::Int32(0),
+        Scalar::I64 => Value::Int64(0),
+        Scalar::F32 => Value::Float(0.0),
+        // Enum variants are reported as strings
+        Scalar::Text => Value::String(""),
+    }
+    .datatype()
+}
+
+struct MetricSerializer<'a, F> {
+    path: &'a mut String<METRIC_NAME_SIZE_MAX>,
+    f: &'a mut F,
+    declared: Declared,
+}
+
+impl<'a, F: FnMut(&str, Value)> MetricSerializer<'a, F> {
+    fn emit(self, value: Value) -> Result<(), Error> {
+        (self.f)(self.path, value);
+        Ok(())
+    }
+
+    /// Serialize a nested value, with `key` appended to the path.
+    fn nested<T: ?Sized + Serialize>(
+        &mut self,
+        key: impl fmt::Display,
+        value: &T,
+    ) -> Result<(), Error> {
+        let len = self.path.len();
+        if len != 0 {
+            self.path.push('/').map_err(|_| Error::NameTooLong)?;
+        }
+        write!(self.path, "{}", key).map_err(|_| Error::NameTooLong)?;
+
+        let result = value.serialize(MetricSerializer {
+            path: &mut *self.path,
+            f: &mut *self.f,
+            declared: self.declared,
+        });
+        self.path.truncate(len);
+        result
+    }
+}
+
+/// Serializes the elements of a sequence.
+struct Elements<'a, F> {
+    serializer: MetricSerializer<'a, F>,
+    index: usize,
+}
+
+impl<'a, F: FnMut(&str, Value)> Elements<'a, F> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.serializer.nested(self.index, value)?;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl<'a, F: FnMut(&str, Value)> ser::SerializeSeq for Elements<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a, F: FnMut(&str, Value)> ser::SerializeTuple for Elements<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a, F: FnMut(&str, Value)> ser::Serializer for MetricSerializer<'a, F> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Elements<'a, F>;
+    type SerializeTuple = Elements<'a, F>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.emit(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.emit(Value::Int8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.emit(Value::Int16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.emit(Value::Int32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.emit(Value::Int64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.emit(Value::UInt8(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.emit(Value::UInt16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.emit(Value::UInt32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.emit(Value::UInt64(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.emit(Value::Float(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.emit(Value::Double(v))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.emit(Value::String(v))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        let mut nulls = Nulls {
+            path: self.path,
+            f: self.f,
+            result: Ok(()),
+        };
+        (self.declared)(&mut nulls).map_err(|_| Error::NameTooLong)?;
+        nulls.result
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.emit(Value::String(variant))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Ok(Elements {
+            serializer: self,
+            index: 0,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Ok(Elements {
+            serializer: self,
+            index: 0,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn collect_str<T: ?Sized + fmt::Display>(self, _value: &T) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+impl<'a, F: FnMut(&str, Value)> ser::SerializeStruct for MetricSerializer<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.nested(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
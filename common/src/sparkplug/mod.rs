@@ -0,0 +1,107 @@
+//! Sparkplug B payloads of the edge node.
+//!
+//! # Design
+//!  The payloads are encoded here, without the MQTT session of the firmware, so that the
+//!  host tests can check the largest one, NBIRTH, against `SPARKPLUG_MESSAGE_SIZE_MIN`.
+//!  NBIRTH declares `bdSeq`, `Node Control/Rebirth`, every setting as `settings/<path>`
+//!  and every telemetry field, named after its path.
+use crate::schema::Schema;
+use heapless::String;
+use metrics::{for_each_metric, MetricSet, METRIC_NAME_SIZE_MAX};
+use payload::{BufferFull, Metric, PayloadEncoder, Value};
+use serde::Serialize;
+
+pub mod metrics;
+pub mod payload;
+
+pub const NAMESPACE: &str = "spBv1.0";
+
+/// The longest message type, `NBIRTH` and `NDEATH`.
+pub const MESSAGE_TYPE_MAX: usize = 6;
+
+pub const BD_SEQ: &str = "bdSeq";
+pub const REBIRTH: &str = "Node Control/Rebirth";
+pub const SETTINGS_PREFIX: &str = "settings/";
+
+/// The smallest MQTT message size in Sparkplug mode, which holds NBIRTH.
+pub const SPARKPLUG_MESSAGE_SIZE_MIN: usize = 4096;
+
+/// Get the length of the longest topic of an edge node, `spBv1.0/<group>/NBIRTH/<node>`.
+///
+/// # Args
+/// * `group_id` - The length of the group ID.
+/// * `edge_node_id` - The length of the edge node ID.
+pub const fn topic_size(group_id: usize, edge_node_id: usize) -> usize {
+    NAMESPACE.len() + group_id + MESSAGE_TYPE_MAX + edge_node_id + 3
+}
+
+/// Start an NBIRTH with its sequence number and the metrics of the node.
+///
+/// # Args
+/// * `timestamp` - UTC time of the payload in milliseconds, if known.
+/// * `bd_seq` - The birth/death sequence number of the session.
+pub fn start_birth<const N: usize>(
+    payload: &mut PayloadEncoder<N>,
+    timestamp: Option<u64>,
+    bd_seq: u8,
+) -> Result<(), BufferFull> {
+    payload.start(timestamp, Some(0))?;
+    payload.metric(&Metric {
+        name: Some(BD_SEQ),
+        alias: None,
+        value: Value::UInt64(u64::from(bd_seq)),
+    })?;
+    payload.metric(&Metric {
+        name: Some(REBIRTH),
+        alias: None,
+        value: Value::Boolean(false),
+    })
+}
+
+/// Encode every telemetry field as a metric, null if it isn't set.
+///
+/// # Returns
+/// The encoded metrics, or `None` if the telemetry doesn't fit in the payload.
+pub fn encode_telemetry<T: Serialize + Schema, const N: usize>(
+    payload: &mut PayloadEncoder<N>,
+    telemetry: &T,
+) -> Option<MetricSet> {
+    let mut result = Ok(());
+    let mut set = MetricSet::default();
+    let flattened = for_each_metric(telemetry, |name, value| {
+        if result.is_ok() {
+            set.add(name, &value);
+            result = payload.metric(&Metric {
+                name: Some(name),
+                alias: None,
+                value,
+            });
+        }
+    });
+    (flattened.is_ok() && result.is_ok()).then_some(set)
+}
+
+/// Encode a setting as a metric, a boolean for `true` and `false` and JSON text otherwise.
+///
+/// # Args
+/// * `path` - The path of the setting.
+/// * `value` - The setting, serialized as UTF-8 JSON.
+pub fn encode_setting<const N: usize>(
+    payload: &mut PayloadEncoder<N>,
+    path: &str,
+    value: &[u8],
+) -> Result<(), BufferFull> {
+    let mut name: String<METRIC_NAME_SIZE_MAX> = String::from(SETTINGS_PREFIX);
+    name.push_str(path).map_err(|_| BufferFull)?;
+    let value = match value {
+        b"true" => Value::Boolean(true),
+        b"false" => Value::Boolean(false),
+        // Note(unwrap): miniconf serializes settings as UTF-8 JSON
+        value => Value::Text(core::str::from_utf8(value).unwrap()),
+    };
+    payload.metric(&Metric {
+        name: Some(&name),
+        alias: None,
+        value,
+    })
+}
@@ -0,0 +1,322 @@
+//! Sparkplug B payload encoding.
+//!
+//! # Design
+//!  Only the subset of the Sparkplug B protobuf schema used by an edge node without
+//!  devices is supported: the payload timestamp and sequence number, and scalar
+//!  metrics with a name and/or alias, or null. Unknown fields are skipped when decoding.
+use heapless::Vec;
+
+const PAYLOAD_TIMESTAMP: u32 = 1;
+const PAYLOAD_METRICS: u32 = 2;
+const PAYLOAD_SEQ: u32 = 3;
+
+const METRIC_NAME: u32 = 1;
+const METRIC_ALIAS: u32 = 2;
+const METRIC_DATATYPE: u32 = 4;
+const METRIC_IS_NULL: u32 = 7;
+const METRIC_INT_VALUE: u32 = 10;
+const METRIC_LONG_VALUE: u32 = 11;
+const METRIC_FLOAT_VALUE: u32 = 12;
+const METRIC_DOUBLE_VALUE: u32 = 13;
+const METRIC_BOOLEAN_VALUE: u32 = 14;
+const METRIC_STRING_VALUE: u32 = 15;
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_BYTES: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+/// A single encoded metric must fit in this many bytes.
+const METRIC_SIZE_MAX: usize = 192;
+
+/// The encoded payload exceeds its buffer.
+#[derive(Copy, Clone, Debug)]
+pub struct BufferFull;
+
+/// A scalar metric value, tagged with its Sparkplug data type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    String(&'a str),
+    /// Settings values are carried as JSON text.
+    Text(&'a str),
+    /// No value, with the datatype of the values of the metric.
+    Null(u32),
+}
+
+impl<'a> Value<'a> {
+    /// The Sparkplug datatype of the value.
+    pub fn datatype(&self) -> u32 {
+        match self {
+            Value::Int8(_) => 1,
+            Value::Int16(_) => 2,
+            Value::Int32(_) => 3,
+            Value::Int64(_) => 4,
+            Value::UInt8(_) => 5,
+            Value::UInt16(_) => 6,
+            Value::UInt32(_) => 7,
+            Value::UInt64(_) => 8,
+            Value::Float(_) => 9,
+            Value::Double(_) => 10,
+            Value::Boolean(_) => 11,
+            Value::String(_) => 12,
+            Value::Text(_) => 14,
+            Value::Null(datatype) => *datatype,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Metric<'a> {
+    pub name: Option<&'a str>,
+    pub alias: Option<u64>,
+    pub value: Value<'a>,
+}
+
+#[derive(Default)]
+struct Writer<const N: usize> {
+    buf: Vec<u8, N>,
+}
+
+impl<const N: usize> Writer<N> {
+    fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    fn varint(&mut self, mut value: u64) -> Result<(), BufferFull> {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                return self.buf.push(byte).map_err(|_| BufferFull);
+            }
+            self.buf.push(byte | 0x80).map_err(|_| BufferFull)?;
+        }
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) -> Result<(), BufferFull> {
+        self.varint(u64::from((field << 3) | u32::from(wire_type)))
+    }
+
+    fn uint(&mut self, field: u32, value: u64) -> Result<(), BufferFull> {
+        self.key(field, WIRE_VARINT)?;
+        self.varint(value)
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) -> Result<(), BufferFull> {
+        self.key(field, WIRE_BYTES)?;
+        self.varint(value.len() as u64)?;
+        self.buf.extend_from_slice(value).map_err(|_| BufferFull)
+    }
+
+    fn fixed32(&mut self, field: u32, value: u32) -> Result<(), BufferFull> {
+        self.key(field, WIRE_FIXED32)?;
+        self.buf
+            .extend_from_slice(&value.to_le_bytes())
+            .map_err(|_| BufferFull)
+    }
+
+    fn fixed64(&mut self, field: u32, value: u64) -> Result<(), BufferFull> {
+        self.key(field, WIRE_FIXED64)?;
+        self.buf
+            .extend_from_slice(&value.to_le_bytes())
+            .map_err(|_| BufferFull)
+    }
+}
+
+/// Encodes a Sparkplug B payload.
+#[derive(Default)]
+pub struct PayloadEncoder<const N: usize> {
+    writer: Writer<N>,
+}
+
+impl<const N: usize> PayloadEncoder<N> {
+    /// Start a payload, discarding the previous one.
+    ///
+    /// # Args
+    /// * `timestamp` - UTC time of the payload in milliseconds, if known.
+    /// * `seq` - The sequence number, `None` for NDEATH.
+    pub fn start(&mut self, timestamp: Option<u64>, seq: Option<u8>) -> Result<(), BufferFull> {
+        self.writer.buf.clear();
+        if let Some(timestamp) = timestamp {
+            self.writer.uint(PAYLOAD_TIMESTAMP, timestamp)?;
+        }
+        if let Some(seq) = seq {
+            self.writer.uint(PAYLOAD_SEQ, u64::from(seq))?;
+        }
+        Ok(())
+    }
+
+    pub fn metric(&mut self, metric: &Metric) -> Result<(), BufferFull> {
+        let mut m: Writer<METRIC_SIZE_MAX> = Writer::new();
+        if let Some(name) = metric.name {
+            m.bytes(METRIC_NAME, name.as_bytes())?;
+        }
+        if let Some(alias) = metric.alias {
+            m.uint(METRIC_ALIAS, alias)?;
+        }
+        m.uint(METRIC_DATATYPE, u64::from(metric.value.datatype()))?;
+        // Signed integers are stored as their two's complement in the unsigned fields
+        match metric.value {
+            Value::Int8(v) => m.uint(METRIC_INT_VALUE, u64::from(v as i32 as u32))?,
+            Value::Int16(v) => m.uint(METRIC_INT_VALUE, u64::from(v as i32 as u32))?,
+            Value::Int32(v) => m.uint(METRIC_INT_VALUE, u64::from(v as u32))?,
+            Value::Int64(v) => m.uint(METRIC_LONG_VALUE, v as u64)?,
+            Value::UInt8(v) => m.uint(METRIC_INT_VALUE, u64::from(v))?,
+            Value::UInt16(v) => m.uint(METRIC_INT_VALUE, u64::from(v))?,
+            Value::UInt32(v) => m.uint(METRIC_INT_VALUE, u64::from(v))?,
+            Value::UInt64(v) => m.uint(METRIC_LONG_VALUE, v)?,
+            Value::Float(v) => m.fixed32(METRIC_FLOAT_VALUE, v.to_bits())?,
+            Value::Double(v) => m.fixed64(METRIC_DOUBLE_VALUE, v.to_bits())?,
+            Value::Boolean(v) => m.uint(METRIC_BOOLEAN_VALUE, u64::from(v))?,
+            Value::String(v) | Value::Text(v) => m.bytes(METRIC_STRING_VALUE, v.as_bytes())?,
+            Value::Null(_) => m.uint(METRIC_IS_NULL, 1)?,
+        }
+        self.writer.bytes(PAYLOAD_METRICS, &m.buf)
+    }
+
+    /// The encoded payload.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.writer.buf
+    }
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.data.split_first()?;
+            self.data = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.data.len() {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn field(&mut self) -> Option<(u32, Field<'a>)> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let key = self.varint()?;
+        let field = match (key & 0x7) as u8 {
+            WIRE_VARINT => Field::Varint(self.varint()?),
+            WIRE_FIXED64 => {
+                let mut value = [0; 8];
+                value.copy_from_slice(self.take(8)?);
+                Field::Fixed64(u64::from_le_bytes(value))
+            }
+            WIRE_BYTES => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            WIRE_FIXED32 => {
+                let mut value = [0; 4];
+                value.copy_from_slice(self.take(4)?);
+                Field::Fixed32(u32::from_le_bytes(value))
+            }
+            // Groups are deprecated and not used by Sparkplug
+            _ => return None,
+        };
+        Some(((key >> 3) as u32, field))
+    }
+}
+
+/// Iterate over the metrics of a payload.
+///
+/// # Args
+/// * `payload` - The encoded payload.
+///
+/// # Returns
+/// The metrics that could be decoded. Decoding stops at the first malformed field.
+pub fn metrics(payload: &[u8]) -> impl Iterator<Item = Metric<'_>> {
+    let mut reader = Reader { data: payload };
+    core::iter::from_fn(move || loop {
+        match reader.field()? {
+            (PAYLOAD_METRICS, Field::Bytes(metric)) => {
+                if let Some(metric) = decode_metric(metric) {
+                    return Some(metric);
+                }
+            }
+            _ => continue,
+        }
+    })
+}
+
+fn decode_metric(data: &[u8]) -> Option<Metric<'_>> {
+    let mut reader = Reader { data };
+    let mut name = None;
+    let mut alias = None;
+    let mut datatype = None;
+    let mut is_null = false;
+    let mut value = None;
+
+    while let Some((number, field)) = reader.field() {
+        match (number, field) {
+            (METRIC_NAME, Field::Bytes(v)) => name = core::str::from_utf8(v).ok(),
+            (METRIC_ALIAS, Field::Varint(v)) => alias = Some(v),
+            (METRIC_DATATYPE, Field::Varint(v)) => datatype = Some(v as u32),
+            (METRIC_IS_NULL, Field::Varint(v)) => is_null = v != 0,
+            (METRIC_INT_VALUE..=METRIC_STRING_VALUE, field) => value = Some((number, field)),
+            _ => {}
+        }
+    }
+
+    if is_null {
+        let value = Value::Null(datatype?);
+        return Some(Metric { name, alias, value });
+    }
+
+    let value = match (datatype, value?) {
+        (Some(1), (_, Field::Varint(v))) => Value::Int8(v as u32 as i32 as i8),
+        (Some(2), (_, Field::Varint(v))) => Value::Int16(v as u32 as i32 as i16),
+        (Some(3), (_, Field::Varint(v))) => Value::Int32(v as u32 as i32),
+        (Some(4), (_, Field::Varint(v))) => Value::Int64(v as i64),
+        (Some(5), (_, Field::Varint(v))) => Value::UInt8(v as u8),
+        (Some(6), (_, Field::Varint(v))) => Value::UInt16(v as u16),
+        (Some(7), (_, Field::Varint(v))) => Value::UInt32(v as u32),
+        (Some(8) | Some(13), (_, Field::Varint(v))) => Value::UInt64(v),
+        (Some(14), (_, Field::Bytes(v))) => Value::Text(core::str::from_utf8(v).ok()?),
+        // Without a known data type, fall back to the value field
+        (_, (METRIC_INT_VALUE, Field::Varint(v))) => Value::UInt32(v as u32),
+        (_, (METRIC_LONG_VALUE, Field::Varint(v))) => Value::UInt64(v),
+        (_, (METRIC_FLOAT_VALUE, Field::Fixed32(v))) => Value::Float(f32::from_bits(v)),
+        (_, (METRIC_DOUBLE_VALUE, Field::Fixed64(v))) => Value::Double(f64::from_bits(v)),
+        (_, (METRIC_BOOLEAN_VALUE, Field::Varint(v))) => Value::Boolean(v != 0),
+        (_, (METRIC_STRING_VALUE, Field::Bytes(v))) => Value::String(core::str::from_utf8(v).ok()?),
+        _ => return None,
+    };
+
+    Some(Metric { name, alias, value })
+}
@@ -0,0 +1,190 @@
+//! Status indicator on the board LEDs.
+//!
+//! # Design
+//!  The tasks report what they observe into a single `SystemStatus`, from which the
+//!  system state is derived by priority: a fault hides everything else, then a firmware
+//!  update, booting, a missing link and a missing broker connection. Every state has a
+//!  blink pattern for each LED, stepped every `TICK_MS` and restarted when the state
+//!  changes.
+//!
+//!  Once connected, the blue LED flashes on network activity and the red LED shows the
+//!  `led` setting. This module has no hardware dependencies, the caller drives the pins
+//!  and logs the state changes.
+
+/// Time between two steps of the blink patterns.
+pub const TICK_MS: u32 = 100;
+
+/// What the tasks report about the system.
+#[derive(Copy, Clone, Debug)]
+pub struct SystemStatus {
+    /// Until the first link check after boot.
+    pub booting: bool,
+    /// The Ethernet link is up.
+    pub link: bool,
+    /// The MQTT session with the broker is established.
+    pub mqtt: bool,
+    /// A firmware update is in progress. Reserved, nothing sets it until the firmware
+    /// can be updated over the network.
+    pub updating: bool,
+    /// A subsystem is failing, e.g. a sensor.
+    pub fault: bool,
+    /// Network traffic since the last tick.
+    pub activity: bool,
+    /// The `led` setting.
+    pub led: bool,
+}
+
+impl Default for SystemStatus {
+    fn default() -> Self {
+        Self {
+            booting: true,
+            link: false,
+            mqtt: false,
+            updating: false,
+            fault: false,
+            activity: false,
+            led: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SystemState {
+    Booting,
+    NoLink,
+    /// The link is up, but not the MQTT session.
+    NoBroker,
+    Connected,
+    Updating,
+    Fault,
+}
+
+impl SystemStatus {
+    pub fn state(&self) -> SystemState {
+        if self.fault {
+            SystemState::Fault
+        } else if self.updating {
+            SystemState::Updating
+        } else if self.booting {
+            SystemState::Booting
+        } else if !self.link {
+            SystemState::NoLink
+        } else if !self.mqtt {
+            SystemState::NoBroker
+        } else {
+            SystemState::Connected
+        }
+    }
+}
+
+/// A periodic blink, on for the first `on` ticks of every `period` ticks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Blink {
+    period: u8,
+    on: u8,
+}
+
+impl Blink {
+    pub const OFF: Blink = Blink { period: 1, on: 0 };
+    pub const ON: Blink = Blink { period: 1, on: 1 };
+    /// 1 Hz.
+    pub const SLOW: Blink = Blink { period: 10, on: 5 };
+    /// 5 Hz.
+    pub const FAST: Blink = Blink { period: 2, on: 1 };
+
+    pub fn is_on(&self, tick: u32) -> bool {
+        tick % u32::from(self.period) < u32::from(self.on)
+    }
+}
+
+/// The blinks of the LEDs in a state.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pattern {
+    pub green: Blink,
+    pub blue: Blink,
+    pub red: Blink,
+}
+
+impl Pattern {
+    /// The LEDs at a tick of the pattern.
+    pub fn at(&self, tick: u32) -> LedStates {
+        LedStates {
+            green: self.green.is_on(tick),
+            blue: self.blue.is_on(tick),
+            red: self.red.is_on(tick),
+        }
+    }
+}
+
+impl SystemState {
+    pub fn pattern(self) -> Pattern {
+        let (green, blue, red) = match self {
+            SystemState::Booting => (Blink::ON, Blink::ON, Blink::ON),
+            SystemState::NoLink => (Blink::OFF, Blink::OFF, Blink::SLOW),
+            SystemState::NoBroker => (Blink::ON, Blink::SLOW, Blink::OFF),
+            SystemState::Connected => (Blink::ON, Blink::OFF, Blink::OFF),
+            SystemState::Updating => (Blink::ON, Blink::FAST, Blink::OFF),
+            SystemState::Fault => (Blink::OFF, Blink::OFF, Blink::FAST),
+        };
+        Pattern { green, blue, red }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LedStates {
+    pub green: bool,
+    pub blue: bool,
+    pub red: bool,
+}
+
+pub struct StatusIndicator {
+    state: SystemState,
+    tick: u32,
+}
+
+impl StatusIndicator {
+    pub const fn new() -> Self {
+        Self {
+            state: SystemState::Booting,
+            tick: 0,
+        }
+    }
+
+    /// The state shown since the last update.
+    pub fn state(&self) -> SystemState {
+        self.state
+    }
+
+    /// Step the pattern of the current state.
+    ///
+    /// # Args
+    /// * `status` - The reported status, its network activity is consumed.
+    ///
+    /// # Returns
+    /// The LEDs for the next tick.
+    pub fn update(&mut self, status: &mut SystemStatus) -> LedStates {
+        let state = status.state();
+        if state != self.state {
+            self.state = state;
+            self.tick = 0;
+        }
+
+        let pattern = state.pattern();
+        let mut leds = pattern.at(self.tick);
+        if state == SystemState::Connected {
+            leds.blue = status.activity;
+        }
+        if pattern.red == Blink::OFF {
+            leds.red = status.led;
+        }
+        status.activity = false;
+        self.tick = self.tick.wrapping_add(1);
+        leds
+    }
+}
+
+impl Default for StatusIndicator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,335 @@
+//! Telemetry published on `<prefix>/telemetry` and the input events.
+//!
+//! # Design
+//!  Every field is always serialized, postcard has no notion of skipped fields, so an
+//!  unavailable value is `null` rather than missing. The field order is the postcard
+//!  wire format: fields must only be appended.
+use crate::schema::{DocumentVisitor, Scalar, Schema};
+use crate::telemetry_type;
+use core::fmt::{self, Write};
+use serde::{Deserialize, Serialize};
+
+pub const ANALOG_INPUTS_MAX: usize = 4;
+
+pub const DIGITAL_INPUTS_MAX: usize = 4;
+
+telemetry_type! {
+    /// Telemetry published every second.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct Telemetry {
+        /// Counter incremented with every sample.
+        pub dummy: u32,
+
+        /// UTC time of the sample since the Unix epoch, once synchronized.
+        #[unit = "ms"]
+        #[class = "timestamp"]
+        pub timestamp: Option<u64>,
+
+        /// Time synchronisation status.
+        pub time_sync: TimeSyncStatus,
+
+        /// Reachability of the gateway and the broker.
+        pub reachability: Reachability,
+
+        /// State of the connection to the broker.
+        pub connection: ConnectionStatus,
+
+        /// IP stack polling statistics.
+        pub polling: PollStats,
+
+        /// Readings of the environmental sensor.
+        pub environment: Environment,
+
+        /// Averages of the analog inputs.
+        pub analog: AnalogTelemetry,
+
+        /// Debounced states of the digital inputs.
+        pub inputs: InputTelemetry,
+
+        /// Escalations of the connectivity watchdog.
+        pub watchdog: WatchdogStatus,
+    }
+}
+
+telemetry_type! {
+    /// Time synchronisation status reported in telemetry.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct TimeSyncStatus {
+        /// Whether the wall clock has been synchronized with SNTP.
+        pub synchronized: bool,
+        /// UTC minus monotonic time.
+        #[unit = "ms"]
+        pub offset_ms: i64,
+        /// Rate of the monotonic relative to UTC.
+        #[unit = "ppm"]
+        pub drift_ppm: i32,
+        /// Time since the last synchronization.
+        #[unit = "s"]
+        pub since_sync_s: u32,
+    }
+}
+
+telemetry_type! {
+    /// Round-trip statistics of a target over the most recent probes.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct PingStats {
+        /// Number of probes in the window.
+        pub samples: u8,
+        /// Share of the probes in the window without a reply.
+        #[unit = "%"]
+        pub loss_percent: u8,
+        /// Minimum round-trip time of the replies in the window.
+        #[unit = "ms"]
+        pub rtt_min_ms: u32,
+        /// Average round-trip time of the replies in the window.
+        #[unit = "ms"]
+        pub rtt_avg_ms: u32,
+        /// Maximum round-trip time of the replies in the window.
+        #[unit = "ms"]
+        pub rtt_max_ms: u32,
+    }
+}
+
+telemetry_type! {
+    /// ICMP echo reachability of the default gateway and the broker, without gateway
+    /// samples while no gateway is configured.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct Reachability {
+        /// The default gateway, no samples while none is configured.
+        pub gateway: PingStats,
+        /// The MQTT broker.
+        pub broker: PingStats,
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// No Ethernet link.
+    #[default]
+    Down,
+    /// The link is up, without an address to reach the broker.
+    Link,
+    /// An address to reach the broker is configured.
+    IpConfigured,
+    /// The TCP connection to the broker is established.
+    TcpConnected,
+    /// The MQTT session is established.
+    MqttConnected,
+    /// The settings subscription was sent.
+    Subscribed,
+}
+
+impl Schema for ConnectionState {
+    const DESCRIPTION: &'static str =
+        "States of the connection to the broker, in the order they are reached.";
+    const TITLE: Option<&'static str> = Some("ConnectionState");
+    const SCALAR: Option<Scalar> = Some(Scalar::Text);
+
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        w.write_str(concat!(
+            "\"type\":\"string\",\"enum\":[\"down\",\"link\",\"ip_configured\",",
+            "\"tcp_connected\",\"mqtt_connected\",\"subscribed\"]"
+        ))
+    }
+
+    fn visit_documents<V: DocumentVisitor>(visitor: &mut V) {
+        visitor.visit::<Self>();
+    }
+}
+
+telemetry_type! {
+    /// Time spent in every connection state since boot.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct StateTimes {
+        /// Without link.
+        #[unit = "s"]
+        pub down_s: u32,
+        /// Link up, no address.
+        #[unit = "s"]
+        pub link_s: u32,
+        /// Address configured, no TCP connection.
+        #[unit = "s"]
+        pub ip_configured_s: u32,
+        /// TCP connection, no MQTT session.
+        #[unit = "s"]
+        pub tcp_connected_s: u32,
+        /// MQTT session, no subscription.
+        #[unit = "s"]
+        pub mqtt_connected_s: u32,
+        /// Subscribed to the settings.
+        #[unit = "s"]
+        pub subscribed_s: u32,
+    }
+}
+
+telemetry_type! {
+    /// Reconnection attempts to the broker.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct ReconnectStats {
+        /// Connection attempts since boot.
+        pub attempts: u32,
+        /// Failed attempts since the session was last established.
+        pub failures: u32,
+        /// Time until the next attempt, 0 while connected or connecting.
+        #[unit = "ms"]
+        pub retry_in_ms: u32,
+    }
+}
+
+telemetry_type! {
+    /// State of the connection to the broker.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct ConnectionStatus {
+        /// The current state.
+        pub state: ConnectionState,
+        /// Time in the current state.
+        #[unit = "s"]
+        pub since_s: u32,
+        /// Time spent in every state since boot.
+        pub totals: StateTimes,
+        /// Number of MQTT sessions established since boot.
+        pub sessions: u32,
+        /// Reconnection attempts to the broker.
+        pub reconnect: ReconnectStats,
+    }
+}
+
+telemetry_type! {
+    /// IP stack polling statistics since the previous sample.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct PollStats {
+        /// Number of polls of the IP stack.
+        pub polls: u32,
+        /// Share of the CPU cycles spent polling the IP stack.
+        #[unit = "permille"]
+        pub cpu_load_permille: u32,
+        /// Average time from the ETH interrupt to the start of the next poll.
+        #[unit = "us"]
+        pub latency_avg_us: u32,
+        /// Maximum time from the ETH interrupt to the start of the next poll.
+        #[unit = "us"]
+        pub latency_max_us: u32,
+    }
+}
+
+telemetry_type! {
+    /// Readings of the environmental sensor.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct Environment {
+        /// Air temperature, null while the sensor fails.
+        #[unit = "°C"]
+        #[class = "temperature"]
+        pub temperature_c: Option<f32>,
+        /// Relative humidity, null while the sensor fails.
+        #[unit = "%"]
+        #[class = "humidity"]
+        pub humidity_percent: Option<f32>,
+        /// Number of failed measurements since boot.
+        pub errors: u32,
+    }
+}
+
+telemetry_type! {
+    /// Averages of the analog inputs since the previous sample.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct AnalogTelemetry {
+        /// Engineering values in the order of the configured inputs, null when unused.
+        pub values: [Option<f32>; ANALOG_INPUTS_MAX],
+        /// Number of ADC buffers lost because they weren't processed in time.
+        pub overruns: u32,
+    }
+}
+
+telemetry_type! {
+    /// Debounced states of the digital inputs.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct InputTelemetry {
+        /// Levels in the order of the configured inputs, null when unused.
+        pub states: [Option<bool>; DIGITAL_INPUTS_MAX],
+        /// Number of debounced changes since boot.
+        pub changes: u32,
+    }
+}
+
+/// Published on `<prefix>/event/input/<name>` when an input changes.
+#[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+pub struct InputEvent {
+    /// The debounced level of the input.
+    pub state: bool,
+    /// UTC time of the first edge in milliseconds since the Unix epoch, once
+    /// synchronized.
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Escalation {
+    StackReset,
+    PhyReset,
+    SystemReset,
+}
+
+impl Schema for Escalation {
+    const DESCRIPTION: &'static str = "Recovery action of the connectivity watchdog.";
+    const TITLE: Option<&'static str> = Some("Escalation");
+    const SCALAR: Option<Scalar> = Some(Scalar::Text);
+
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        w.write_str("\"type\":\"string\",\"enum\":[\"stack_reset\",\"phy_reset\",\"system_reset\"]")
+    }
+
+    fn visit_documents<V: DocumentVisitor>(visitor: &mut V) {
+        visitor.visit::<Self>();
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetCause {
+    #[default]
+    PowerOn,
+    BrownOut,
+    Pin,
+    Software,
+    Watchdog,
+    WindowWatchdog,
+    LowPower,
+    /// No reset flag, or a software reset without escalation record.
+    Unknown,
+}
+
+impl Schema for ResetCause {
+    const DESCRIPTION: &'static str = "Cause of a system reset.";
+    const TITLE: Option<&'static str> = Some("ResetCause");
+    const SCALAR: Option<Scalar> = Some(Scalar::Text);
+
+    fn write_keywords<W: Write>(w: &mut W) -> fmt::Result {
+        w.write_str(concat!(
+            "\"type\":\"string\",\"enum\":[\"power_on\",\"brown_out\",\"pin\",\"software\",",
+            "\"watchdog\",\"window_watchdog\",\"low_power\",\"unknown\"]"
+        ))
+    }
+
+    fn visit_documents<V: DocumentVisitor>(visitor: &mut V) {
+        visitor.visit::<Self>();
+    }
+}
+
+telemetry_type! {
+    /// Escalations of the connectivity watchdog.
+    #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, PartialEq)]
+    pub struct WatchdogStatus {
+        /// Cause of the last system reset.
+        pub reset_cause: ResetCause,
+        /// IP stack resets since boot.
+        pub stack_resets: u32,
+        /// PHY resets since boot.
+        pub phy_resets: u32,
+        /// The last escalation, a system reset before boot included.
+        pub last_escalation: Option<Escalation>,
+        /// Duration of the current outage.
+        #[unit = "s"]
+        pub outage_s: u32,
+    }
+}
@@ -0,0 +1,115 @@
+use heapless::String;
+use mqtt_rtic_common::diagnostics::{
+    NetworkDiagnostics, RouteEntry, NUM_IP_ADDRESSES, NUM_ROUTING_TABLE_ENTRIES,
+};
+use mqtt_rtic_common::message::{
+    content_type_size, payload_size_max, publish_size, DEVICE_PREFIX_MAX, MQTT_MESSAGE_SIZE_MIN,
+};
+use mqtt_rtic_common::telemetry::Telemetry;
+use serde::Serialize;
+use worst_case::{worst_case, WIDEST_F32};
+
+mod worst_case;
+
+/// A value in every encoding, with its content type.
+fn encodings<T: Serialize>(value: &T) -> [(&'static str, Vec<u8>); 3] {
+    // The firmware encodes CBOR structs as maps keyed by field name
+    let mut cbor = Vec::new();
+    value
+        .serialize(&mut serde_cbor::Serializer::new(&mut cbor))
+        .unwrap();
+    [
+        ("application/json", serde_json::to_vec(value).unwrap()),
+        ("application/cbor", cbor),
+        (
+            "application/x-postcard",
+            postcard::to_stdvec(value).unwrap(),
+        ),
+    ]
+}
+
+/// Diagnostics with every address and route at its longest.
+fn widest_diagnostics() -> NetworkDiagnostics {
+    let mut diagnostics = NetworkDiagnostics::default();
+    for _ in 0..NUM_IP_ADDRESSES {
+        diagnostics
+            .addresses
+            .push(String::from("f".repeat(48).as_str()))
+            .unwrap();
+    }
+    for _ in 0..NUM_ROUTING_TABLE_ENTRIES {
+        let route = RouteEntry {
+            destination: String::from("f".repeat(48).as_str()),
+            via: String::from("f".repeat(40).as_str()),
+        };
+        diagnostics.routes.push(route).unwrap();
+    }
+    diagnostics
+}
+
+#[test]
+fn widest_numbers_are_wider_than_defaults() {
+    assert_eq!(serde_json::to_string(&WIDEST_F32).unwrap().len(), 16);
+    for ((_, worst), (_, default)) in encodings(&worst_case())
+        .iter()
+        .zip(encodings(&Telemetry::default()).iter())
+    {
+        assert!(worst.len() > default.len());
+    }
+}
+
+#[test]
+fn telemetry_fits_in_a_message() {
+    let topic = DEVICE_PREFIX_MAX + "/telemetry".len();
+    for (content_type, payload) in encodings(&worst_case()) {
+        let size = publish_size(topic, content_type_size(content_type.len()), payload.len());
+        assert!(
+            size <= MQTT_MESSAGE_SIZE_MIN,
+            "{} telemetry is {} bytes, {} with the headers",
+            content_type,
+            payload.len(),
+            size
+        );
+    }
+}
+
+#[test]
+fn payload_size_max_is_the_boundary() {
+    for message in [
+        MQTT_MESSAGE_SIZE_MIN,
+        4096,
+        16_386,
+        16_387,
+        16_388,
+        2_097_155,
+    ] {
+        for topic in [0, 1, DEVICE_PREFIX_MAX + "/diagnostics".len()] {
+            for properties in [0, content_type_size("application/json".len()), 127, 128] {
+                let payload = payload_size_max(message, topic, properties);
+                assert!(publish_size(topic, properties, payload) <= message);
+                // At most a byte is lost where the remaining length needs a shorter varint
+                assert!(publish_size(topic, properties, payload + 2) > message);
+            }
+        }
+    }
+}
+
+#[test]
+fn diagnostics_fit_in_a_message() {
+    // The firmware drops the last routes if they don't fit, never at the minimum size
+    let topic = DEVICE_PREFIX_MAX + "/diagnostics".len();
+    for (content_type, payload) in encodings(&widest_diagnostics()) {
+        let size = payload_size_max(
+            MQTT_MESSAGE_SIZE_MIN,
+            topic,
+            content_type_size(content_type.len()),
+        );
+        assert!(
+            payload.len() <= size,
+            "{} diagnostics are {} bytes, {} fit",
+            content_type,
+            payload.len(),
+            size
+        );
+    }
+}
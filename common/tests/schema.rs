@@ -0,0 +1,211 @@
+use mqtt_rtic_common::message::{
+    content_type_size, publish_size, MQTT_MESSAGE_SIZE_MIN, TOPIC_SIZE_MAX,
+};
+use mqtt_rtic_common::schema::{
+    visit_fields, write_nth_document, write_root, Annotations, Field, FieldVisitor, Kind,
+    DOCUMENTS_MAX,
+};
+use mqtt_rtic_common::telemetry::Telemetry;
+use serde_json::Value;
+
+fn documents() -> Vec<(&'static str, String)> {
+    (0..)
+        .map_while(|index| {
+            let mut document = String::new();
+            let id = write_nth_document::<Telemetry, _>(&mut document, index)?.unwrap();
+            Some((id, document))
+        })
+        .collect()
+}
+
+/// Collect the `$ref` values of a schema.
+fn references(value: &Value, refs: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => refs.push(reference.clone()),
+                    _ => references(value, refs),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| references(value, refs)),
+        _ => {}
+    }
+}
+
+#[test]
+fn root_refers_to_the_root_type() {
+    let mut root = String::new();
+    write_root::<Telemetry, _>(&mut root, "mqtt-rtic 0.1.0").unwrap();
+    let root: Value = serde_json::from_str(&root).unwrap();
+    assert_eq!(
+        root["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+    assert_eq!(root["$comment"], "mqtt-rtic 0.1.0");
+    assert_eq!(root["$ref"], "Telemetry");
+}
+
+#[test]
+fn documents_are_json_with_their_id() {
+    let documents = documents();
+    assert_eq!(documents[0].0, "Telemetry");
+    for (id, document) in &documents {
+        let schema: Value = serde_json::from_str(document).unwrap();
+        assert_eq!(schema["$id"], *id);
+        assert!(!schema["description"].as_str().unwrap().is_empty());
+    }
+}
+
+#[test]
+fn documents_fit_in_a_message() {
+    // The topic is `<prefix>/schema/<$id>`
+    let properties = content_type_size("application/schema+json".len());
+    for (id, document) in documents() {
+        let size = publish_size(TOPIC_SIZE_MAX, properties, document.len());
+        assert!(
+            size <= MQTT_MESSAGE_SIZE_MIN,
+            "`{}` is {} bytes, {} with the headers",
+            id,
+            document.len(),
+            size
+        );
+    }
+}
+
+#[test]
+fn references_resolve_to_a_single_document() {
+    let documents = documents();
+    assert!(documents.len() < DOCUMENTS_MAX);
+
+    let ids: Vec<_> = documents.iter().map(|(id, _)| *id).collect();
+    for (i, id) in ids.iter().enumerate() {
+        assert!(!ids[..i].contains(id), "`{}` is published twice", id);
+    }
+
+    let mut refs = Vec::new();
+    for (_, document) in &documents {
+        references(&serde_json::from_str(document).unwrap(), &mut refs);
+    }
+    for id in &ids[1..] {
+        assert!(refs.iter().any(|r| r == id), "`{}` isn't referenced", id);
+    }
+    for reference in refs {
+        assert!(
+            ids.contains(&reference.as_str()),
+            "`{}` is missing",
+            reference
+        );
+    }
+}
+
+#[test]
+fn fields_are_described_by_their_type() {
+    let mut document = String::new();
+    write_nth_document::<Telemetry, _>(&mut document, 0)
+        .unwrap()
+        .unwrap();
+    let schema: Value = serde_json::from_str(&document).unwrap();
+
+    let time_sync = &schema["properties"]["time_sync"];
+    assert_eq!(time_sync["$ref"], "TimeSyncStatus");
+    assert_eq!(time_sync.get("description"), None);
+
+    let timestamp = &schema["properties"]["timestamp"];
+    assert_eq!(
+        timestamp["description"],
+        "UTC time of the sample since the Unix epoch, once synchronized."
+    );
+    assert_eq!(timestamp["unit"], "ms");
+    assert_eq!(timestamp["anyOf"][1]["type"], "integer");
+}
+
+#[derive(Default)]
+struct Fields(Vec<(String, Kind, Annotations)>);
+
+impl FieldVisitor for Fields {
+    fn visit(&mut self, field: &Field) {
+        self.0
+            .push((field.path.to_string(), field.kind, field.annotations));
+    }
+}
+
+/// Collect the paths of the scalar values of JSON.
+fn leaves(path: String, value: &Value, paths: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let path = match path.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", path, key),
+                };
+                leaves(path, value, paths);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                leaves(format!("{}[{}]", path, i), value, paths);
+            }
+        }
+        _ => paths.push(path),
+    }
+}
+
+#[test]
+fn fields_are_the_scalars_of_the_telemetry() {
+    let mut fields = Fields::default();
+    visit_fields::<Telemetry, _>(&mut fields).unwrap();
+    let paths: Vec<_> = fields.0.iter().map(|(path, _, _)| path.clone()).collect();
+
+    let mut expected = Vec::new();
+    leaves(
+        String::new(),
+        &serde_json::to_value(Telemetry::default()).unwrap(),
+        &mut expected,
+    );
+    expected.sort();
+    let mut sorted = paths.clone();
+    sorted.sort();
+    assert_eq!(sorted, expected);
+}
+
+#[test]
+fn fields_carry_their_annotations() {
+    let mut fields = Fields::default();
+    visit_fields::<Telemetry, _>(&mut fields).unwrap();
+    let field = |path: &str| {
+        fields
+            .0
+            .iter()
+            .find(|(p, _, _)| p == path)
+            .map(|(_, kind, annotations)| (*kind, *annotations))
+            .unwrap()
+    };
+
+    let (kind, annotations) = field("timestamp");
+    assert_eq!(kind, Kind::Number);
+    assert_eq!(annotations.unit, Some("ms"));
+    assert_eq!(annotations.class, Some("timestamp"));
+
+    let (kind, annotations) = field("reachability.gateway.rtt_min_ms");
+    assert_eq!(kind, Kind::Number);
+    assert_eq!(
+        annotations.description,
+        "Minimum round-trip time of the replies in the window."
+    );
+    assert_eq!(annotations.unit, Some("ms"));
+    assert_eq!(annotations.class, None);
+
+    let (kind, annotations) = field("analog.values[3]");
+    assert_eq!(kind, Kind::Number);
+    assert!(annotations.description.starts_with("Engineering values"));
+
+    assert_eq!(field("inputs.states[0]").0, Kind::Boolean);
+    assert_eq!(field("connection.state").0, Kind::Text);
+    assert_eq!(field("watchdog.last_escalation").0, Kind::Text);
+    assert_eq!(
+        field("environment.humidity_percent").1.class,
+        Some("humidity")
+    );
+}
@@ -0,0 +1,165 @@
+use mqtt_rtic_common::message::{publish_size, TOPIC_SIZE_MAX};
+use mqtt_rtic_common::settings::*;
+use mqtt_rtic_common::sparkplug::{
+    encode_setting, encode_telemetry,
+    metrics::MetricSet,
+    payload::{metrics, PayloadEncoder, Value},
+    start_birth, topic_size, SETTINGS_PREFIX, SPARKPLUG_MESSAGE_SIZE_MIN,
+};
+use mqtt_rtic_common::telemetry::{Telemetry, ANALOG_INPUTS_MAX};
+use worst_case::{worst_case, WIDEST_F32};
+
+mod worst_case;
+
+/// Larger than any payload, the tests measure it.
+type Payload = PayloadEncoder<16384>;
+
+/// Every setting at its widest JSON, as miniconf serializes it.
+///
+/// Mirrors `Settings` of the firmware, which doesn't build on the host, through the sizes
+/// of its tree.
+fn settings() -> Vec<(String, String)> {
+    let float = serde_json::to_string(&WIDEST_F32).unwrap();
+    let mut settings = vec![
+        ("led".to_string(), "false".to_string()),
+        ("log/level".to_string(), "\"Trace\"".to_string()),
+    ];
+    for n in 0..MODULE_OVERRIDES_MAX {
+        let module = "m".repeat(MODULE_NAME_MAX);
+        settings.push((
+            format!("log/modules/{}", n),
+            format!("{{\"module\":\"{}\",\"level\":\"Trace\"}}", module),
+        ));
+    }
+    for n in 0..ANALOG_INPUTS_MAX {
+        settings.push((
+            format!("analog/{}", n),
+            format!(
+                "{{\"gain\":{0},\"offset\":{0},\"scale\":{0},\"zero\":{0}}}",
+                float
+            ),
+        ));
+    }
+    // Named outputs are reported under their alias, `outputs/<name>`
+    for n in 0..DIGITAL_OUTPUTS_MAX {
+        let name = format!("{}", n).repeat(OUTPUT_NAME_MAX);
+        settings.push((format!("outputs/{}", name), "false".to_string()));
+    }
+    settings.push(("pwm/frequency".to_string(), u32::MAX.to_string()));
+    for channel in 1..=PWM_CHANNELS {
+        for field in ["duty", "min", "max", "ramp"] {
+            settings.push((format!("pwm/ch{}/{}", channel, field), float.clone()));
+        }
+    }
+    settings
+}
+
+/// Encode an NBIRTH as the firmware does.
+///
+/// # Returns
+/// The payload and the telemetry metrics.
+fn birth() -> (Box<Payload>, MetricSet) {
+    let mut payload = Box::new(Payload::default());
+    start_birth(&mut payload, Some(u64::MAX), u8::MAX).unwrap();
+    for (path, value) in settings() {
+        encode_setting(&mut payload, &path, value.as_bytes()).unwrap();
+    }
+    let telemetry = encode_telemetry(&mut payload, &worst_case()).unwrap();
+    (payload, telemetry)
+}
+
+#[test]
+fn birth_fits_in_a_message() {
+    let (payload, _) = birth();
+    // The topic is `spBv1.0/<group>/NBIRTH/<edge node>`, without properties
+    let size = publish_size(TOPIC_SIZE_MAX, 0, payload.as_bytes().len());
+    assert!(
+        size <= SPARKPLUG_MESSAGE_SIZE_MIN,
+        "NBIRTH is {} bytes, {} with the headers",
+        payload.as_bytes().len(),
+        size
+    );
+}
+
+#[test]
+fn birth_declares_every_metric() {
+    let (payload, telemetry) = birth();
+    let names: Vec<_> = metrics(payload.as_bytes())
+        .map(|metric| metric.name.unwrap().to_string())
+        .collect();
+
+    let settings = settings();
+    assert_eq!(names.len(), 2 + settings.len() + telemetry.len());
+    assert_eq!(names[..2], ["bdSeq", "Node Control/Rebirth"]);
+    for ((path, _), name) in settings.iter().zip(&names[2..]) {
+        assert_eq!(name.strip_prefix(SETTINGS_PREFIX), Some(path.as_str()));
+    }
+    assert!(names.contains(&"analog/values/3".to_string()));
+    assert!(names.contains(&"watchdog/last_escalation".to_string()));
+}
+
+#[test]
+fn topic_size_is_the_longest_topic() {
+    let topic = "spBv1.0/group/NBIRTH/mqtt-rtic-02-00-00-03-02-00";
+    assert_eq!(
+        topic_size("group".len(), "mqtt-rtic-02-00-00-03-02-00".len()),
+        topic.len()
+    );
+}
+
+#[test]
+fn unset_telemetry_has_the_same_metrics() {
+    let encode = |telemetry: &Telemetry| {
+        let mut payload = Box::new(Payload::default());
+        let set = encode_telemetry(&mut payload, telemetry).unwrap();
+        let metrics: Vec<_> = metrics(payload.as_bytes())
+            .map(|metric| (metric.name.unwrap().to_string(), metric.value.datatype()))
+            .collect();
+        (set, metrics, payload)
+    };
+    let (set, metrics, _) = encode(&worst_case());
+    let (unset, unset_metrics, payload) = encode(&Telemetry::default());
+    assert_eq!(set, unset);
+    assert_eq!(metrics, unset_metrics);
+
+    let null = |name: &str| {
+        find_metric(payload.as_bytes(), name)
+            .map(|value| matches!(value, Value::Null(_)))
+            .unwrap()
+    };
+    assert!(null("timestamp"));
+    assert!(null("analog/values/3"));
+    assert!(null("inputs/states/0"));
+    assert!(null("watchdog/last_escalation"));
+    assert!(!null("connection/state"));
+}
+
+#[test]
+fn metric_sets_differ_by_name_and_datatype() {
+    let set = |metrics: &[(&str, Value)]| {
+        let mut set = MetricSet::default();
+        for (name, value) in metrics {
+            set.add(name, value);
+        }
+        set
+    };
+    let metrics = [("a", Value::UInt32(1)), ("b", Value::Boolean(true))];
+    assert_eq!(set(&metrics), set(&[("a", Value::Null(7)), metrics[1]]));
+    assert_ne!(set(&metrics), set(&[("a", Value::UInt64(1)), metrics[1]]));
+    assert_ne!(
+        set(&metrics),
+        set(&[("a", Value::UInt32(1)), ("c", Value::Boolean(true))])
+    );
+    assert_ne!(
+        set(&metrics),
+        set(&[("ab", Value::UInt32(1)), ("", Value::Boolean(true))])
+    );
+    assert_ne!(set(&metrics), set(&metrics[..1]));
+}
+
+/// Find the value of a metric in a payload.
+fn find_metric<'a>(payload: &'a [u8], name: &str) -> Option<Value<'a>> {
+    metrics(payload)
+        .find(|metric| metric.name == Some(name))
+        .map(|metric| metric.value)
+}
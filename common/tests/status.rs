@@ -0,0 +1,136 @@
+use mqtt_rtic_common::status::{Blink, LedStates, StatusIndicator, SystemState, SystemStatus};
+
+fn connected() -> SystemStatus {
+    SystemStatus {
+        booting: false,
+        link: true,
+        mqtt: true,
+        ..Default::default()
+    }
+}
+
+/// Step the indicator `ticks` times, returning the LEDs of the last step.
+fn run(indicator: &mut StatusIndicator, status: &mut SystemStatus, ticks: u32) -> LedStates {
+    (0..ticks).map(|_| indicator.update(status)).last().unwrap()
+}
+
+#[test]
+fn state_follows_priority() {
+    let mut status = connected();
+    assert_eq!(status.state(), SystemState::Connected);
+    status.mqtt = false;
+    assert_eq!(status.state(), SystemState::NoBroker);
+    status.link = false;
+    assert_eq!(status.state(), SystemState::NoLink);
+    status.booting = true;
+    assert_eq!(status.state(), SystemState::Booting);
+    status.updating = true;
+    assert_eq!(status.state(), SystemState::Updating);
+    status.fault = true;
+    assert_eq!(status.state(), SystemState::Fault);
+
+    // A fault hides a healthy connection too.
+    let status = SystemStatus {
+        fault: true,
+        ..connected()
+    };
+    assert_eq!(status.state(), SystemState::Fault);
+    assert_eq!(SystemStatus::default().state(), SystemState::Booting);
+}
+
+#[test]
+fn blinks_are_periodic() {
+    let on = |blink: Blink| (0..20).map(|tick| blink.is_on(tick)).collect::<Vec<_>>();
+    assert!(on(Blink::OFF).iter().all(|&on| !on));
+    assert!(on(Blink::ON).iter().all(|&on| on));
+    assert_eq!(
+        on(Blink::FAST),
+        (0..20).map(|tick| tick % 2 == 0).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        on(Blink::SLOW),
+        (0..20).map(|tick| tick % 10 < 5).collect::<Vec<_>>()
+    );
+    assert!(Blink::SLOW.is_on(u32::MAX - 5));
+}
+
+#[test]
+fn pattern_restarts_on_state_change() {
+    let mut indicator = StatusIndicator::new();
+    let mut status = SystemStatus {
+        booting: false,
+        ..Default::default()
+    };
+
+    // The red LED of the no link state is off in the second half of its period.
+    assert!(!run(&mut indicator, &mut status, 7).red);
+    assert_eq!(indicator.state(), SystemState::NoLink);
+
+    status.link = true;
+    run(&mut indicator, &mut status, 1);
+    assert_eq!(indicator.state(), SystemState::NoBroker);
+
+    // Back to the start of the period, not where the pattern was left.
+    status.link = false;
+    assert!(indicator.update(&mut status).red);
+    assert_eq!(indicator.state(), SystemState::NoLink);
+}
+
+#[test]
+fn blue_shows_activity_once_connected() {
+    let mut indicator = StatusIndicator::new();
+    let mut status = connected();
+    assert!(!run(&mut indicator, &mut status, 3).blue);
+
+    status.activity = true;
+    assert!(indicator.update(&mut status).blue);
+    assert!(!status.activity, "the activity is consumed");
+    assert!(!indicator.update(&mut status).blue);
+
+    // Without a connection, the blue LED follows the pattern.
+    let mut indicator = StatusIndicator::new();
+    let mut status = SystemStatus {
+        mqtt: false,
+        activity: true,
+        ..connected()
+    };
+    assert!(indicator.update(&mut status).blue);
+    status.activity = true;
+    assert!(!run(&mut indicator, &mut status, 6).blue);
+}
+
+#[test]
+fn red_shows_led_unless_the_pattern_uses_it() {
+    let shows_led = |status: SystemStatus| {
+        let mut indicator = StatusIndicator::new();
+        let mut red = |led| {
+            let mut status = SystemStatus { led, ..status };
+            // Both ticks of the fast blink.
+            [
+                indicator.update(&mut status).red,
+                indicator.update(&mut status).red,
+            ]
+        };
+        let (off, on) = (red(false), red(true));
+        off == [false; 2] && on == [true; 2]
+    };
+
+    assert!(shows_led(connected()));
+    assert!(shows_led(SystemStatus {
+        mqtt: false,
+        ..connected()
+    }));
+    assert!(shows_led(SystemStatus {
+        updating: true,
+        ..connected()
+    }));
+    assert!(!shows_led(SystemStatus {
+        link: false,
+        ..connected()
+    }));
+    assert!(!shows_led(SystemStatus {
+        fault: true,
+        ..connected()
+    }));
+    assert!(!shows_led(SystemStatus::default()));
+}
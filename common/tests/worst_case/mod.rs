@@ -0,0 +1,79 @@
+//! The largest values of the shared types, shared by the tests.
+use mqtt_rtic_common::telemetry::*;
+
+/// An `f32` of the widest JSON, 16 characters: none is wider, serializing every `f32`
+/// shows.
+pub const WIDEST_F32: f32 = -0.000_001_000_000_1;
+
+fn ping_stats() -> PingStats {
+    PingStats {
+        samples: u8::MAX,
+        loss_percent: u8::MAX,
+        rtt_min_ms: u32::MAX,
+        rtt_avg_ms: u32::MAX,
+        rtt_max_ms: u32::MAX,
+    }
+}
+
+/// Every `Option` set, the widest numbers and enum variants, and `false` over `true`.
+pub fn worst_case() -> Telemetry {
+    let float = WIDEST_F32;
+    Telemetry {
+        dummy: u32::MAX,
+        timestamp: Some(u64::MAX),
+        time_sync: TimeSyncStatus {
+            synchronized: false,
+            offset_ms: i64::MIN,
+            drift_ppm: i32::MIN,
+            since_sync_s: u32::MAX,
+        },
+        reachability: Reachability {
+            gateway: ping_stats(),
+            broker: ping_stats(),
+        },
+        connection: ConnectionStatus {
+            state: ConnectionState::MqttConnected,
+            since_s: u32::MAX,
+            totals: StateTimes {
+                down_s: u32::MAX,
+                link_s: u32::MAX,
+                ip_configured_s: u32::MAX,
+                tcp_connected_s: u32::MAX,
+                mqtt_connected_s: u32::MAX,
+                subscribed_s: u32::MAX,
+            },
+            sessions: u32::MAX,
+            reconnect: ReconnectStats {
+                attempts: u32::MAX,
+                failures: u32::MAX,
+                retry_in_ms: u32::MAX,
+            },
+        },
+        polling: PollStats {
+            polls: u32::MAX,
+            cpu_load_permille: u32::MAX,
+            latency_avg_us: u32::MAX,
+            latency_max_us: u32::MAX,
+        },
+        environment: Environment {
+            temperature_c: Some(float),
+            humidity_percent: Some(float),
+            errors: u32::MAX,
+        },
+        analog: AnalogTelemetry {
+            values: [Some(float); ANALOG_INPUTS_MAX],
+            overruns: u32::MAX,
+        },
+        inputs: InputTelemetry {
+            states: [Some(false); DIGITAL_INPUTS_MAX],
+            changes: u32::MAX,
+        },
+        watchdog: WatchdogStatus {
+            reset_cause: ResetCause::WindowWatchdog,
+            stack_resets: u32::MAX,
+            phy_resets: u32::MAX,
+            last_escalation: Some(Escalation::SystemReset),
+            outage_s: u32::MAX,
+        },
+    }
+}
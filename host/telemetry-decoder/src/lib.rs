@@ -0,0 +1,72 @@
+//! Host-side decoder of the mqtt-rtic telemetry streams.
+//!
+//! # Design
+//!  The payload encoding is selected by the MQTT v5 content type of the message. The
+//!  telemetry and diagnostics types are the firmware's own, from `mqtt-rtic-common`,
+//!  which postcard relies on since it isn't self-describing.
+pub use mqtt_rtic_common::{
+    diagnostics::{NetworkDiagnostics, RouteEntry},
+    telemetry::*,
+};
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+    Postcard,
+}
+
+impl Encoding {
+    /// Get the encoding of an MQTT v5 content type.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "application/json" => Some(Encoding::Json),
+            "application/cbor" => Some(Encoding::Cbor),
+            "application/x-postcard" => Some(Encoding::Postcard),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownContentType(String),
+    Json(serde_json::Error),
+    Cbor(serde_cbor::Error),
+    Postcard(postcard::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownContentType(content_type) => {
+                write!(f, "unknown content type `{}`", content_type)
+            }
+            Error::Json(e) => write!(f, "JSON: {}", e),
+            Error::Cbor(e) => write!(f, "CBOR: {}", e),
+            Error::Postcard(e) => write!(f, "postcard: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Decode a payload.
+///
+/// # Args
+/// * `content_type` - The MQTT v5 content type of the message, JSON if it has none.
+/// * `payload` - The message payload.
+pub fn decode<T: DeserializeOwned>(content_type: Option<&str>, payload: &[u8]) -> Result<T, Error> {
+    let encoding = match content_type {
+        Some(content_type) => Encoding::from_content_type(content_type)
+            .ok_or_else(|| Error::UnknownContentType(content_type.to_owned()))?,
+        None => Encoding::Json,
+    };
+    match encoding {
+        Encoding::Json => serde_json::from_slice(payload).map_err(Error::Json),
+        Encoding::Cbor => serde_cbor::from_slice(payload).map_err(Error::Cbor),
+        Encoding::Postcard => postcard::from_bytes(payload).map_err(Error::Postcard),
+    }
+}
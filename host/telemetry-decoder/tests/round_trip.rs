@@ -0,0 +1,185 @@
+//! Round trips through the encodings of the firmware, and decoding of payloads encoded
+//! by the firmware types.
+//!
+//! The fixtures pin the wire format: a field that is moved, renamed or retyped breaks
+//! them, and with them existing consumers. Regenerate them only along with a
+//! deliberate format change, with `REGENERATE_FIXTURES=1 cargo test`.
+use heapless::{String, Vec};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, fs, path::PathBuf};
+use telemetry_decoder::*;
+
+const CONTENT_TYPES: [(&str, &str); 3] = [
+    ("application/json", "json"),
+    ("application/cbor", "cbor"),
+    ("application/x-postcard", "postcard"),
+];
+
+/// Encode a value like the firmware does.
+///
+/// The firmware encodes JSON with serde-json-core, which writes the same compact JSON
+/// as serde_json for these types, and CBOR with the default serde_cbor serializer.
+fn encode<T: Serialize>(extension: &str, value: &T) -> std::vec::Vec<u8> {
+    match extension {
+        "json" => serde_json::to_vec(value).unwrap(),
+        "cbor" => serde_cbor::to_vec(value).unwrap(),
+        "postcard" => postcard::to_allocvec(value).unwrap(),
+        _ => unreachable!(),
+    }
+}
+
+/// Check the round trip of a value in every encoding, and its fixtures.
+fn check<T: Serialize + DeserializeOwned + PartialEq + Debug>(name: &str, value: &T) {
+    for (content_type, extension) in CONTENT_TYPES {
+        let payload = encode(extension, value);
+        let decoded: T = decode(Some(content_type), &payload).unwrap();
+        assert_eq!(&decoded, value, "{} round trip", content_type);
+
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures"]
+            .iter()
+            .collect::<PathBuf>()
+            .join(format!("{}.{}", name, extension));
+        if std::env::var_os("REGENERATE_FIXTURES").is_some() {
+            fs::write(&path, &payload).unwrap();
+        }
+        let fixture = fs::read(&path).unwrap();
+        let decoded: T = decode(Some(content_type), &fixture).unwrap();
+        assert_eq!(&decoded, value, "{} fixture", path.display());
+        assert_eq!(payload, fixture, "{} changed", path.display());
+    }
+}
+
+fn telemetry() -> Telemetry {
+    Telemetry {
+        dummy: 42,
+        timestamp: Some(1_650_000_000_000),
+        time_sync: TimeSyncStatus {
+            synchronized: true,
+            offset_ms: 1_649_999_990_000,
+            drift_ppm: -12,
+            since_sync_s: 30,
+        },
+        reachability: Reachability {
+            gateway: PingStats {
+                samples: 16,
+                loss_percent: 0,
+                rtt_min_ms: 1,
+                rtt_avg_ms: 2,
+                rtt_max_ms: 5,
+            },
+            broker: PingStats {
+                samples: 16,
+                loss_percent: 6,
+                rtt_min_ms: 3,
+                rtt_avg_ms: 4,
+                rtt_max_ms: 9,
+            },
+        },
+        connection: ConnectionStatus {
+            state: ConnectionState::Subscribed,
+            since_s: 600,
+            totals: StateTimes {
+                down_s: 2,
+                link_s: 3,
+                ip_configured_s: 1,
+                tcp_connected_s: 0,
+                mqtt_connected_s: 0,
+                subscribed_s: 600,
+            },
+            sessions: 1,
+            reconnect: ReconnectStats {
+                attempts: 1,
+                failures: 0,
+                retry_in_ms: 0,
+            },
+        },
+        polling: PollStats {
+            polls: 120,
+            cpu_load_permille: 7,
+            latency_avg_us: 15,
+            latency_max_us: 40,
+        },
+        environment: Environment {
+            temperature_c: Some(21.5),
+            humidity_percent: None,
+            errors: 1,
+        },
+        analog: AnalogTelemetry {
+            values: [Some(0.25), Some(-3.5), None, None],
+            overruns: 0,
+        },
+        inputs: InputTelemetry {
+            states: [Some(true), Some(false), None, None],
+            changes: 3,
+        },
+        watchdog: WatchdogStatus {
+            reset_cause: ResetCause::Pin,
+            stack_resets: 1,
+            phy_resets: 0,
+            last_escalation: Some(Escalation::StackReset),
+            outage_s: 0,
+        },
+    }
+}
+
+#[test]
+fn telemetry_round_trip() {
+    check("telemetry", &telemetry());
+}
+
+#[test]
+fn unsynchronized_telemetry_round_trip() {
+    check("telemetry-default", &Telemetry::default());
+}
+
+#[test]
+fn input_event_round_trip() {
+    check(
+        "input-event",
+        &InputEvent {
+            state: true,
+            timestamp: Some(1_650_000_000_000),
+        },
+    );
+}
+
+#[test]
+fn diagnostics_round_trip() {
+    let mut addresses = Vec::new();
+    addresses.push(String::from("10.0.0.2/24")).unwrap();
+    addresses.push(String::from("fe80::ff:fe00:1/64")).unwrap();
+    let mut routes = Vec::new();
+    routes
+        .push(RouteEntry {
+            destination: String::from("0.0.0.0/0"),
+            via: String::from("10.0.0.1"),
+        })
+        .unwrap();
+    check(
+        "diagnostics",
+        &NetworkDiagnostics {
+            addresses,
+            routes,
+            omitted_routes: 2,
+        },
+    );
+}
+
+#[test]
+fn json_without_content_type() {
+    let payload = encode("json", &telemetry());
+    let decoded: Telemetry = decode(None, &payload).unwrap();
+    assert_eq!(decoded, telemetry());
+}
+
+#[test]
+fn unsynchronized_timestamp_is_null() {
+    let json = std::string::String::from_utf8(encode("json", &Telemetry::default())).unwrap();
+    assert!(json.contains("\"timestamp\":null"));
+}
+
+#[test]
+fn unknown_content_type() {
+    let result: Result<Telemetry, _> = decode(Some("text/plain"), b"{}");
+    assert!(matches!(result, Err(Error::UnknownContentType(_))));
+}
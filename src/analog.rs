@@ -0,0 +1,84 @@
+//! Engineering values of the analog inputs.
+//!
+//! # Design
+//!  The raw samples of every input are summed as the ADC buffers complete and averaged
+//!  when telemetry is sampled. The average is converted to the voltage at the pin,
+//!  corrected by the calibration of the input, and scaled to engineering units:
+//!
+//!  `value = (volts * gain + offset) * scale + zero`
+use crate::hardware::adc::ANALOG_INPUTS_MAX;
+use crate::settings::AnalogSettings;
+pub use mqtt_rtic_common::telemetry::AnalogTelemetry;
+
+/// Full scale of the 12 bit conversions.
+const ADC_FULL_SCALE: f32 = 4095.0;
+
+pub struct AnalogAverages {
+    inputs: usize,
+    reference_mv: u32,
+    sums: [u32; ANALOG_INPUTS_MAX],
+    scans: u32,
+    overruns: u32,
+}
+
+impl AnalogAverages {
+    /// Construct the averages of the analog inputs.
+    ///
+    /// # Args
+    /// * `inputs` - The number of inputs scanned, zero without analog sampling.
+    /// * `reference_mv` - The analog supply voltage.
+    pub fn new(inputs: usize, reference_mv: u32) -> Self {
+        Self {
+            inputs: inputs.min(ANALOG_INPUTS_MAX),
+            reference_mv,
+            sums: [0; ANALOG_INPUTS_MAX],
+            scans: 0,
+            overruns: 0,
+        }
+    }
+
+    /// Add the samples of a completed ADC buffer.
+    pub fn process(&mut self, samples: &[u16]) {
+        if self.inputs == 0 {
+            return;
+        }
+        for scan in samples.chunks_exact(self.inputs) {
+            for (sum, &sample) in self.sums.iter_mut().zip(scan) {
+                *sum += u32::from(sample);
+            }
+            self.scans += 1;
+        }
+    }
+
+    pub fn overrun(&mut self) {
+        self.overruns = self.overruns.wrapping_add(1);
+    }
+
+    /// Take the averages since the previous call.
+    ///
+    /// # Args
+    /// * `settings` - The calibration and scaling of the inputs.
+    pub fn take(&mut self, settings: &[AnalogSettings; ANALOG_INPUTS_MAX]) -> AnalogTelemetry {
+        let mut values = [None; ANALOG_INPUTS_MAX];
+        if self.scans != 0 {
+            let volts_per_count = self.reference_mv as f32 / 1000.0 / ADC_FULL_SCALE;
+            for ((value, sum), settings) in values
+                .iter_mut()
+                .zip(self.sums.iter())
+                .zip(settings.iter())
+                .take(self.inputs)
+            {
+                let volts = *sum as f32 / self.scans as f32 * volts_per_count;
+                let calibrated = volts * settings.gain + settings.offset;
+                *value = Some(calibrated * settings.scale + settings.zero);
+            }
+        }
+        self.sums = [0; ANALOG_INPUTS_MAX];
+        self.scans = 0;
+
+        AnalogTelemetry {
+            values,
+            overruns: self.overruns,
+        }
+    }
+}
@@ -1,26 +1,322 @@
+use crate::hardware::adc::{AnalogInput, ANALOG_INPUTS_MAX};
+use crate::inputs::{InputConfig, DIGITAL_INPUTS_MAX, INPUT_NAME_MAX};
+use crate::net::backoff::BackoffConfig;
+use crate::net::encoding::Encoding;
+use crate::outputs::{OutputConfig, DIGITAL_OUTPUTS_MAX};
+use crate::watchdog::WatchdogConfig;
+use heapless::Vec;
 use log::info;
-use smoltcp::wire::{EthernetAddress, Ipv4Address};
+use smoltcp::wire::{EthernetAddress, IpAddress, Ipv4Address, Ipv4Cidr};
 
-#[derive(Clone, Copy, Debug)]
+pub const IP_DEFAULT_PREFIX_LEN: u8 = 24;
+
+pub use mqtt_rtic_common::diagnostics::STATIC_ROUTES_MAX;
+
+pub const SYSLOG_DEFAULT_PORT: u16 = 514;
+
+pub use mqtt_rtic_common::config::NTP_SERVERS_MAX;
+pub const NTP_DEFAULT_POLL_INTERVAL_SECS: u32 = 64;
+
+pub const TOPIC_DEFAULT_TEMPLATE: &str = "dt/{class}/{app}/{mac}";
+pub const DEFAULT_DEVICE_CLASS: &str = "dummy";
+
+/// The user button of the Nucleo board.
+pub const DIGITAL_INPUTS_DEFAULT: &str = "PC13=button";
+
+pub const OUTPUT_DEFAULT_FAIL_SAFE_TIMEOUT_SECS: u32 = 10;
+
+pub const MQTT_DEFAULT_BACKOFF_MIN_MS: u32 = 1_000;
+pub const MQTT_DEFAULT_BACKOFF_MAX_MS: u32 = 60_000;
+
+pub const WATCHDOG_DEFAULT_STACK_RESET_MINS: u32 = 5;
+pub const WATCHDOG_DEFAULT_PHY_RESET_MINS: u32 = 10;
+pub const WATCHDOG_DEFAULT_SYSTEM_RESET_MINS: u32 = 15;
+
+#[derive(Clone, Debug)]
 pub struct Config {
     pub mac_address: EthernetAddress,
     pub ip_address: Ipv4Address,
-    pub broker_ip_address: Ipv4Address,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Address>,
+    pub routes: Vec<StaticRoute, STATIC_ROUTES_MAX>,
+    /// IPv4 or IPv6 address of the MQTT broker.
+    pub broker_ip_address: IpAddress,
+    pub syslog: Option<SyslogConfig>,
+    pub ntp: Option<NtpConfig>,
+    pub telemetry_encoding: Encoding,
+    pub diagnostics_encoding: Encoding,
+    /// Sparkplug B group ID, publishes telemetry as a Sparkplug edge node when set.
+    pub sparkplug_group_id: Option<&'static str>,
+    pub topic: TopicConfig,
+    /// The analog inputs sampled by the ADC, in order.
+    pub analog_inputs: Vec<AnalogInput, ANALOG_INPUTS_MAX>,
+    /// The digital inputs publishing events, in order.
+    pub digital_inputs: Vec<InputConfig, DIGITAL_INPUTS_MAX>,
+    /// The digital outputs controlled by the settings, in order.
+    pub digital_outputs: Vec<OutputConfig, DIGITAL_OUTPUTS_MAX>,
+    /// Time without broker connection before the outputs fail safe.
+    pub output_fail_safe_timeout_secs: u32,
+    /// Limits of the delay between reconnections to the broker.
+    pub mqtt_backoff: BackoffConfig,
+    /// Escalation of the resets without broker connection.
+    pub watchdog: WatchdogConfig,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StaticRoute {
+    pub destination: Ipv4Cidr,
+    pub via: Ipv4Address,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SyslogConfig {
+    pub ip_address: Ipv4Address,
+    pub port: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct NtpConfig {
+    pub servers: Vec<Ipv4Address, NTP_SERVERS_MAX>,
+    pub poll_interval_secs: u32,
+}
+
+/// The MQTT topic template of the device and the values of its placeholders.
+#[derive(Clone, Copy, Debug)]
+pub struct TopicConfig {
+    pub template: &'static str,
+    pub site: Option<&'static str>,
+    pub device_class: &'static str,
+    pub device_name: Option<&'static str>,
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            template: TOPIC_DEFAULT_TEMPLATE,
+            site: None,
+            device_class: DEFAULT_DEVICE_CLASS,
+            device_name: None,
+        }
+    }
 }
 
 impl Config {
     /// export MAC_ADDRESS="02:00:00:03:02:00"
     /// export IP_ADDRESS="a.b.c.d"
-    /// export BROKER_IP_ADDRESS="a.b.c.d"
+    /// export BROKER_IP_ADDRESS="a.b.c.d" (or an IPv6 address)
+    ///
+    /// Optional:
+    /// export IP_PREFIX_LEN="24"
+    /// export GATEWAY_IP_ADDRESS="a.b.c.d"
+    /// export STATIC_ROUTES="a.b.0.0/16=a.b.c.d,e.f.0.0/16=a.b.c.e"
+    /// export SYSLOG_IP_ADDRESS="a.b.c.d"
+    /// export SYSLOG_PORT="514"
+    /// export NTP_SERVERS="a.b.c.d,a.b.c.e"
+    /// export NTP_POLL_INTERVAL="64"
+    /// export SPARKPLUG_GROUP_ID="group"
+    /// export TELEMETRY_ENCODING="json" (or "cbor", "postcard")
+    /// export DIAGNOSTICS_ENCODING="json" (or "cbor", "postcard")
+    /// export TOPIC_TEMPLATE="{site}/{class}/{app}/{mac}"
+    /// export SITE="site"
+    /// export DEVICE_CLASS="dummy"
+    /// export DEVICE_NAME="name"
+    /// export ADC_CHANNELS="PA3,PC0,PC3,PB1"
+    /// export DIGITAL_INPUTS="PC13=button" (or "" for none)
+    /// export DIGITAL_OUTPUTS="PG0=relay1:high:off:off,PG1=valve:low:off:on"
+    /// export OUTPUT_FAIL_SAFE_TIMEOUT="10"
+    /// export MQTT_BACKOFF_MIN_MS="1000"
+    /// export MQTT_BACKOFF_MAX_MS="60000"
+    /// export WATCHDOG_STACK_RESET_MINS="5" (or "0" to disable)
+    /// export WATCHDOG_PHY_RESET_MINS="10" (or "0" to disable)
+    /// export WATCHDOG_SYSTEM_RESET_MINS="15" (or "0" to disable)
     pub fn load_from_env() -> Self {
         let cfg = Self {
             mac_address: env!("MAC_ADDRESS").parse().unwrap(),
             ip_address: env!("IP_ADDRESS").parse().unwrap(),
+            prefix_len: option_env!("IP_PREFIX_LEN")
+                .map(|p| p.parse().unwrap())
+                .unwrap_or(IP_DEFAULT_PREFIX_LEN),
+            gateway: option_env!("GATEWAY_IP_ADDRESS").map(|ip| ip.parse().unwrap()),
+            // build.rs checks that there are at most `STATIC_ROUTES_MAX`
+            routes: option_env!("STATIC_ROUTES")
+                .map(|routes| routes.split(',').map(parse_static_route).collect())
+                .unwrap_or_default(),
             broker_ip_address: env!("BROKER_IP_ADDRESS").parse().unwrap(),
+            syslog: option_env!("SYSLOG_IP_ADDRESS").map(|ip| SyslogConfig {
+                ip_address: ip.parse().unwrap(),
+                port: option_env!("SYSLOG_PORT")
+                    .map(|p| p.parse().unwrap())
+                    .unwrap_or(SYSLOG_DEFAULT_PORT),
+            }),
+            ntp: option_env!("NTP_SERVERS").map(|servers| NtpConfig {
+                // build.rs checks that there are at most `NTP_SERVERS_MAX`
+                servers: servers
+                    .split(',')
+                    .map(|ip| ip.trim().parse().unwrap())
+                    .collect(),
+                poll_interval_secs: option_env!("NTP_POLL_INTERVAL")
+                    .map(|p| p.parse().unwrap())
+                    .unwrap_or(NTP_DEFAULT_POLL_INTERVAL_SECS),
+            }),
+            telemetry_encoding: option_env!("TELEMETRY_ENCODING")
+                .map(|e| e.parse().unwrap())
+                .unwrap_or_default(),
+            diagnostics_encoding: option_env!("DIAGNOSTICS_ENCODING")
+                .map(|e| e.parse().unwrap())
+                .unwrap_or_default(),
+            sparkplug_group_id: option_env!("SPARKPLUG_GROUP_ID"),
+            topic: TopicConfig {
+                template: option_env!("TOPIC_TEMPLATE").unwrap_or(TOPIC_DEFAULT_TEMPLATE),
+                site: option_env!("SITE"),
+                device_class: option_env!("DEVICE_CLASS").unwrap_or(DEFAULT_DEVICE_CLASS),
+                device_name: option_env!("DEVICE_NAME"),
+            },
+            // build.rs checks that there are at most `ANALOG_INPUTS_MAX`
+            analog_inputs: option_env!("ADC_CHANNELS")
+                .map(|inputs| {
+                    inputs
+                        .split(',')
+                        .map(|i| i.trim().parse().unwrap())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            digital_inputs: option_env!("DIGITAL_INPUTS")
+                .unwrap_or(DIGITAL_INPUTS_DEFAULT)
+                .split(',')
+                .filter(|input| !input.trim().is_empty())
+                .map(parse_digital_input)
+                .collect(),
+            // build.rs checks the outputs, see `validate_outputs` there
+            digital_outputs: option_env!("DIGITAL_OUTPUTS")
+                .map(|outputs| outputs.split(',').map(parse_digital_output).collect())
+                .unwrap_or_default(),
+            output_fail_safe_timeout_secs: option_env!("OUTPUT_FAIL_SAFE_TIMEOUT")
+                .map(|t| t.parse().unwrap())
+                .unwrap_or(OUTPUT_DEFAULT_FAIL_SAFE_TIMEOUT_SECS),
+            mqtt_backoff: BackoffConfig {
+                min_ms: option_env!("MQTT_BACKOFF_MIN_MS")
+                    .map(|t| t.parse().unwrap())
+                    .unwrap_or(MQTT_DEFAULT_BACKOFF_MIN_MS),
+                max_ms: option_env!("MQTT_BACKOFF_MAX_MS")
+                    .map(|t| t.parse().unwrap())
+                    .unwrap_or(MQTT_DEFAULT_BACKOFF_MAX_MS),
+            },
+            watchdog: WatchdogConfig {
+                stack_reset_mins: option_env!("WATCHDOG_STACK_RESET_MINS")
+                    .map(|t| t.parse().unwrap())
+                    .unwrap_or(WATCHDOG_DEFAULT_STACK_RESET_MINS),
+                phy_reset_mins: option_env!("WATCHDOG_PHY_RESET_MINS")
+                    .map(|t| t.parse().unwrap())
+                    .unwrap_or(WATCHDOG_DEFAULT_PHY_RESET_MINS),
+                system_reset_mins: option_env!("WATCHDOG_SYSTEM_RESET_MINS")
+                    .map(|t| t.parse().unwrap())
+                    .unwrap_or(WATCHDOG_DEFAULT_SYSTEM_RESET_MINS),
+            },
         };
+        assert!(
+            0 < cfg.mqtt_backoff.min_ms && cfg.mqtt_backoff.min_ms <= cfg.mqtt_backoff.max_ms,
+            "Invalid MQTT backoff limits"
+        );
+        assert!(
+            cfg.watchdog.is_valid(),
+            "The watchdog steps must escalate in order"
+        );
         info!("MAC address: {}", cfg.mac_address);
-        info!("IP address: {}", cfg.ip_address);
+        info!("IP address: {}/{}", cfg.ip_address, cfg.prefix_len);
+        if let Some(gateway) = cfg.gateway {
+            info!("Gateway: {}", gateway);
+        }
+        for route in cfg.routes.iter() {
+            info!("Static route: {} via {}", route.destination, route.via);
+        }
         info!("Broker IP address: {}", cfg.broker_ip_address);
+        info!(
+            "Broker reconnect backoff: {} to {} ms",
+            cfg.mqtt_backoff.min_ms, cfg.mqtt_backoff.max_ms
+        );
+        info!(
+            "Connectivity watchdog: stack reset {} min, PHY reset {} min, system reset {} min",
+            cfg.watchdog.stack_reset_mins,
+            cfg.watchdog.phy_reset_mins,
+            cfg.watchdog.system_reset_mins
+        );
+        if let Some(syslog) = cfg.syslog {
+            info!("Syslog collector: {}:{}", syslog.ip_address, syslog.port);
+        }
+        if let Some(ntp) = &cfg.ntp {
+            for server in ntp.servers.iter() {
+                info!("NTP server: {}", server);
+            }
+        }
+        info!(
+            "Encodings: telemetry {:?}, diagnostics {:?}",
+            cfg.telemetry_encoding, cfg.diagnostics_encoding
+        );
+        if let Some(group_id) = cfg.sparkplug_group_id {
+            info!("Sparkplug group ID: {}", group_id);
+        }
+        info!("Topic template: {}", cfg.topic.template);
+        if !cfg.analog_inputs.is_empty() {
+            info!("Analog inputs: {:?}", cfg.analog_inputs);
+        }
+        for input in cfg.digital_inputs.iter() {
+            info!("Digital input: {} on {:?}", input.name, input.pin);
+        }
+        for output in cfg.digital_outputs.iter() {
+            info!(
+                "Digital output: {} on {:?}, {:?}, default {}, fail-safe {}",
+                output.name, output.pin, output.polarity, output.default, output.fail_safe
+            );
+        }
         cfg
     }
 }
+
+/// Parse a `<destination>/<prefix length>=<gateway>` static route.
+fn parse_static_route(route: &str) -> StaticRoute {
+    let (destination, via) = route.trim().split_once('=').unwrap();
+    StaticRoute {
+        destination: destination.parse().unwrap(),
+        via: via.parse().unwrap(),
+    }
+}
+
+/// Parse a `<pin>=<name>` digital input.
+fn parse_digital_input(input: &'static str) -> InputConfig {
+    let (pin, name) = input.trim().split_once('=').unwrap();
+    assert!(
+        is_valid_name(name, INPUT_NAME_MAX),
+        "Invalid input name `{}`",
+        name
+    );
+    InputConfig {
+        pin: pin.parse().unwrap(),
+        name,
+    }
+}
+
+/// Parse a `<pin>=<name>:<polarity>:<default>:<fail-safe>` digital output, e.g.
+/// `PG0=relay1:high:off:off`, which build.rs checked.
+fn parse_digital_output(output: &'static str) -> OutputConfig {
+    let (pin, fields) = output.trim().split_once('=').unwrap();
+    let mut fields = fields.split(':');
+    let mut field = || fields.next().unwrap();
+    let name = field();
+    OutputConfig {
+        pin: pin.parse().unwrap(),
+        name,
+        polarity: field().parse().unwrap(),
+        default: parse_state(field()),
+        fail_safe: parse_state(field()),
+    }
+}
+
+/// Parse an `on` or `off` output state.
+fn parse_state(state: &str) -> bool {
+    state == "on"
+}
+
+/// Whether a name is usable as a topic level.
+fn is_valid_name(name: &str, len_max: usize) -> bool {
+    !name.is_empty() && name.len() <= len_max && !name.contains(|c| matches!(c, '/' | '+' | '#'))
+}
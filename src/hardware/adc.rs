@@ -0,0 +1,177 @@
+//! DMA-driven sampling of the analog inputs.
+//!
+//! # Design
+//!  The update event of TIM2 (TRGO) triggers a scan of the configured inputs on ADC1
+//!  at `SCAN_RATE_HZ`, and DMA2 stream 0 moves the conversions into two buffers in
+//!  double buffer mode. When a buffer is full it's swapped for a spare buffer, so the
+//!  samples are processed while the DMA fills the other buffer. If the samples aren't
+//!  taken before the other buffer is full too, the buffer is lost and reported as an
+//!  overrun.
+//!
+//!  The buffer length is a multiple of every channel count, so a scan never straddles
+//!  two buffers.
+use core::str::FromStr;
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, Dma, ExternalTrigger, SampleTime, Scan, Sequence, TriggerMode},
+        Adc,
+    },
+    dma::{config::DmaConfig, DMAError, PeripheralToMemory, Stream0, StreamsTuple, Transfer},
+    gpio::{Analog, PA3, PB1, PC0, PC3},
+    pac::{ADC1, DMA2, TIM2},
+    rcc::Clocks,
+    time::Hertz,
+    timer::Timer,
+};
+
+pub use mqtt_rtic_common::telemetry::ANALOG_INPUTS_MAX;
+
+/// Scans of the configured inputs per second.
+const SCAN_RATE_HZ: u32 = 1_000;
+
+/// A multiple of every channel count up to `ANALOG_INPUTS_MAX`.
+pub const ADC_BUFFER_LEN: usize = 12 * 32;
+
+pub type AdcBuffer = [u16; ADC_BUFFER_LEN];
+
+const SEQUENCE: [Sequence; ANALOG_INPUTS_MAX] = [
+    Sequence::One,
+    Sequence::Two,
+    Sequence::Three,
+    Sequence::Four,
+];
+
+type AdcTransfer =
+    Transfer<Stream0<DMA2>, 0, Adc<ADC1>, PeripheralToMemory, &'static mut AdcBuffer>;
+
+/// The pins usable as analog inputs, named after the pin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AnalogInput {
+    Pa3,
+    Pc0,
+    Pc3,
+    Pb1,
+}
+
+impl FromStr for AnalogInput {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "PA3" => Ok(AnalogInput::Pa3),
+            "PC0" => Ok(AnalogInput::Pc0),
+            "PC3" => Ok(AnalogInput::Pc3),
+            "PB1" => Ok(AnalogInput::Pb1),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct AnalogPins {
+    pub pa3: PA3<Analog>,
+    pub pc0: PC0<Analog>,
+    pub pc3: PC3<Analog>,
+    pub pb1: PB1<Analog>,
+}
+
+/// The ADC buffers were not swapped in time.
+#[derive(Copy, Clone, Debug)]
+pub struct Overrun;
+
+pub struct AnalogSampling {
+    transfer: AdcTransfer,
+    spare: Option<&'static mut AdcBuffer>,
+    reference_mv: u32,
+}
+
+impl AnalogSampling {
+    /// Start sampling the analog inputs.
+    ///
+    /// # Args
+    /// * `adc` - The ADC peripheral.
+    /// * `tim` - The timer triggering the scans.
+    /// * `dma` - The DMA controller of the ADC.
+    /// * `pins` - The analog input pins.
+    /// * `inputs` - The inputs to scan, in order. Must not be empty, at most
+    ///   `ANALOG_INPUTS_MAX` are used.
+    /// * `clocks` - The frozen clock configuration.
+    /// * `buffers` - The two DMA buffers and the spare buffer.
+    pub fn new(
+        adc: ADC1,
+        tim: TIM2,
+        dma: DMA2,
+        pins: AnalogPins,
+        inputs: &[AnalogInput],
+        clocks: &Clocks,
+        buffers: [&'static mut AdcBuffer; 3],
+    ) -> Self {
+        let config = AdcConfig::default()
+            .dma(Dma::Continuous)
+            .scan(Scan::Enabled)
+            .external_trigger(TriggerMode::RisingEdge, ExternalTrigger::Tim_2_trgo);
+        let mut adc = Adc::adc1(adc, true, config);
+        for (&sequence, input) in SEQUENCE.iter().zip(inputs) {
+            let sample_time = SampleTime::Cycles_144;
+            match input {
+                AnalogInput::Pa3 => adc.configure_channel(&pins.pa3, sequence, sample_time),
+                AnalogInput::Pc0 => adc.configure_channel(&pins.pc0, sequence, sample_time),
+                AnalogInput::Pc3 => adc.configure_channel(&pins.pc3, sequence, sample_time),
+                AnalogInput::Pb1 => adc.configure_channel(&pins.pb1, sequence, sample_time),
+            }
+        }
+        let reference_mv = adc.reference_voltage();
+        adc.enable();
+
+        let [first, second, spare] = buffers;
+        let mut transfer = Transfer::init_peripheral_to_memory(
+            StreamsTuple::new(dma).0,
+            adc,
+            first,
+            Some(second),
+            DmaConfig::default()
+                .memory_increment(true)
+                .double_buffer(true)
+                .transfer_complete_interrupt(true),
+        );
+        transfer.start(|_| {});
+
+        // The HAL doesn't expose the trigger output, so the timer is configured by the HAL
+        // and released to route its update event to TRGO.
+        let mut counter = Timer::new(tim, clocks).counter_hz();
+        // Note(unwrap): The scan rate is within the range of the timer
+        counter.start(Hertz::Hz(SCAN_RATE_HZ)).unwrap();
+        let tim = counter.release().release();
+        tim.cr2.modify(|_, w| w.mms().update());
+        tim.cr1.modify(|_, w| w.cen().enabled());
+
+        Self {
+            transfer,
+            spare: Some(spare),
+            reference_mv,
+        }
+    }
+
+    /// The analog supply voltage measured against the internal reference.
+    pub fn reference_mv(&self) -> u32 {
+        self.reference_mv
+    }
+
+    /// Swap the filled buffer on a transfer complete interrupt.
+    ///
+    /// # Returns
+    /// The samples of the filled buffer, interleaved in the order of the inputs.
+    pub fn on_transfer_complete(&mut self) -> Result<&[u16], Overrun> {
+        self.transfer.clear_transfer_complete_interrupt();
+        // Note(unwrap): The spare buffer is always put back below
+        let spare = self.spare.take().unwrap();
+        match self.transfer.next_transfer(spare) {
+            Ok((filled, _)) => Ok(&self.spare.insert(filled)[..]),
+            Err(
+                DMAError::NotReady(spare) | DMAError::SmallBuffer(spare) | DMAError::Overrun(spare),
+            ) => {
+                self.spare = Some(spare);
+                Err(Overrun)
+            }
+        }
+    }
+}
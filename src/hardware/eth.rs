@@ -1,21 +1,80 @@
+use super::sizes::{RX_DESC_RING_COUNT, TX_DESC_RING_COUNT};
+#[cfg(feature = "mac-hash-filter")]
+use smoltcp::wire::EthernetAddress;
 use stm32_eth::{RingEntry, RxDescriptor, TxDescriptor};
 
-const RX_DESC_RING_COUNT: usize = 8;
-const TX_DESC_RING_COUNT: usize = 4;
-
 const RX_DESC_INIT: RingEntry<RxDescriptor> = RingEntry::<RxDescriptor>::new();
 const TX_DESC_INIT: RingEntry<TxDescriptor> = RingEntry::<TxDescriptor>::new();
 
-pub struct EthStorage {
-    pub rx_ring: [RingEntry<RxDescriptor>; RX_DESC_RING_COUNT],
-    pub tx_ring: [RingEntry<TxDescriptor>; TX_DESC_RING_COUNT],
+/// Each descriptor holds a full ethernet frame (1536 bytes).
+pub struct EthStorage<const RX: usize = RX_DESC_RING_COUNT, const TX: usize = TX_DESC_RING_COUNT> {
+    pub rx_ring: [RingEntry<RxDescriptor>; RX],
+    pub tx_ring: [RingEntry<TxDescriptor>; TX],
 }
 
-impl EthStorage {
+impl<const RX: usize, const TX: usize> EthStorage<RX, TX> {
     pub const fn new() -> Self {
         Self {
-            rx_ring: [RX_DESC_INIT; RX_DESC_RING_COUNT],
-            tx_ring: [TX_DESC_INIT; TX_DESC_RING_COUNT],
+            rx_ring: [RX_DESC_INIT; RX],
+            tx_ring: [TX_DESC_INIT; TX],
+        }
+    }
+}
+
+/// The multicast groups the MAC passes, besides broadcast and its own address.
+#[cfg(feature = "mac-hash-filter")]
+pub const MULTICAST_GROUPS: usize = 5;
+
+/// Get the destination addresses of the multicast groups the MAC passes.
+///
+/// # Args
+/// * `mac` - The ethernet MAC address of the device.
+///
+/// # Returns
+/// The IPv4 all-hosts (IGMP queries) and mDNS groups, the IPv6 all-nodes (router
+/// advertisements) and mDNS groups, and the IPv6 solicited-node group of the device. The
+/// link-local and global addresses share their interface identifier, and so their
+/// solicited-node group.
+#[cfg(feature = "mac-hash-filter")]
+pub fn multicast_groups(mac: EthernetAddress) -> [EthernetAddress; MULTICAST_GROUPS] {
+    let m = mac.0;
+    [
+        EthernetAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]),
+        EthernetAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb]),
+        EthernetAddress([0x33, 0x33, 0x00, 0x00, 0x00, 0x01]),
+        EthernetAddress([0x33, 0x33, 0x00, 0x00, 0x00, 0xfb]),
+        EthernetAddress([0x33, 0x33, 0xff, m[3], m[4], m[5]]),
+    ]
+}
+
+/// Get the hash table of the MAC multicast filter passing a set of groups.
+///
+/// The MAC indexes the table with the upper 6 bits of the bit-reversed CRC32 of the
+/// destination address, so unrelated groups sharing a bucket also pass, 1 in 64.
+///
+/// # Returns
+/// The table, `MACHTHR` in the upper 32 bits and `MACHTLR` in the lower ones.
+#[cfg(feature = "mac-hash-filter")]
+pub fn multicast_hash_table(groups: &[EthernetAddress]) -> u64 {
+    groups.iter().fold(0, |table, group| {
+        let index = crc32(group.as_bytes()).reverse_bits() >> 26;
+        table | 1 << index
+    })
+}
+
+/// The Ethernet FCS CRC32.
+#[cfg(feature = "mac-hash-filter")]
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
         }
     }
+    !crc
 }
@@ -1,4 +1,6 @@
-use stm32f4xx_hal::gpio::{Alternate, Output, PushPull, PA2, PB0, PB14, PB7, PC1};
+use stm32f4xx_hal::gpio::{
+    Alternate, OpenDrain, Output, PushPull, PA2, PB0, PB14, PB7, PB8, PB9, PC1, PC6, PC7, PC8, PC9,
+};
 
 pub type LedGreenPin = PB0<Output<PushPull>>;
 pub type LedBluePin = PB7<Output<PushPull>>;
@@ -6,3 +8,11 @@ pub type LedRedPin = PB14<Output<PushPull>>;
 
 pub type PhyMdioPin = PA2<Alternate<PushPull, 11>>;
 pub type PhyMdcPin = PC1<Alternate<PushPull, 11>>;
+
+pub type I2cSclPin = PB8<Alternate<OpenDrain, 4>>;
+pub type I2cSdaPin = PB9<Alternate<OpenDrain, 4>>;
+
+pub type PwmCh1Pin = PC6<Alternate<PushPull, 2>>;
+pub type PwmCh2Pin = PC7<Alternate<PushPull, 2>>;
+pub type PwmCh3Pin = PC8<Alternate<PushPull, 2>>;
+pub type PwmCh4Pin = PC9<Alternate<PushPull, 2>>;
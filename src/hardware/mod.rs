@@ -1,9 +1,34 @@
+pub mod adc;
 pub mod eth;
 pub mod gpio;
 pub mod net;
 pub mod network_clock;
 pub mod phy;
 
+/// The I2C bus of the sensors.
+pub type I2cBus =
+    stm32f4xx_hal::i2c::I2c<stm32f4xx_hal::pac::I2C1, (gpio::I2cSclPin, gpio::I2cSdaPin)>;
+
+/// A handle to the I2C bus, shared by tasks of the same priority.
+pub type SharedI2c = shared_bus_rtic::SharedBus<I2cBus>;
+
+/// The PWM outputs, channels 1 to 4 of TIM3.
+pub type PwmTimer = stm32f4xx_hal::timer::PwmHz<
+    stm32f4xx_hal::pac::TIM3,
+    (
+        stm32f4xx_hal::timer::Ch<{ stm32f4xx_hal::timer::C1 }>,
+        stm32f4xx_hal::timer::Ch<{ stm32f4xx_hal::timer::C2 }>,
+        stm32f4xx_hal::timer::Ch<{ stm32f4xx_hal::timer::C3 }>,
+        stm32f4xx_hal::timer::Ch<{ stm32f4xx_hal::timer::C4 }>,
+    ),
+    (
+        gpio::PwmCh1Pin,
+        gpio::PwmCh2Pin,
+        gpio::PwmCh3Pin,
+        gpio::PwmCh4Pin,
+    ),
+>;
+
 pub type NetworkStack = smoltcp_nal::NetworkStack<
     'static,
     &'static mut stm32_eth::Eth<'static, 'static>,
@@ -15,3 +40,33 @@ pub type NetworkManager = smoltcp_nal::shared::NetworkManager<
     &'static mut stm32_eth::Eth<'static, 'static>,
     network_clock::NetworkClock,
 >;
+
+mod memory {
+    include!(concat!(env!("OUT_DIR"), "/memory.rs"));
+}
+
+/// Sizes set at build time, see `build.rs`.
+pub mod sizes {
+    include!(concat!(env!("OUT_DIR"), "/sizes.rs"));
+}
+
+/// RAM left for the remaining statics, e.g. the other RTIC resources and the logger.
+const RAM_RESERVED: usize = 48 * 1024;
+
+/// The network users, with the MQTT clients and their buffers.
+type NetworkUsers =
+    crate::net::NetworkUsers<crate::settings::Settings, mqtt_rtic_common::telemetry::Telemetry>;
+
+/// `init` builds the network users on its stack before RTIC moves them to their static.
+const STACK_RESERVED: usize = sizes::STACK_SIZE + core::mem::size_of::<NetworkUsers>();
+
+/// Fail the build when the network buffers don't fit in the RAM of `memory.x`.
+const _: () = assert!(
+    core::mem::size_of::<net::NetStorage>()
+        + core::mem::size_of::<eth::EthStorage>()
+        + core::mem::size_of::<NetworkUsers>()
+        + STACK_RESERVED
+        + RAM_RESERVED
+        <= memory::RAM_SIZE,
+    "Network buffers exceed the RAM budget of memory.x"
+);
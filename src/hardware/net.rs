@@ -1,76 +1,187 @@
+//! Static storage of the IP stack.
+//!
+//! # Design
+//!  Every socket role has its own buffer sizes. Sockets handed out by smoltcp-nal
+//!  (MQTT over TCP, syslog and SNTP over UDP) come from a pool in no particular
+//!  order, so a pool is sized for the largest role drawing from it. Sockets used
+//!  directly through smoltcp (mDNS, ICMP, ICMPv6) are sized exactly.
+//!
+//!  The storage types are generic over their sizes, and the hardware module
+//!  checks at compile time that everything fits in the RAM of `memory.x`. The sizes
+//!  that depend on the deployment are set at build time, see `build.rs`.
+use super::sizes::{MQTT_MESSAGE_SIZE_MAX, NUM_NEIGHBOR_CACHE_ENTRIES};
+pub use mqtt_rtic_common::diagnostics::{NUM_IP_ADDRESSES, NUM_ROUTING_TABLE_ENTRIES};
 use smoltcp::{
     iface::{Neighbor, Route, SocketStorage},
-    socket::UdpPacketMetadata,
-    wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr},
+    socket::{IcmpPacketMetadata, RawPacketMetadata, UdpPacketMetadata},
+    wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr},
 };
 
-const NUM_TCP_SOCKETS: usize = 4;
-const NUM_UDP_SOCKETS: usize = 1;
-const NUM_SOCKETS: usize = NUM_UDP_SOCKETS + NUM_TCP_SOCKETS;
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// The MQTT session shared by settings and telemetry.
+const MQTT_SOCKETS: usize = 1;
+/// A full MQTT message, its fixed and variable headers, and a second message in flight.
+const MQTT_RX_BUFFER_SIZE: usize = 2 * (MQTT_MESSAGE_SIZE_MAX + 128);
+const MQTT_TX_BUFFER_SIZE: usize = 2 * (MQTT_MESSAGE_SIZE_MAX + 128);
+
+/// Syslog sends a burst of up to four messages per poll and receives nothing.
+const SYSLOG_RX_BUFFER_SIZE: usize = 64;
+const SYSLOG_TX_BUFFER_SIZE: usize = 4 * 320;
+const SYSLOG_METADATA_COUNT: usize = 4;
+
+/// SNTP has a single 48-byte request or response outstanding.
+const SNTP_RX_BUFFER_SIZE: usize = 128;
+const SNTP_TX_BUFFER_SIZE: usize = 64;
+const SNTP_METADATA_COUNT: usize = 2;
+
+/// mDNS receives queries from every host on the link.
+const MDNS_RX_BUFFER_SIZE: usize = 1024;
+const MDNS_TX_BUFFER_SIZE: usize = 1024;
+const MDNS_METADATA_COUNT: usize = 4;
+
+/// Echo requests and replies of the ping monitor, with an 8-byte payload.
+const ICMP_RX_BUFFER_SIZE: usize = 256;
+const ICMP_TX_BUFFER_SIZE: usize = 256;
+const ICMP_METADATA_COUNT: usize = 4;
 
-const UDP_RX_SOCKET_BUFFER_SIZE: usize = 512;
-const UDP_TX_SOCKET_BUFFER_SIZE: usize = 512;
-const UDP_SOCKET_METADATA_COUNT: usize = 10;
+/// Router advertisements and solicitations, along with any other ICMPv6 traffic.
+const ICMPV6_RX_BUFFER_SIZE: usize = 512;
+const ICMPV6_TX_BUFFER_SIZE: usize = 128;
+const ICMPV6_METADATA_COUNT: usize = 4;
 
-const TCP_RX_SOCKET_BUFFER_SIZE: usize = 512;
-const TCP_TX_SOCKET_BUFFER_SIZE: usize = 512;
+const NUM_TCP_SOCKETS: usize = MQTT_SOCKETS;
+/// Syslog and SNTP.
+const NUM_UDP_SOCKETS: usize = 2;
+/// Sockets used directly through smoltcp rather than the embedded-nal stack (mDNS, ICMP,
+/// ICMPv6 router discovery).
+const NUM_DEDICATED_SOCKETS: usize = 3;
+const NUM_SOCKETS: usize = NUM_UDP_SOCKETS + NUM_TCP_SOCKETS + NUM_DEDICATED_SOCKETS;
 
-const NUM_NEIGHBOR_CACHE_ENTRIES: usize = 8;
-const NUM_ROUTING_TABLE_ENTRIES: usize = 8;
+pub type TcpPoolSocketStorage = TcpSocketStorage<MQTT_RX_BUFFER_SIZE, MQTT_TX_BUFFER_SIZE>;
+pub type UdpPoolSocketStorage = UdpSocketStorage<
+    { max(SYSLOG_RX_BUFFER_SIZE, SNTP_RX_BUFFER_SIZE) },
+    { max(SYSLOG_TX_BUFFER_SIZE, SNTP_TX_BUFFER_SIZE) },
+    { max(SYSLOG_METADATA_COUNT, SNTP_METADATA_COUNT) },
+>;
+pub type MdnsSocketStorage =
+    UdpSocketStorage<MDNS_RX_BUFFER_SIZE, MDNS_TX_BUFFER_SIZE, MDNS_METADATA_COUNT>;
+pub type PingSocketStorage =
+    IcmpSocketStorage<ICMP_RX_BUFFER_SIZE, ICMP_TX_BUFFER_SIZE, ICMP_METADATA_COUNT>;
+pub type RouterDiscoverySocketStorage =
+    RawSocketStorage<ICMPV6_RX_BUFFER_SIZE, ICMPV6_TX_BUFFER_SIZE, ICMPV6_METADATA_COUNT>;
+
+const NUM_IPV4_MULTICAST_GROUPS: usize = 4;
 
 pub struct NetStorage {
-    pub ip_addrs: [IpCidr; 1],
+    pub ip_addrs: [IpCidr; NUM_IP_ADDRESSES],
     pub sockets: [SocketStorage<'static>; NUM_SOCKETS],
-    pub tcp_socket_storage: [TcpSocketStorage; NUM_TCP_SOCKETS],
-    pub udp_socket_storage: [UdpSocketStorage; NUM_UDP_SOCKETS],
+    pub tcp_socket_storage: [TcpPoolSocketStorage; NUM_TCP_SOCKETS],
+    pub udp_socket_storage: [UdpPoolSocketStorage; NUM_UDP_SOCKETS],
+    pub mdns_socket_storage: MdnsSocketStorage,
+    pub icmp_socket_storage: PingSocketStorage,
+    pub raw_socket_storage: RouterDiscoverySocketStorage,
     pub neighbor_cache: [Option<(IpAddress, Neighbor)>; NUM_NEIGHBOR_CACHE_ENTRIES],
     pub routes_cache: [Option<(IpCidr, Route)>; NUM_ROUTING_TABLE_ENTRIES],
+    pub ipv4_multicast_groups: [Option<(Ipv4Address, ())>; NUM_IPV4_MULTICAST_GROUPS],
 }
 
 impl NetStorage {
     pub const fn new() -> Self {
         Self {
-            // NOTE: IP address set at runtime
-            ip_addrs: [IpCidr::Ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 24)); 1],
+            // NOTE: IP addresses set at runtime
+            ip_addrs: [
+                IpCidr::Ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 24)),
+                IpCidr::Ipv6(Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0)),
+                IpCidr::Ipv6(Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0)),
+            ],
             sockets: [SocketStorage::EMPTY; NUM_SOCKETS],
-            tcp_socket_storage: [TcpSocketStorage::INIT; NUM_TCP_SOCKETS],
-            udp_socket_storage: [UdpSocketStorage::new(); NUM_UDP_SOCKETS],
+            tcp_socket_storage: [TcpPoolSocketStorage::INIT; NUM_TCP_SOCKETS],
+            udp_socket_storage: [UdpPoolSocketStorage::INIT; NUM_UDP_SOCKETS],
+            mdns_socket_storage: MdnsSocketStorage::new(),
+            icmp_socket_storage: PingSocketStorage::new(),
+            raw_socket_storage: RouterDiscoverySocketStorage::new(),
             neighbor_cache: [None; NUM_NEIGHBOR_CACHE_ENTRIES],
             routes_cache: [None; NUM_ROUTING_TABLE_ENTRIES],
+            ipv4_multicast_groups: [None; NUM_IPV4_MULTICAST_GROUPS],
+        }
+    }
+}
+
+pub struct UdpSocketStorage<const RX: usize, const TX: usize, const METADATA: usize> {
+    pub rx_storage: [u8; RX],
+    pub tx_storage: [u8; TX],
+    pub rx_metadata: [UdpPacketMetadata; METADATA],
+    pub tx_metadata: [UdpPacketMetadata; METADATA],
+}
+
+impl<const RX: usize, const TX: usize, const METADATA: usize> UdpSocketStorage<RX, TX, METADATA> {
+    const INIT: Self = Self::new();
+
+    const fn new() -> Self {
+        Self {
+            rx_storage: [0; RX],
+            tx_storage: [0; TX],
+            rx_metadata: [UdpPacketMetadata::EMPTY; METADATA],
+            tx_metadata: [UdpPacketMetadata::EMPTY; METADATA],
+        }
+    }
+}
+
+pub struct IcmpSocketStorage<const RX: usize, const TX: usize, const METADATA: usize> {
+    pub rx_storage: [u8; RX],
+    pub tx_storage: [u8; TX],
+    pub rx_metadata: [IcmpPacketMetadata; METADATA],
+    pub tx_metadata: [IcmpPacketMetadata; METADATA],
+}
+
+impl<const RX: usize, const TX: usize, const METADATA: usize> IcmpSocketStorage<RX, TX, METADATA> {
+    const fn new() -> Self {
+        Self {
+            rx_storage: [0; RX],
+            tx_storage: [0; TX],
+            rx_metadata: [IcmpPacketMetadata::EMPTY; METADATA],
+            tx_metadata: [IcmpPacketMetadata::EMPTY; METADATA],
         }
     }
 }
 
-pub struct UdpSocketStorage {
-    pub rx_storage: [u8; UDP_RX_SOCKET_BUFFER_SIZE],
-    pub tx_storage: [u8; UDP_TX_SOCKET_BUFFER_SIZE],
-    pub rx_metadata: [UdpPacketMetadata; UDP_SOCKET_METADATA_COUNT],
-    pub tx_metadata: [UdpPacketMetadata; UDP_SOCKET_METADATA_COUNT],
+pub struct RawSocketStorage<const RX: usize, const TX: usize, const METADATA: usize> {
+    pub rx_storage: [u8; RX],
+    pub tx_storage: [u8; TX],
+    pub rx_metadata: [RawPacketMetadata; METADATA],
+    pub tx_metadata: [RawPacketMetadata; METADATA],
 }
 
-impl UdpSocketStorage {
+impl<const RX: usize, const TX: usize, const METADATA: usize> RawSocketStorage<RX, TX, METADATA> {
     const fn new() -> Self {
         Self {
-            rx_storage: [0; UDP_RX_SOCKET_BUFFER_SIZE],
-            tx_storage: [0; UDP_TX_SOCKET_BUFFER_SIZE],
-            rx_metadata: [UdpPacketMetadata::EMPTY; UDP_SOCKET_METADATA_COUNT],
-            tx_metadata: [UdpPacketMetadata::EMPTY; UDP_SOCKET_METADATA_COUNT],
+            rx_storage: [0; RX],
+            tx_storage: [0; TX],
+            rx_metadata: [RawPacketMetadata::EMPTY; METADATA],
+            tx_metadata: [RawPacketMetadata::EMPTY; METADATA],
         }
     }
 }
 
-pub struct TcpSocketStorage {
-    pub rx_storage: [u8; TCP_RX_SOCKET_BUFFER_SIZE],
-    pub tx_storage: [u8; TCP_TX_SOCKET_BUFFER_SIZE],
+pub struct TcpSocketStorage<const RX: usize, const TX: usize> {
+    pub rx_storage: [u8; RX],
+    pub tx_storage: [u8; TX],
 }
 
-impl TcpSocketStorage {
+impl<const RX: usize, const TX: usize> TcpSocketStorage<RX, TX> {
     const INIT: Self = Self::new();
 
     const fn new() -> Self {
         Self {
-            rx_storage: [0; TCP_RX_SOCKET_BUFFER_SIZE],
-            tx_storage: [0; TCP_TX_SOCKET_BUFFER_SIZE],
+            rx_storage: [0; RX],
+            tx_storage: [0; TX],
         }
     }
 }
@@ -12,6 +12,11 @@ impl NetworkClock {
     pub fn new(now: fn() -> u64) -> Self {
         Self(now)
     }
+
+    /// Milliseconds since boot, without the 32-bit wrap of `Clock::T`.
+    pub fn now_ms(&self) -> u64 {
+        (self.0)()
+    }
 }
 
 impl Clock for NetworkClock {
@@ -0,0 +1,176 @@
+//! Debounced digital inputs.
+//!
+//! # Design
+//!  Every edge of an input raises its EXTI interrupt, which only clears the pending bit,
+//!  records the time of the first edge and (re)schedules the debounce. Once an input
+//!  hasn't bounced for `DEBOUNCE_MS` its level is sampled, and a level that differs from
+//!  the previous debounced state is a change. A pulse shorter than the debounce time is
+//!  ignored.
+//!
+//!  Changes are published as events, timestamped with the first edge, and the debounced
+//!  states are reported in telemetry.
+use core::str::FromStr;
+use heapless::Vec;
+use log::warn;
+pub use mqtt_rtic_common::telemetry::{InputEvent, InputTelemetry, DIGITAL_INPUTS_MAX};
+use stm32f4xx_hal::{
+    gpio::{Edge, ErasedPin, ExtiPin, Input, PC13},
+    pac::EXTI,
+    syscfg::SysCfg,
+};
+
+/// Longest name of an input, the last level of its event topic.
+pub const INPUT_NAME_MAX: usize = 16;
+
+/// Time an input must be stable before its level is accepted.
+pub const DEBOUNCE_MS: u32 = 20;
+
+/// The pins usable as digital inputs, named after the pin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputPin {
+    /// The user button of the Nucleo board, high while pressed.
+    Pc13,
+}
+
+impl FromStr for InputPin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "PC13" => Ok(InputPin::Pc13),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A digital input and the name of its events.
+#[derive(Copy, Clone, Debug)]
+pub struct InputConfig {
+    pub pin: InputPin,
+    pub name: &'static str,
+}
+
+pub struct InputPins {
+    pub pc13: PC13<Input>,
+}
+
+/// A debounced change of an input.
+#[derive(Copy, Clone, Debug)]
+pub struct InputChange {
+    pub name: &'static str,
+    pub state: bool,
+    /// Monotonic time of the first edge, in milliseconds.
+    pub edge_ms: u64,
+}
+
+struct DigitalInput {
+    name: &'static str,
+    pin: ErasedPin<Input>,
+    state: bool,
+    /// Monotonic time of the first edge since the input was last debounced.
+    edge_ms: Option<u64>,
+}
+
+pub struct DigitalInputs {
+    inputs: Vec<DigitalInput, DIGITAL_INPUTS_MAX>,
+    changes: u32,
+}
+
+impl DigitalInputs {
+    /// Configure the inputs and enable their interrupts on both edges.
+    ///
+    /// # Args
+    /// * `pins` - The digital input pins.
+    /// * `config` - The inputs to use, each pin at most once.
+    /// * `syscfg` - Routes the pins to their EXTI lines.
+    /// * `exti` - The external interrupt controller.
+    pub fn new(
+        pins: InputPins,
+        config: &[InputConfig],
+        syscfg: &mut SysCfg,
+        exti: &mut EXTI,
+    ) -> Self {
+        let mut pc13 = Some(pins.pc13);
+        let mut inputs = Vec::new();
+        for input in config {
+            let pin = match input.pin {
+                InputPin::Pc13 => pc13.take().map(|pin| pin.erase()),
+            };
+            let mut pin = match pin {
+                Some(pin) => pin,
+                None => {
+                    warn!("Input `{}` reuses {:?}", input.name, input.pin);
+                    continue;
+                }
+            };
+            pin.make_interrupt_source(syscfg);
+            pin.trigger_on_edge(exti, Edge::RisingFalling);
+            pin.enable_interrupt(exti);
+            let state = pin.is_high();
+            // Note(unwrap): The configuration holds at most `DIGITAL_INPUTS_MAX` inputs
+            inputs
+                .push(DigitalInput {
+                    name: input.name,
+                    pin,
+                    state,
+                    edge_ms: None,
+                })
+                .ok()
+                .unwrap();
+        }
+
+        Self { inputs, changes: 0 }
+    }
+
+    /// Clear the pending interrupts of the inputs.
+    ///
+    /// # Args
+    /// * `now_ms` - The monotonic time of the interrupt.
+    pub fn on_interrupt(&mut self, now_ms: u64) {
+        for input in self.inputs.iter_mut() {
+            if input.pin.check_interrupt() {
+                input.pin.clear_interrupt_pending_bit();
+                input.edge_ms.get_or_insert(now_ms);
+            }
+        }
+    }
+
+    /// Sample the inputs once they stopped bouncing.
+    ///
+    /// # Returns
+    /// The inputs whose level changed since they were last debounced.
+    pub fn debounce(&mut self) -> Vec<InputChange, DIGITAL_INPUTS_MAX> {
+        let mut changes = Vec::new();
+        for input in self.inputs.iter_mut() {
+            let edge_ms = match input.edge_ms.take() {
+                Some(edge_ms) => edge_ms,
+                None => continue,
+            };
+            let state = input.pin.is_high();
+            if state != input.state {
+                input.state = state;
+                self.changes = self.changes.wrapping_add(1);
+                // Note(unwrap): There is at most one change per input
+                changes
+                    .push(InputChange {
+                        name: input.name,
+                        state,
+                        edge_ms,
+                    })
+                    .unwrap();
+            }
+        }
+        changes
+    }
+
+    pub fn telemetry(&self) -> InputTelemetry {
+        let mut states = [None; DIGITAL_INPUTS_MAX];
+        for (state, input) in states.iter_mut().zip(self.inputs.iter()) {
+            *state = Some(input.state);
+        }
+        InputTelemetry {
+            states,
+            changes: self.changes,
+        }
+    }
+}
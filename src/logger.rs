@@ -0,0 +1,202 @@
+//! RTT logger with a runtime adjustable global level and per-module overrides.
+//!
+//! # Design
+//!  The level filter lives in a critical-section protected cell so that it can be
+//!  updated from `settings_update` while any other task or interrupt is logging.
+//!  `log::set_max_level` is kept at the most verbose level in use so that the
+//!  `log` macros let records through to `Logger::enabled` for the final decision.
+//!
+//!  Once a remote sink (syslog) is enabled, every record that passes the filter is
+//!  also copied into a bounded queue which the network task drains. Records that
+//!  arrive while the queue is full are dropped and counted.
+use crate::settings::LogSettings;
+use crate::time::WALL_CLOCK;
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use cortex_m::interrupt::{self, Mutex};
+use heapless::{Deque, String};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rtt_logger::RTTLogger;
+
+pub use mqtt_rtic_common::settings::{MODULE_NAME_MAX, MODULE_OVERRIDES_MAX};
+
+const REMOTE_QUEUE_SIZE: usize = 16;
+
+const CRATE_PREFIX: &str = concat!(env!("CARGO_CRATE_NAME"), "::");
+
+pub static LOGGER: Logger = Logger::new();
+
+#[derive(Clone)]
+struct ModuleFilter {
+    module: String<MODULE_NAME_MAX>,
+    level: LevelFilter,
+}
+
+impl ModuleFilter {
+    const INIT: Option<Self> = None;
+}
+
+struct Filter {
+    level: LevelFilter,
+    modules: [Option<ModuleFilter>; MODULE_OVERRIDES_MAX],
+}
+
+impl Filter {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+        self.modules
+            .iter()
+            .flatten()
+            .filter(|m| is_module_match(target, &m.module))
+            .max_by_key(|m| m.module.len())
+            .map(|m| m.level)
+            .unwrap_or(self.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .flatten()
+            .map(|m| m.level)
+            .fold(self.level, core::cmp::max)
+    }
+}
+
+/// A log record queued for a remote sink.
+pub struct LogEntry {
+    /// UTC time of the record in milliseconds since the Unix epoch, if synchronized.
+    pub timestamp: Option<u64>,
+    pub level: Level,
+    pub target: String<32>,
+    pub message: String<128>,
+}
+
+impl LogEntry {
+    fn new(record: &Record) -> Self {
+        let mut target = String::new();
+        for c in record.target().chars() {
+            if target.push(c).is_err() {
+                break;
+            }
+        }
+        // Note(ok): Messages longer than the entry capacity are truncated
+        let mut message = String::new();
+        write!(&mut message, "{}", record.args()).ok();
+        Self {
+            timestamp: WALL_CLOCK.now_utc_ms(),
+            level: record.level(),
+            target,
+            message,
+        }
+    }
+}
+
+pub struct Logger {
+    sink: RTTLogger,
+    filter: Mutex<RefCell<Filter>>,
+    remote_enabled: AtomicBool,
+    remote_queue: Mutex<RefCell<Deque<LogEntry, REMOTE_QUEUE_SIZE>>>,
+    remote_dropped: AtomicU32,
+}
+
+impl Logger {
+    const fn new() -> Self {
+        Self {
+            sink: RTTLogger::new(LevelFilter::Trace),
+            filter: Mutex::new(RefCell::new(Filter {
+                level: LevelFilter::Trace,
+                modules: [ModuleFilter::INIT; MODULE_OVERRIDES_MAX],
+            })),
+            remote_enabled: AtomicBool::new(false),
+            remote_queue: Mutex::new(RefCell::new(Deque::new())),
+            remote_dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// Install the logger. Must be called once, after RTT has been initialized.
+    pub fn init(&'static self) {
+        log::set_logger(self)
+            .map(|()| log::set_max_level(LevelFilter::Trace))
+            .unwrap();
+    }
+
+    /// Apply the logging settings.
+    ///
+    /// # Args
+    /// * `settings` - The global level and module overrides to use from now on.
+    pub fn configure(&self, settings: &LogSettings) {
+        let max_level = interrupt::free(|cs| {
+            let mut filter = self.filter.borrow(cs).borrow_mut();
+            filter.level = settings.level.into();
+            for (slot, m) in filter.modules.iter_mut().zip(settings.modules.iter()) {
+                *slot = if m.module.is_empty() {
+                    None
+                } else {
+                    Some(ModuleFilter {
+                        module: m.module.clone(),
+                        level: m.level.into(),
+                    })
+                };
+            }
+            filter.max_level()
+        });
+        log::set_max_level(max_level);
+    }
+
+    /// Start copying records into the remote sink queue.
+    pub fn enable_remote(&self) {
+        self.remote_enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Take the oldest record from the remote sink queue.
+    pub fn pop_remote(&self) -> Option<LogEntry> {
+        interrupt::free(|cs| self.remote_queue.borrow(cs).borrow_mut().pop_front())
+    }
+
+    /// Number of records dropped because the remote sink queue was full.
+    pub fn remote_dropped(&self) -> u32 {
+        self.remote_dropped.load(Ordering::Relaxed)
+    }
+
+    fn push_remote(&self, record: &Record) {
+        let entry = LogEntry::new(record);
+        let full = interrupt::free(|cs| {
+            self.remote_queue
+                .borrow(cs)
+                .borrow_mut()
+                .push_back(entry)
+                .is_err()
+        });
+        if full {
+            self.remote_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level =
+            interrupt::free(|cs| self.filter.borrow(cs).borrow().level_for(metadata.target()));
+        metadata.level() <= level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.sink.log(record);
+            if self.remote_enabled.load(Ordering::Relaxed) {
+                self.push_remote(record);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Returns true if `target` is `module` or one of its submodules.
+fn is_module_match(target: &str, module: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
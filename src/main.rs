@@ -10,11 +10,18 @@
 //use panic_abort as _; // panic handler
 use panic_rtt_target as _; // panic handler
 
+mod analog;
 mod config;
 mod hardware;
+mod inputs;
+mod logger;
 mod net;
+mod outputs;
+mod pwm;
+mod sensors;
 mod settings;
-mod telemetry;
+mod time;
+mod watchdog;
 
 mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -24,48 +31,85 @@ mod built_info {
 mod app {
     use crate::built_info;
     use crate::hardware::{
+        adc::{AdcBuffer, AnalogPins, AnalogSampling, Overrun, ADC_BUFFER_LEN},
         eth::EthStorage,
-        gpio::{LedBluePin, LedGreenPin, LedRedPin},
+        gpio::{I2cSclPin, I2cSdaPin, LedBluePin, LedGreenPin, LedRedPin},
         net::NetStorage,
         network_clock::NetworkClock,
         phy::Phy,
-        NetworkManager, NetworkStack,
+        I2cBus, NetworkManager, NetworkStack, SharedI2c,
     };
     use crate::{
+        analog::AnalogAverages,
         config::Config,
-        net::{NetworkState, NetworkUsers},
+        inputs::{DigitalInputs, InputEvent, InputPins, DEBOUNCE_MS},
+        logger::LOGGER,
+        net::{
+            connection::ConnectionState,
+            poll_stats::PollStatistics,
+            slaac::{self, IPV4_ADDRESS_INDEX, IPV6_PREFERRED_ADDRESS_INDEX},
+            DedicatedSockets, NetworkState, NetworkUsers,
+        },
+        outputs::{DigitalOutputs, OutputPins},
+        pwm::{PwmOutputs, PWM_DEFAULT_FREQUENCY_HZ, RAMP_INTERVAL_MS},
+        sensors::{
+            sht3x::{self, Sht3x},
+            SensorScheduler,
+        },
         settings::Settings,
-        telemetry::Telemetry,
+        time::WALL_CLOCK,
+        watchdog::{take_reset_cause, ConnectivityWatchdog, Escalation, EscalationRecord},
     };
+    use core::fmt::Write;
+    use heapless::String;
     use log::info;
+    use mqtt_rtic_common::{
+        status::{LedStates, StatusIndicator, SystemState, SystemStatus, TICK_MS},
+        telemetry::Telemetry,
+    };
     use rand_core::RngCore;
-    use rtt_logger::RTTLogger;
     use rtt_target::rtt_init_print;
     use smoltcp::{
-        iface::{InterfaceBuilder, NeighborCache, Routes},
-        socket::{TcpSocket, TcpSocketBuffer, UdpSocket, UdpSocketBuffer},
-        wire::{IpCidr, Ipv4Address, Ipv4Cidr},
+        iface::{InterfaceBuilder, NeighborCache, Route, Routes},
+        socket::{
+            IcmpSocket, IcmpSocketBuffer, RawSocket, RawSocketBuffer, TcpSocket, TcpSocketBuffer,
+            UdpSocket, UdpSocketBuffer,
+        },
+        wire::{IpCidr, IpProtocol, IpVersion, Ipv4Cidr},
     };
     use stm32_eth::{Eth, EthPins, FilterMode};
-    use stm32f4xx_hal::{gpio::Speed, prelude::*, time::Hertz};
+    use stm32f4xx_hal::{gpio::Speed, i2c::I2c, prelude::*, time::Hertz};
     use systick_monotonic::{ExtU64, Systick};
 
     const SYS_CLOCK_FREQ: Hertz = Hertz::MHz(180);
 
-    static LOGGER: RTTLogger = RTTLogger::new(log::LevelFilter::Trace);
+    /// Upper bound on the time between IP stack polls. The stack itself is polled
+    /// as smoltcp requests, the network users (MQTT keepalive, SNTP, mDNS, ping)
+    /// run their timers from the same poll.
+    const POLL_INTERVAL_MAX_MS: u64 = 100;
 
     #[shared]
     struct Shared {
         net: NetworkUsers<Settings, Telemetry>,
         settings: Settings,
         telemetry: Telemetry,
+        poll_handle: Option<poll_ip_stack::SpawnHandle>,
+        poll_stats: PollStatistics,
+        analog: AnalogAverages,
+        inputs: DigitalInputs,
+        outputs: DigitalOutputs,
+        pwm: PwmOutputs,
+        status: SystemStatus,
     }
 
     #[local]
     struct Local {
-        led_r: LedRedPin,
-        activity_led: LedBluePin,
-        link_led: LedGreenPin,
+        led_green: LedGreenPin,
+        led_blue: LedBluePin,
+        led_red: LedRedPin,
+        sensors: SensorScheduler,
+        analog_sampling: Option<AnalogSampling>,
+        watchdog: ConnectivityWatchdog,
     }
 
     #[monotonic(binds = SysTick, default = true)]
@@ -76,12 +120,12 @@ mod app {
         net_storage: NetStorage = NetStorage::new(),
         eth: Option<Eth<'static, 'static>> = None,
         net_stack_manager: Option<NetworkManager> = None,
+        sht3x: Option<Sht3x<SharedI2c>> = None,
+        adc_buffers: [AdcBuffer; 3] = [[0; ADC_BUFFER_LEN]; 3],
     ])]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
         rtt_init_print!();
-        log::set_logger(&LOGGER)
-            .map(|()| log::set_max_level(log::LevelFilter::Trace))
-            .unwrap();
+        LOGGER.init();
 
         info!(
             "{} version {}",
@@ -96,6 +140,10 @@ mod app {
         // Set up the system clock
         // HCLK must be at least 25MHz to use the ethernet peripheral
         // The RNG requires the PLL48_CLK to be active
+        let reset_cause = take_reset_cause(&ctx.device.RCC);
+        info!("Reset cause: {:?}", reset_cause);
+        let escalation_record =
+            EscalationRecord::new(&ctx.device.RCC, &ctx.device.PWR, ctx.device.RTC);
         let rcc = ctx.device.RCC.constrain();
         let clocks = rcc
             .cfgr
@@ -112,12 +160,93 @@ mod app {
         let gpioc = ctx.device.GPIOC.split();
         let gpiog = ctx.device.GPIOG.split();
 
-        let mut link_led = gpiob.pb0.into_push_pull_output();
-        let mut activity_led = gpiob.pb7.into_push_pull_output();
-        let mut led_r = gpiob.pb14.into_push_pull_output();
-        link_led.set_low();
-        activity_led.set_low();
-        led_r.set_low();
+        let mut led_green = gpiob.pb0.into_push_pull_output();
+        let mut led_blue = gpiob.pb7.into_push_pull_output();
+        let mut led_red = gpiob.pb14.into_push_pull_output();
+        // The status indicator only runs once init is done
+        set_leds(
+            &mut led_green,
+            &mut led_blue,
+            &mut led_red,
+            SystemState::Booting.pattern().at(0),
+        );
+
+        info!("Setup I2C");
+        let i2c_scl: I2cSclPin = gpiob.pb8.into_alternate_open_drain();
+        let i2c_sda: I2cSdaPin = gpiob.pb9.into_alternate_open_drain();
+        let i2c = I2c::new(
+            ctx.device.I2C1,
+            (i2c_scl, i2c_sda),
+            Hertz::kHz(100),
+            &clocks,
+        );
+        let i2c_bus = shared_bus_rtic::new!(i2c, I2cBus);
+        let mut sensors = SensorScheduler::default();
+        sensors.add(
+            ctx.local
+                .sht3x
+                .insert(Sht3x::new(i2c_bus.acquire(), sht3x::DEFAULT_ADDRESS)),
+        );
+
+        info!("Setup ADC");
+        let analog_pins = AnalogPins {
+            pa3: gpioa.pa3.into_analog(),
+            pc0: gpioc.pc0.into_analog(),
+            pc3: gpioc.pc3.into_analog(),
+            pb1: gpiob.pb1.into_analog(),
+        };
+        let [adc_buffer_0, adc_buffer_1, adc_buffer_2] = ctx.local.adc_buffers;
+        let analog_sampling = if config.analog_inputs.is_empty() {
+            None
+        } else {
+            Some(AnalogSampling::new(
+                ctx.device.ADC1,
+                ctx.device.TIM2,
+                ctx.device.DMA2,
+                analog_pins,
+                &config.analog_inputs,
+                &clocks,
+                [adc_buffer_0, adc_buffer_1, adc_buffer_2],
+            ))
+        };
+        let analog = AnalogAverages::new(
+            config.analog_inputs.len(),
+            analog_sampling.as_ref().map_or(0, |s| s.reference_mv()),
+        );
+
+        info!("Setup inputs");
+        let mut syscfg = ctx.device.SYSCFG.constrain();
+        let mut exti = ctx.device.EXTI;
+        let input_pins = InputPins {
+            pc13: gpioc.pc13.into_floating_input(),
+        };
+        let inputs = DigitalInputs::new(input_pins, &config.digital_inputs, &mut syscfg, &mut exti);
+
+        info!("Setup outputs");
+        let output_pins = OutputPins {
+            pg0: gpiog.pg0,
+            pg1: gpiog.pg1,
+            pg2: gpiog.pg2,
+            pg3: gpiog.pg3,
+        };
+        let outputs = DigitalOutputs::new(
+            output_pins,
+            &config.digital_outputs,
+            config.output_fail_safe_timeout_secs,
+        );
+
+        info!("Setup PWM");
+        let pwm_pins = (
+            gpioc.pc6.into_alternate(),
+            gpioc.pc7.into_alternate(),
+            gpioc.pc8.into_alternate(),
+            gpioc.pc9.into_alternate(),
+        );
+        let pwm = PwmOutputs::new(ctx.device.TIM3.pwm_hz(
+            pwm_pins,
+            Hertz::Hz(PWM_DEFAULT_FREQUENCY_HZ),
+            &clocks,
+        ));
 
         info!("Setup Ethernet");
         let mut mdio_pin = gpioa.pa2.into_alternate().set_speed(Speed::VeryHigh);
@@ -133,14 +262,26 @@ mod app {
             rx_d1: gpioc.pc5,
         };
 
+        #[cfg(feature = "mac-hash-filter")]
+        let filter = {
+            use crate::hardware::eth::{multicast_groups, multicast_hash_table};
+            FilterMode::FilterDestHashMulticast(
+                config.mac_address.0,
+                multicast_hash_table(&multicast_groups(config.mac_address)),
+            )
+        };
+        // FilterDest only passes our unicast address, which drops the multicast traffic
+        // mDNS and IPv6 neighbor/router discovery rely on. smoltcp discards frames not
+        // addressed to us instead.
+        #[cfg(not(feature = "mac-hash-filter"))]
+        let filter = FilterMode::Promiscuous;
         let mut eth = Eth::new(
             ctx.device.ETHERNET_MAC,
             ctx.device.ETHERNET_DMA,
             ctx.device.ETHERNET_MMC,
             &mut ctx.local.eth_storage.rx_ring[..],
             &mut ctx.local.eth_storage.tx_ring[..],
-            FilterMode::FilterDest(config.mac_address.0),
-            //FilterMode::Promiscuous,
+            filter,
             clocks,
             eth_pins,
         )
@@ -160,12 +301,26 @@ mod app {
         ctx.local.eth.replace(eth);
 
         info!("Setup TCP/IP");
-        ctx.local.net_storage.ip_addrs[0] = IpCidr::Ipv4(Ipv4Cidr::new(config.ip_address, 24));
+        ctx.local.net_storage.ip_addrs[IPV4_ADDRESS_INDEX] =
+            IpCidr::Ipv4(Ipv4Cidr::new(config.ip_address, config.prefix_len));
+        ctx.local.net_storage.ip_addrs[IPV6_PREFERRED_ADDRESS_INDEX] =
+            IpCidr::Ipv6(slaac::link_local_address(config.mac_address));
         let neighbor_cache = NeighborCache::new(&mut ctx.local.net_storage.neighbor_cache[..]);
         let mut routes = Routes::new(&mut ctx.local.net_storage.routes_cache[..]);
-        routes
-            .add_default_ipv4_route(Ipv4Address::UNSPECIFIED)
-            .unwrap();
+        if let Some(gateway) = config.gateway {
+            routes.add_default_ipv4_route(gateway).unwrap();
+        }
+        routes.update(|table| {
+            for route in config.routes.iter() {
+                // Note(unwrap): The routing table has room for every static route
+                table
+                    .insert(
+                        IpCidr::Ipv4(route.destination),
+                        Route::new_ipv4_gateway(route.via),
+                    )
+                    .unwrap();
+            }
+        });
         let mut eth_iface = InterfaceBuilder::new(
             ctx.local.eth.as_mut().unwrap(),
             &mut ctx.local.net_storage.sockets[..],
@@ -174,6 +329,7 @@ mod app {
         .ip_addrs(&mut ctx.local.net_storage.ip_addrs[..])
         .neighbor_cache(neighbor_cache)
         .routes(routes)
+        .ipv4_multicast_groups(&mut ctx.local.net_storage.ipv4_multicast_groups[..])
         .finalize();
 
         for storage in ctx.local.net_storage.tcp_socket_storage[..].iter_mut() {
@@ -201,30 +357,86 @@ mod app {
         }
 
         info!("Setup SysTick");
+        let mut dcb = ctx.core.DCB;
+        let mut dwt = ctx.core.DWT;
+        dcb.enable_trace();
+        dwt.enable_cycle_counter();
         let systick = ctx.core.SYST;
         let mono = Systick::new(systick, clocks.sysclk().raw());
         let net_clock = NetworkClock::new(|| monotonics::now().ticks());
+        WALL_CLOCK.init(|| monotonics::now().ticks());
 
         info!("Setup network");
-        let random_seed = {
+        let (random_seed, backoff_seed) = {
             let mut rng = ctx.device.RNG.constrain(&clocks);
             let mut data = [0u8; 4];
             rng.fill_bytes(&mut data);
-            data
+            (data, rng.next_u32())
         };
         let mut net_stack = NetworkStack::new(eth_iface, net_clock);
         net_stack.seed_random_port(&random_seed);
+        let mdns_socket = {
+            let storage = &mut ctx.local.net_storage.mdns_socket_storage;
+            let rx_buffer =
+                UdpSocketBuffer::new(&mut storage.rx_metadata[..], &mut storage.rx_storage[..]);
+            let tx_buffer =
+                UdpSocketBuffer::new(&mut storage.tx_metadata[..], &mut storage.tx_storage[..]);
+
+            UdpSocket::new(rx_buffer, tx_buffer)
+        };
+        let icmp_socket = {
+            let storage = &mut ctx.local.net_storage.icmp_socket_storage;
+            let rx_buffer =
+                IcmpSocketBuffer::new(&mut storage.rx_metadata[..], &mut storage.rx_storage[..]);
+            let tx_buffer =
+                IcmpSocketBuffer::new(&mut storage.tx_metadata[..], &mut storage.tx_storage[..]);
+
+            IcmpSocket::new(rx_buffer, tx_buffer)
+        };
+        let icmpv6_socket = {
+            let storage = &mut ctx.local.net_storage.raw_socket_storage;
+            let rx_buffer =
+                RawSocketBuffer::new(&mut storage.rx_metadata[..], &mut storage.rx_storage[..]);
+            let tx_buffer =
+                RawSocketBuffer::new(&mut storage.tx_metadata[..], &mut storage.tx_storage[..]);
+
+            RawSocket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, rx_buffer, tx_buffer)
+        };
+        let sockets = DedicatedSockets {
+            mdns: net_stack.interface_mut().add_socket(mdns_socket),
+            icmp: net_stack.interface_mut().add_socket(icmp_socket),
+            icmpv6: net_stack.interface_mut().add_socket(icmpv6_socket),
+        };
         let stack_manager = NetworkManager::new(net_stack);
         ctx.local.net_stack_manager.replace(stack_manager);
-        let net = NetworkUsers::new(
+        let mut net = NetworkUsers::new(
             ctx.local.net_stack_manager.as_mut().unwrap(),
             mdio_pin,
             mdc_pin,
             net_clock,
             env!("CARGO_BIN_NAME"),
-            config.mac_address,
-            minimq::embedded_nal::Ipv4Addr::from(config.broker_ip_address.0).into(),
+            &config,
+            sockets,
         );
+        net.connection.seed_backoff(backoff_seed);
+        if config.syslog.is_some() {
+            LOGGER.enable_remote();
+        }
+        let settings = Settings {
+            outputs: outputs.defaults(),
+            ..Settings::default()
+        };
+        net.settings.set_settings(settings.clone());
+        for (index, name) in outputs.names().enumerate() {
+            let mut alias: String<32> = String::new();
+            let mut path: String<32> = String::new();
+            // Note(unwrap): Output names are at most `OUTPUT_NAME_MAX` bytes
+            write!(&mut alias, "outputs/{}", name).unwrap();
+            write!(&mut path, "outputs/{}", index).unwrap();
+            net.settings.add_alias(&alias, &path);
+        }
+
+        let watchdog = ConnectivityWatchdog::new(config.watchdog, reset_cause, escalation_record);
 
         info!("--- Hardware setup done");
 
@@ -232,72 +444,282 @@ mod app {
         poll_ip_stack::spawn().unwrap();
         settings_update::spawn().unwrap();
         telemetry_task::spawn().unwrap();
+        sample_sensors::spawn().unwrap();
+        output_fail_safe::spawn().unwrap();
+        status_leds::spawn().unwrap();
+        connectivity_watchdog::spawn().unwrap();
 
         (
             Shared {
                 net,
-                settings: Settings::default(),
-                telemetry: Telemetry::default(),
+                settings,
+                telemetry: Telemetry {
+                    inputs: inputs.telemetry(),
+                    watchdog: watchdog.status(),
+                    ..Telemetry::default()
+                },
+                poll_handle: None,
+                poll_stats: PollStatistics::new(SYS_CLOCK_FREQ.raw() / 1_000_000),
+                analog,
+                inputs,
+                outputs,
+                pwm,
+                status: SystemStatus::default(),
             },
             Local {
-                led_r,
-                activity_led,
-                link_led,
+                led_green,
+                led_blue,
+                led_red,
+                sensors,
+                analog_sampling,
+                watchdog,
             },
             init::Monotonics(mono),
         )
     }
 
-    #[task(local = [led_r], shared = [net, settings], priority = 1)]
+    #[task(shared = [net, settings, outputs, status], priority = 1)]
     fn settings_update(ctx: settings_update::Context) {
-        let led = ctx.local.led_r;
         let mut net = ctx.shared.net;
         let mut settings = ctx.shared.settings;
-        let s = net.lock(|n| *n.miniconf.settings());
+        let mut outputs = ctx.shared.outputs;
+        let mut status = ctx.shared.status;
+        let s = net.lock(|n| n.settings.settings().clone());
+        LOGGER.configure(&s.log);
+        status.lock(|status| status.led = s.led);
+        outputs.lock(|outputs| outputs.set(&s.outputs));
+        // Already pending while ramping, picks up the new settings then
+        ramp_pwm::spawn().ok();
         settings.lock(|current| *current = s);
-        led.set_state(s.led.into());
     }
 
-    #[task(shared = [net, telemetry], priority = 1)]
+    #[task(
+        shared = [net, settings, telemetry, poll_handle, poll_stats, analog],
+        priority = 1
+    )]
     fn telemetry_task(ctx: telemetry_task::Context) {
         let mut net = ctx.shared.net;
+        let mut settings = ctx.shared.settings;
         let mut telemetry = ctx.shared.telemetry;
+        let mut poll_handle = ctx.shared.poll_handle;
+        let mut poll_stats = ctx.shared.poll_stats;
+        let mut analog = ctx.shared.analog;
+        let now = monotonics::now().ticks();
         let t: Telemetry = telemetry.lock(|telemetry| {
             telemetry.dummy += 1;
+            telemetry.timestamp = WALL_CLOCK.utc_ms(now);
+            telemetry.time_sync = WALL_CLOCK.status(now);
             *telemetry
         });
-        net.lock(|n| n.telemetry.publish(&t));
+        let t = Telemetry {
+            reachability: net.lock(|n| n.ping.reachability()),
+            connection: net.lock(|n| n.connection.status()),
+            polling: poll_stats.lock(|stats| stats.take()),
+            analog: {
+                let calibration = settings.lock(|settings| settings.analog);
+                analog.lock(|analog| analog.take(&calibration))
+            },
+            ..t
+        };
+        net.lock(|n| n.publish_telemetry(&t));
+        poll_handle.lock(poll_ip_stack_now);
         telemetry_task::spawn_after(1_u64.secs()).unwrap();
     }
 
-    #[task(local = [activity_led], shared = [net], priority = 1)]
+    #[task(local = [sensors], shared = [telemetry, status], priority = 1)]
+    fn sample_sensors(ctx: sample_sensors::Context) {
+        let sensors = ctx.local.sensors;
+        let mut telemetry = ctx.shared.telemetry;
+        let mut status = ctx.shared.status;
+        let delay = telemetry.lock(|telemetry| sensors.update(telemetry));
+        status.lock(|status| status.fault = sensors.is_failing());
+        sample_sensors::spawn_after(u64::from(delay).millis()).unwrap();
+    }
+
+    #[task(
+        binds = EXTI15_10,
+        local = [debounce: Option<debounce_inputs::SpawnHandle> = None],
+        shared = [inputs],
+        priority = 1
+    )]
+    fn on_exti(ctx: on_exti::Context) {
+        let mut inputs = ctx.shared.inputs;
+        inputs.lock(|inputs| inputs.on_interrupt(monotonics::now().ticks()));
+        // Debounce once the inputs stopped bouncing
+        let delay = u64::from(DEBOUNCE_MS).millis();
+        let debounce = ctx.local.debounce;
+        *debounce = match debounce.take().map(|handle| handle.reschedule_after(delay)) {
+            Some(Ok(handle)) => Some(handle),
+            // The debounce already ran or is about to, so debounce again
+            _ => debounce_inputs::spawn_after(delay).ok(),
+        };
+    }
+
+    #[task(shared = [net, telemetry, poll_handle, inputs], priority = 1, capacity = 2)]
+    fn debounce_inputs(ctx: debounce_inputs::Context) {
+        let mut net = ctx.shared.net;
+        let mut telemetry = ctx.shared.telemetry;
+        let mut poll_handle = ctx.shared.poll_handle;
+        let mut inputs = ctx.shared.inputs;
+        let (changes, states) = inputs.lock(|inputs| (inputs.debounce(), inputs.telemetry()));
+        telemetry.lock(|telemetry| telemetry.inputs = states);
+        if changes.is_empty() {
+            return;
+        }
+        for change in changes.iter() {
+            let event = InputEvent {
+                state: change.state,
+                timestamp: WALL_CLOCK.utc_ms(change.edge_ms),
+            };
+            net.lock(|n| n.publish_input_event(change.name, &event));
+        }
+        poll_handle.lock(poll_ip_stack_now);
+    }
+
+    #[task(shared = [settings, pwm], priority = 1)]
+    fn ramp_pwm(ctx: ramp_pwm::Context) {
+        let mut settings = ctx.shared.settings;
+        let mut pwm = ctx.shared.pwm;
+        let s = settings.lock(|settings| settings.pwm);
+        if pwm.lock(|pwm| pwm.update(&s)) {
+            ramp_pwm::spawn_after(u64::from(RAMP_INTERVAL_MS).millis()).ok();
+        }
+    }
+
+    #[task(shared = [net, outputs], priority = 1)]
+    fn output_fail_safe(ctx: output_fail_safe::Context) {
+        let mut net = ctx.shared.net;
+        let mut outputs = ctx.shared.outputs;
+        let connected = net.lock(|n| n.is_connected());
+        let now = monotonics::now().ticks();
+        outputs.lock(|outputs| outputs.update(connected, now));
+        output_fail_safe::spawn_after(1_u64.secs()).unwrap();
+    }
+
+    #[task(shared = [net, poll_handle, poll_stats, status], priority = 1)]
     fn poll_ip_stack(ctx: poll_ip_stack::Context) {
-        let led = ctx.local.activity_led;
         let mut net = ctx.shared.net;
-        match net.lock(|n| n.update()) {
+        let mut poll_handle = ctx.shared.poll_handle;
+        let mut poll_stats = ctx.shared.poll_stats;
+        let mut status = ctx.shared.status;
+        let start = poll_stats.lock(|stats| stats.poll_start());
+        let (state, delay) = net.lock(|n| (n.update(), n.processor.poll_delay_ms()));
+        match state {
             NetworkState::SettingsChanged => settings_update::spawn().unwrap(),
-            NetworkState::Updated => led.toggle(),
+            NetworkState::Updated => status.lock(|status| status.activity = true),
             NetworkState::NoChange => {}
         }
-        poll_ip_stack::spawn_after(10_u64.millis()).unwrap();
+        let delay = delay.map_or(POLL_INTERVAL_MAX_MS, |d| d.min(POLL_INTERVAL_MAX_MS));
+        poll_handle.lock(|handle| {
+            if let Some(scheduled) = handle.take() {
+                scheduled.cancel().ok();
+            }
+            *handle = poll_ip_stack::spawn_after(delay.millis()).ok();
+        });
+        poll_stats.lock(|stats| stats.poll_done(start));
     }
 
-    #[task(local = [link_led], shared = [net], priority = 1)]
+    /// Poll the IP stack as soon as possible instead of waiting for the scheduled poll.
+    fn poll_ip_stack_now(handle: &mut Option<poll_ip_stack::SpawnHandle>) {
+        if let Some(scheduled) = handle.take() {
+            scheduled.cancel().ok();
+        }
+        poll_ip_stack::spawn().ok();
+    }
+
+    #[task(shared = [net, status], priority = 1)]
     fn link_status(ctx: link_status::Context) {
-        let led = ctx.local.link_led;
         let mut net = ctx.shared.net;
+        let mut status = ctx.shared.status;
         let link_status = net.lock(|n| n.processor.handle_link());
-        if link_status {
-            led.set_high();
-        } else {
-            led.set_low();
-        }
+        status.lock(|status| {
+            status.booting = false;
+            status.link = link_status;
+        });
         link_status::spawn_after(1_u64.secs()).unwrap();
     }
 
-    #[task(binds = ETH, shared = [net], priority = 1)]
+    #[task(
+        local = [led_green, led_blue, led_red, indicator: StatusIndicator = StatusIndicator::new()],
+        shared = [net, status],
+        priority = 1
+    )]
+    fn status_leds(ctx: status_leds::Context) {
+        let mut net = ctx.shared.net;
+        let mut status = ctx.shared.status;
+        let connected = net.lock(|n| n.connection.state() >= ConnectionState::MqttConnected);
+        let indicator = ctx.local.indicator;
+        let previous = indicator.state();
+        let leds = status.lock(|status| {
+            status.mqtt = connected;
+            indicator.update(status)
+        });
+        if indicator.state() != previous {
+            info!("Status: {:?}", indicator.state());
+        }
+        set_leds(
+            ctx.local.led_green,
+            ctx.local.led_blue,
+            ctx.local.led_red,
+            leds,
+        );
+        status_leds::spawn_after(u64::from(TICK_MS).millis()).unwrap();
+    }
+
+    #[task(local = [watchdog], shared = [net, telemetry], priority = 1)]
+    fn connectivity_watchdog(ctx: connectivity_watchdog::Context) {
+        let mut net = ctx.shared.net;
+        let mut telemetry = ctx.shared.telemetry;
+        let watchdog = ctx.local.watchdog;
+        let (link, connected) = net.lock(|n| {
+            (
+                n.processor.link_up(),
+                n.connection.state() >= ConnectionState::MqttConnected,
+            )
+        });
+        match watchdog.update(link, connected, monotonics::now().ticks()) {
+            Some(Escalation::StackReset) => net.lock(|n| n.processor.reset_stack()),
+            Some(Escalation::PhyReset) => {
+                let mut delay =
+                    asm_delay::AsmDelay::new(asm_delay::bitrate::Hertz(SYS_CLOCK_FREQ.raw()));
+                net.lock(|n| n.processor.reset_phy(&mut delay));
+            }
+            Some(Escalation::SystemReset) => cortex_m::peripheral::SCB::sys_reset(),
+            None => {}
+        }
+        telemetry.lock(|telemetry| telemetry.watchdog = watchdog.status());
+        connectivity_watchdog::spawn_after(1_u64.secs()).unwrap();
+    }
+
+    fn set_leds(
+        green: &mut LedGreenPin,
+        blue: &mut LedBluePin,
+        red: &mut LedRedPin,
+        leds: LedStates,
+    ) {
+        green.set_state(leds.green.into());
+        blue.set_state(leds.blue.into());
+        red.set_state(leds.red.into());
+    }
+
+    #[task(binds = DMA2_STREAM0, local = [analog_sampling], shared = [analog], priority = 2)]
+    fn on_adc_dma(ctx: on_adc_dma::Context) {
+        let mut analog = ctx.shared.analog;
+        if let Some(sampling) = ctx.local.analog_sampling.as_mut() {
+            match sampling.on_transfer_complete() {
+                Ok(samples) => analog.lock(|analog| analog.process(samples)),
+                Err(Overrun) => analog.lock(|analog| analog.overrun()),
+            }
+        }
+    }
+
+    #[task(binds = ETH, shared = [net, poll_handle, poll_stats], priority = 1)]
     fn on_eth(ctx: on_eth::Context) {
         let mut net = ctx.shared.net;
+        let mut poll_handle = ctx.shared.poll_handle;
+        let mut poll_stats = ctx.shared.poll_stats;
         net.lock(|n| n.processor.handle_interrupt());
+        poll_stats.lock(|stats| stats.interrupt());
+        poll_handle.lock(poll_ip_stack_now);
     }
 }
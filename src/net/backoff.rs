@@ -0,0 +1,131 @@
+//! Randomized exponential backoff of the reconnections to the broker.
+//!
+//! # Design
+//!  minimq reconnects on every poll while the session is down. Instead, reconnection
+//!  attempts are given `ATTEMPT_WINDOW_MS` to establish the session, and after every
+//!  failed attempt the session isn't polled for a delay that doubles from the minimum up
+//!  to the maximum backoff. Half of the delay is random, from a generator seeded by the
+//!  hardware RNG, so devices that lost the broker together don't reconnect together.
+//!
+//!  The first attempt after boot is immediate, the first one after a lost session waits
+//!  for a randomized minimum backoff. Settings and telemetry share the session, so they
+//!  share the backoff.
+use log::{info, warn};
+pub use mqtt_rtic_common::telemetry::ReconnectStats;
+
+/// Time an attempt has to establish the session.
+const ATTEMPT_WINDOW_MS: u64 = 3_000;
+
+/// The limits of the backoff.
+#[derive(Copy, Clone, Debug)]
+pub struct BackoffConfig {
+    /// Delay after the first failure.
+    pub min_ms: u32,
+    /// Upper bound of the delay.
+    pub max_ms: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Phase {
+    Connected,
+    /// Polling the session until the deadline.
+    Attempting(u64),
+    /// Waiting for the next attempt at the deadline.
+    Waiting(u64),
+}
+
+pub struct ReconnectBackoff {
+    config: BackoffConfig,
+    phase: Phase,
+    /// xorshift32 state, never zero.
+    rng: u32,
+    attempts: u32,
+    failures: u32,
+}
+
+impl ReconnectBackoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            phase: Phase::Waiting(0),
+            rng: 1,
+            attempts: 0,
+            failures: 0,
+        }
+    }
+
+    /// Seed the jitter, differently on every device.
+    pub fn seed(&mut self, seed: u32) {
+        self.rng = seed.max(1);
+    }
+
+    /// Whether the session should be polled.
+    ///
+    /// # Args
+    /// * `connected` - Whether the session is established.
+    /// * `now_ms` - The monotonic time.
+    pub fn poll_due(&mut self, connected: bool, now_ms: u64) -> bool {
+        if connected {
+            if self.failures != 0 {
+                info!("Broker connected after {} failed attempts", self.failures);
+            }
+            self.phase = Phase::Connected;
+            self.failures = 0;
+            return true;
+        }
+
+        match self.phase {
+            Phase::Connected => {
+                self.phase = Phase::Waiting(now_ms + self.delay_ms());
+                false
+            }
+            Phase::Waiting(deadline) if now_ms >= deadline => {
+                self.attempts = self.attempts.wrapping_add(1);
+                self.phase = Phase::Attempting(now_ms + ATTEMPT_WINDOW_MS);
+                true
+            }
+            Phase::Waiting(_) => false,
+            Phase::Attempting(deadline) if now_ms >= deadline => {
+                self.failures = self.failures.saturating_add(1);
+                let delay = self.delay_ms();
+                warn!(
+                    "Broker connection attempt {} failed, retrying in {} ms",
+                    self.failures, delay
+                );
+                self.phase = Phase::Waiting(now_ms + delay);
+                false
+            }
+            Phase::Attempting(_) => true,
+        }
+    }
+
+    pub fn stats(&self, now_ms: u64) -> ReconnectStats {
+        ReconnectStats {
+            attempts: self.attempts,
+            failures: self.failures,
+            retry_in_ms: match self.phase {
+                Phase::Waiting(deadline) => deadline.saturating_sub(now_ms) as u32,
+                _ => 0,
+            },
+        }
+    }
+
+    /// The randomized delay after the current number of failures.
+    fn delay_ms(&mut self) -> u64 {
+        let exponent = self.failures.saturating_sub(1).min(31);
+        let delay = u64::from(self.config.min_ms)
+            .saturating_mul(1 << exponent)
+            .min(u64::from(self.config.max_ms));
+        let half = delay / 2;
+        delay - half + u64::from(self.next_random()) % (half + 1)
+    }
+
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+}
@@ -0,0 +1,109 @@
+//! State of the connection to the broker.
+//!
+//! # Design
+//!  The connection goes through ordered states, each requiring the previous one: the
+//!  Ethernet link, an IP address usable to reach the broker, the TCP connection to the
+//!  broker, the MQTT session and the settings subscription. Settings and telemetry
+//!  share a single MQTT session, so there is one state for both.
+//!
+//!  The state is sampled on every poll of the IP stack. The time spent in every state
+//!  since boot is accumulated, along with the number of MQTT sessions established.
+//!
+//!  The monitor also paces the reconnections to the broker, see `backoff`.
+use super::backoff::{BackoffConfig, ReconnectBackoff};
+use crate::hardware::network_clock::NetworkClock;
+use log::info;
+pub use mqtt_rtic_common::telemetry::{ConnectionState, ConnectionStatus, StateTimes};
+use smoltcp_nal::smoltcp::wire::IpEndpoint;
+
+/// minimq connects to the default MQTT port.
+pub const MQTT_PORT: u16 = 1883;
+
+pub struct ConnectionMonitor {
+    clock: NetworkClock,
+    broker: IpEndpoint,
+    state: ConnectionState,
+    /// Monotonic time the current state was entered.
+    entered_ms: u64,
+    /// Milliseconds spent in the previous states, excluding the current one.
+    totals_ms: [u64; 6],
+    sessions: u32,
+    backoff: ReconnectBackoff,
+}
+
+impl ConnectionMonitor {
+    /// Construct a new connection monitor.
+    ///
+    /// # Args
+    /// * `clock` - The monotonic clock timing the states.
+    /// * `broker` - The endpoint of the broker.
+    /// * `backoff` - The limits of the delay between reconnections.
+    pub fn new(clock: NetworkClock, broker: IpEndpoint, backoff: BackoffConfig) -> Self {
+        Self {
+            entered_ms: clock.now_ms(),
+            clock,
+            broker,
+            state: ConnectionState::Down,
+            totals_ms: [0; 6],
+            sessions: 0,
+            backoff: ReconnectBackoff::new(backoff),
+        }
+    }
+
+    /// Seed the jitter of the reconnections.
+    pub fn seed_backoff(&mut self, seed: u32) {
+        self.backoff.seed(seed);
+    }
+
+    /// Whether the MQTT client should be polled, it reconnects when polled.
+    pub fn mqtt_poll_due(&mut self) -> bool {
+        let connected = self.state >= ConnectionState::MqttConnected;
+        self.backoff.poll_due(connected, self.clock.now_ms())
+    }
+
+    pub fn broker(&self) -> IpEndpoint {
+        self.broker
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Record the current state.
+    pub fn update(&mut self, state: ConnectionState) {
+        if state == self.state {
+            return;
+        }
+        let now = self.clock.now_ms();
+        self.totals_ms[self.state as usize] += now.saturating_sub(self.entered_ms);
+        if state >= ConnectionState::MqttConnected && self.state < ConnectionState::MqttConnected {
+            self.sessions = self.sessions.wrapping_add(1);
+        }
+        info!("Connection: {:?}", state);
+        self.state = state;
+        self.entered_ms = now;
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        let now = self.clock.now_ms();
+        let in_state_ms = now.saturating_sub(self.entered_ms);
+        let mut totals_ms = self.totals_ms;
+        totals_ms[self.state as usize] += in_state_ms;
+        let [down, link, ip_configured, tcp_connected, mqtt_connected, subscribed] =
+            totals_ms.map(|ms| (ms / 1000) as u32);
+        ConnectionStatus {
+            state: self.state,
+            since_s: (in_state_ms / 1000) as u32,
+            totals: StateTimes {
+                down_s: down,
+                link_s: link,
+                ip_configured_s: ip_configured,
+                tcp_connected_s: tcp_connected,
+                mqtt_connected_s: mqtt_connected,
+                subscribed_s: subscribed,
+            },
+            sessions: self.sessions,
+            reconnect: self.backoff.stats(now),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Payload encodings of telemetry streams.
+//!
+//! # Design
+//!  Every stream is encoded with one of JSON, CBOR or postcard, and tagged with the
+//!  matching MQTT v5 content type so consumers can pick the decoder. CBOR encodes
+//!  structs as maps keyed by field name. Postcard isn't self-describing: consumers need
+//!  the exact type definitions, see the host-side decoder in `host/telemetry-decoder`.
+use core::str::FromStr;
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+    Postcard,
+}
+
+impl FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "json" => Ok(Encoding::Json),
+            "cbor" => Ok(Encoding::Cbor),
+            "postcard" => Ok(Encoding::Postcard),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Encoding {
+    /// The MQTT v5 content type of the encoding.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Cbor => "application/cbor",
+            Encoding::Postcard => "application/x-postcard",
+        }
+    }
+
+    /// Encode a value.
+    ///
+    /// # Args
+    /// * `value` - The value to encode.
+    /// * `buf` - The buffer to encode into.
+    ///
+    /// # Returns
+    /// The encoded value, or `None` if it doesn't fit in the buffer.
+    pub fn encode<'a, T: Serialize>(&self, value: &T, buf: &'a mut [u8]) -> Option<&'a [u8]> {
+        let len = match self {
+            Encoding::Json => serde_json_core::to_slice(value, buf).ok()?,
+            Encoding::Cbor => {
+                let mut serializer =
+                    serde_cbor::Serializer::new(serde_cbor::ser::SliceWrite::new(&mut buf[..]));
+                value.serialize(&mut serializer).ok()?;
+                serializer.into_inner().bytes_written()
+            }
+            Encoding::Postcard => postcard::to_slice(value, buf).ok()?.len(),
+        };
+        Some(&buf[..len])
+    }
+}
@@ -0,0 +1,321 @@
+//! Home Assistant MQTT discovery.
+//!
+//! # Design
+//!  A retained config message is published for every entity under
+//!  `homeassistant/<component>/<node>/<object>/config` after each connection to the
+//!  broker. The `led` setting is a switch bound to its miniconf topic.
+//!
+//!  The telemetry entities are generated from the telemetry type, so they can't drift
+//!  from it: every scalar field is a sensor reading from the telemetry topic, or a binary
+//!  sensor if it's a boolean. The entity is named after the path of the field, e.g.
+//!  `time_sync.offset_ms`, and takes the unit and class of the field as its unit and
+//!  device class. Fields in seconds or milliseconds without a class are durations, and
+//!  numbers are measurements. The value templates check the JSON type of the field and
+//!  render `none` for anything else, so an unset (null) field is shown as unknown instead
+//!  of a `None` that a numeric sensor fails to parse, or a binary sensor reads as off.
+//!
+//!  The configs are published one per update, so they don't overrun the TCP send
+//!  buffer, and use the abbreviated keys and `~` base topic of the discovery schema to
+//!  fit in an MQTT message.
+use super::MqttClient;
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+use log::warn;
+use minimq::{QoS, Retain};
+use mqtt_rtic_common::schema::{self, Field, FieldPath, FieldVisitor, Kind, Schema};
+use serde::Serialize;
+use smoltcp_nal::smoltcp::wire::EthernetAddress;
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Leaves room for the topic and headers of the publish packet.
+const CONFIG_SIZE_MAX: usize = 384;
+
+/// Holds the template of a timestamp, which refers to the field twice.
+const VALUE_TEMPLATE_SIZE_MAX: usize = 256;
+
+struct Entity {
+    component: &'static str,
+    object_id: FieldPath,
+    name: FieldPath,
+    value_template: Option<String<VALUE_TEMPLATE_SIZE_MAX>>,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: Option<&'static str>,
+}
+
+impl Entity {
+    /// The switch of the `led` setting.
+    fn led() -> Self {
+        Self {
+            component: "switch",
+            object_id: String::from("led"),
+            name: String::from("LED"),
+            value_template: None,
+            unit: None,
+            device_class: None,
+            state_class: None,
+        }
+    }
+
+    /// The sensor of a telemetry field.
+    fn sensor(field: &Field) -> Result<Self, fmt::Error> {
+        let path = field.path;
+        let annotations = field.annotations;
+
+        let mut object_id = FieldPath::new();
+        for c in path.chars().filter(|&c| c != ']') {
+            let c = if c == '.' || c == '[' { '_' } else { c };
+            object_id.push(c).map_err(|_| fmt::Error)?;
+        }
+
+        let mut value_template = String::new();
+        let (component, unit, device_class, state_class) = match (field.kind, annotations.class) {
+            (Kind::Boolean, class) => {
+                write!(
+                    &mut value_template,
+                    concat!(
+                        "{{{{ ('ON' if value_json.{} else 'OFF') ",
+                        "if value_json.{} is boolean else none }}}}"
+                    ),
+                    path, path
+                )?;
+                ("binary_sensor", None, class, None)
+            }
+            (Kind::Number, Some("timestamp")) => {
+                let divisor = if annotations.unit == Some("ms") {
+                    " / 1000"
+                } else {
+                    ""
+                };
+                write!(
+                    &mut value_template,
+                    concat!(
+                        "{{{{ (value_json.{}{}) ",
+                        "| timestamp_custom('%Y-%m-%dT%H:%M:%S+00:00', false) ",
+                        "if value_json.{} is number else none }}}}"
+                    ),
+                    path, divisor, path
+                )?;
+                ("sensor", None, Some("timestamp"), None)
+            }
+            (Kind::Number, class) => {
+                write!(
+                    &mut value_template,
+                    "{{{{ value_json.{} if value_json.{} is number else none }}}}",
+                    path, path
+                )?;
+                let class = class.or(match annotations.unit {
+                    Some("s") | Some("ms") => Some("duration"),
+                    _ => None,
+                });
+                ("sensor", annotations.unit, class, Some("measurement"))
+            }
+            (Kind::Text, class) => {
+                write!(
+                    &mut value_template,
+                    "{{{{ value_json.{} if value_json.{} is string else none }}}}",
+                    path, path
+                )?;
+                ("sensor", None, class, None)
+            }
+        };
+
+        Ok(Self {
+            component,
+            object_id,
+            name: String::from(path),
+            value_template: Some(value_template),
+            unit,
+            device_class,
+            state_class,
+        })
+    }
+}
+
+/// Finds the sensor of the scalar field at an index.
+struct NthSensor {
+    index: usize,
+    visited: usize,
+    sensor: Option<Result<Entity, fmt::Error>>,
+}
+
+impl FieldVisitor for NthSensor {
+    fn visit(&mut self, field: &Field) {
+        if self.visited == self.index {
+            self.sensor = Some(Entity::sensor(field));
+        }
+        self.visited += 1;
+    }
+}
+
+/// The sensor of the scalar telemetry field at an index, `None` past the last field.
+fn nth_sensor<T: Schema>(index: usize) -> Option<Result<Entity, fmt::Error>> {
+    let mut visitor = NthSensor {
+        index,
+        visited: 0,
+        sensor: None,
+    };
+    // The paths of the telemetry fit, see the tests of mqtt-rtic-common
+    schema::visit_fields::<T, _>(&mut visitor).ok();
+    visitor.sensor
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    #[serde(rename = "ids")]
+    identifiers: [&'a str; 1],
+    name: &'a str,
+    #[serde(rename = "mdl")]
+    model: &'a str,
+    #[serde(rename = "sw")]
+    sw_version: &'a str,
+}
+
+#[derive(Serialize)]
+struct EntityConfig<'a> {
+    #[serde(rename = "~")]
+    base_topic: &'a str,
+    name: &'a str,
+    #[serde(rename = "uniq_id")]
+    unique_id: &'a str,
+    #[serde(rename = "stat_t", skip_serializing_if = "Option::is_none")]
+    state_topic: Option<&'a str>,
+    #[serde(rename = "val_tpl", skip_serializing_if = "Option::is_none")]
+    value_template: Option<&'a str>,
+    #[serde(rename = "cmd_t", skip_serializing_if = "Option::is_none")]
+    command_topic: Option<&'a str>,
+    #[serde(rename = "pl_on", skip_serializing_if = "Option::is_none")]
+    payload_on: Option<&'a str>,
+    #[serde(rename = "pl_off", skip_serializing_if = "Option::is_none")]
+    payload_off: Option<&'a str>,
+    #[serde(rename = "opt", skip_serializing_if = "Option::is_none")]
+    optimistic: Option<bool>,
+    #[serde(rename = "unit_of_meas", skip_serializing_if = "Option::is_none")]
+    unit: Option<&'a str>,
+    #[serde(rename = "dev_cla", skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'a str>,
+    #[serde(rename = "stat_cla", skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'a str>,
+    #[serde(rename = "dev")]
+    device: &'a Device<'a>,
+}
+
+pub struct HomeAssistantDiscovery<T: Schema> {
+    hostname: String<64>,
+    prefix: String<128>,
+    mac: String<32>,
+    app: String<48>,
+    /// The next entity to publish, 0 is the `led` switch and the sensors follow.
+    next: Option<usize>,
+    _telemetry: core::marker::PhantomData<T>,
+}
+
+impl<T: Schema> HomeAssistantDiscovery<T> {
+    /// Construct a new discovery publisher.
+    ///
+    /// # Args
+    /// * `hostname` - The host name of the device, used as the discovery node ID.
+    /// * `app` - The name of the application, used as the device model.
+    /// * `prefix` - The MQTT prefix of the device.
+    /// * `mac` - The ethernet MAC address of the device, used as the device identifier.
+    pub fn new(hostname: &str, app: &str, prefix: &str, mac: EthernetAddress) -> Self {
+        let mut mac_string = String::new();
+        write!(&mut mac_string, "{}", mac).unwrap();
+
+        Self {
+            hostname: String::from(hostname),
+            prefix: String::from(prefix),
+            mac: mac_string,
+            app: String::from(app),
+            next: None,
+            _telemetry: core::marker::PhantomData::default(),
+        }
+    }
+
+    /// Publish the configs again, e.g. after the connection is (re)established.
+    pub fn restart(&mut self) {
+        self.next = Some(0);
+    }
+
+    /// Publish the next pending config, if any.
+    pub fn update(&mut self, mqtt: &mut MqttClient) {
+        let index = match self.next {
+            Some(index) => index,
+            None => return,
+        };
+        let entity = match index {
+            0 => Entity::led(),
+            index => match nth_sensor::<T>(index - 1) {
+                Some(Ok(entity)) => entity,
+                Some(Err(_)) => {
+                    warn!("Discovery config {} is too large", index);
+                    self.next = Some(index + 1);
+                    return;
+                }
+                None => {
+                    self.next = None;
+                    return;
+                }
+            },
+        };
+
+        let mut topic: String<128> = String::new();
+        let mut unique_id: String<96> = String::new();
+        if write!(
+            &mut topic,
+            "{}/{}/{}/{}/config",
+            DISCOVERY_PREFIX, entity.component, self.hostname, entity.object_id
+        )
+        .is_err()
+        {
+            warn!("Discovery topic of `{}` is too long", entity.object_id);
+            self.next = Some(index + 1);
+            return;
+        }
+        // Note(unwrap): The MAC address and the object ID are short enough to fit.
+        write!(&mut unique_id, "{}-{}", self.mac, entity.object_id).unwrap();
+
+        let device = Device {
+            identifiers: [&self.mac],
+            name: &self.hostname,
+            model: &self.app,
+            sw_version: crate::built_info::PKG_VERSION,
+        };
+        let is_switch = entity.component == "switch";
+        let config = EntityConfig {
+            base_topic: &self.prefix,
+            name: &entity.name,
+            unique_id: &unique_id,
+            state_topic: (!is_switch).then_some("~/telemetry"),
+            value_template: entity.value_template.as_deref(),
+            command_topic: is_switch.then_some("~/settings/led"),
+            payload_on: is_switch.then_some("true"),
+            payload_off: is_switch.then_some("false"),
+            // The settings aren't published, so the switch state can't be read back
+            optimistic: is_switch.then_some(true),
+            unit: entity.unit,
+            device_class: entity.device_class,
+            state_class: entity.state_class,
+            device: &device,
+        };
+
+        let payload: Vec<u8, CONFIG_SIZE_MAX> = match serde_json_core::to_vec(&config) {
+            Ok(payload) => payload,
+            Err(_) => {
+                warn!("Discovery config of `{}` is too large", entity.object_id);
+                self.next = Some(index + 1);
+                return;
+            }
+        };
+
+        // Retry on the next update if the session can't take the message yet
+        if mqtt
+            .client
+            .publish(&topic, &payload, QoS::AtMostOnce, Retain::Retained, &[])
+            .is_ok()
+        {
+            self.next = Some(index + 1);
+        }
+    }
+}
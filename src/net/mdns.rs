@@ -0,0 +1,500 @@
+//! Multicast DNS (RFC 6762) responder with DNS-SD (RFC 6763) service advertisement.
+//!
+//! # Design
+//!  The responder owns a dedicated smoltcp UDP socket bound to port 5353, since
+//!  the embedded-nal UDP client API can't bind to a well-known port or join a
+//!  multicast group. It answers queries for:
+//!  * `<hostname>.local` A
+//!  * `_services._dns-sd._udp.local` PTR (service type enumeration)
+//!  * `_mqtt-device._tcp.local` PTR
+//!  * `<hostname>._mqtt-device._tcp.local` SRV and TXT
+//!
+//!  The device doesn't accept connections, the SRV record points at port 0 and
+//!  exists to carry the TXT metadata (MQTT prefix, version and MAC address).
+//!  All records are announced twice when the socket is (re)opened.
+//!
+//!  Responses are limited to `PACKET_SIZE_MAX`. Answers that don't fit are sent in
+//!  further packets, additional records that don't fit are left out: they only save
+//!  the querier follow-up queries. The names and TXT entries are checked when the
+//!  responder is constructed, so that every record fits in a packet on its own.
+use super::NetworkReference;
+use crate::hardware::network_clock::NetworkClock;
+use core::fmt::Write;
+use heapless::String;
+use log::debug;
+use smoltcp_nal::smoltcp::{
+    self,
+    iface::SocketHandle,
+    socket::UdpSocket,
+    time::Instant,
+    wire::{EthernetAddress, IpEndpoint, Ipv4Address},
+};
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+
+const SERVICE_TYPE: &str = "_mqtt-device._tcp.local";
+const SERVICES_ENUMERATION: &str = "_services._dns-sd._udp.local";
+
+const HOST_RECORD_TTL: u32 = 120;
+const SERVICE_RECORD_TTL: u32 = 4500;
+
+const ANNOUNCEMENT_COUNT: u8 = 2;
+const ANNOUNCEMENT_INTERVAL_MS: u64 = 1_000;
+
+const PACKET_SIZE_MAX: usize = 512;
+const NAME_SIZE_MAX: usize = 128;
+const TXT_SIZE_MAX: usize = 256;
+
+const HEADER_SIZE: usize = 12;
+const FLAGS_RESPONSE: u16 = 0x8400;
+const CLASS_IN: u16 = 1;
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+/// Longest label of a name on the wire.
+const LABEL_SIZE_MAX: usize = 63;
+/// Longest TXT entry on the wire.
+const TXT_ENTRY_SIZE_MAX: usize = 255;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// A name or TXT entry exceeds its capacity or doesn't fit in a response.
+    NameTooLong,
+}
+
+/// Set of records, as a bitmask.
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+struct Records(u8);
+
+impl Records {
+    const A: Self = Self(1 << 0);
+    const SERVICES_PTR: Self = Self(1 << 1);
+    const PTR: Self = Self(1 << 2);
+    const SRV: Self = Self(1 << 3);
+    const TXT: Self = Self(1 << 4);
+    const ALL: Self = Self(0x1F);
+
+    /// Single records, in the order they are written.
+    const ORDER: [Self; 5] = [Self::SERVICES_PTR, Self::PTR, Self::SRV, Self::TXT, Self::A];
+
+    fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// Records that should accompany `self` as additional records.
+    fn implied(self) -> Self {
+        let mut additional = Self::default();
+        if self.contains(Self::PTR) {
+            additional.insert(Self(Self::SRV.0 | Self::TXT.0 | Self::A.0));
+        }
+        if self.contains(Self::SRV) {
+            additional.insert(Self::A);
+        }
+        additional.remove(self);
+        additional
+    }
+}
+
+pub struct MdnsResponder {
+    stack: NetworkReference,
+    clock: NetworkClock,
+    handle: SocketHandle,
+    ip: Ipv4Address,
+    host: String<NAME_SIZE_MAX>,
+    instance: String<NAME_SIZE_MAX>,
+    txt: String<TXT_SIZE_MAX>,
+    announcements: u8,
+    next_announcement_ms: u64,
+}
+
+impl MdnsResponder {
+    /// Construct a new mDNS responder.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    /// * `clock` - The clock used for multicast group membership and announcements.
+    /// * `handle` - The UDP socket dedicated to mDNS.
+    /// * `hostname` - The host name of the device, without the `.local` domain.
+    /// * `ip` - The IPv4 address of the device.
+    /// * `prefix` - The MQTT prefix advertised in the TXT record.
+    /// * `mac` - The MAC address advertised in the TXT record.
+    ///
+    /// # Returns
+    /// The responder, or an error if a record wouldn't fit in a response.
+    pub fn new(
+        stack: NetworkReference,
+        clock: NetworkClock,
+        handle: SocketHandle,
+        hostname: &str,
+        ip: Ipv4Address,
+        prefix: &str,
+        mac: EthernetAddress,
+    ) -> Result<Self, Error> {
+        let mut host = String::new();
+        write!(&mut host, "{}.local", hostname).map_err(|_| Error::NameTooLong)?;
+        host.make_ascii_lowercase();
+
+        let mut instance = String::new();
+        write!(&mut instance, "{}.{}", hostname, SERVICE_TYPE).map_err(|_| Error::NameTooLong)?;
+        instance.make_ascii_lowercase();
+
+        // TXT entries, separated by '\n' and converted to length-prefixed strings on the wire
+        let mut txt = String::new();
+        write!(
+            &mut txt,
+            "prefix={}\nversion={}\nmac={}",
+            prefix,
+            crate::built_info::PKG_VERSION,
+            mac
+        )
+        .map_err(|_| Error::NameTooLong)?;
+
+        let responder = Self {
+            stack,
+            clock,
+            handle,
+            ip,
+            host,
+            instance,
+            txt,
+            announcements: 0,
+            next_announcement_ms: 0,
+        };
+
+        // Every record must fit in a response on its own, or it could never be sent
+        let mut response = [0; PACKET_SIZE_MAX];
+        for record in Records::ORDER {
+            let (_, left) = responder.write_response(&mut response, record, Records::default());
+            if left != Records::default() {
+                return Err(Error::NameTooLong);
+            }
+        }
+        Ok(responder)
+    }
+
+    pub fn update(&mut self) {
+        let now = self.clock.now_ms();
+        let mut packet = [0; PACKET_SIZE_MAX];
+        let mut response = [0; PACKET_SIZE_MAX];
+
+        let handle = self.handle;
+        let opened: smoltcp::Result<bool> = self.stack.lock(|stack| {
+            let iface = stack.interface_mut();
+            if iface.get_socket::<UdpSocket>(handle).is_open() {
+                return Ok(false);
+            }
+            iface.join_multicast_group(MDNS_GROUP, Instant::from_millis(now as i64))?;
+            iface.get_socket::<UdpSocket>(handle).bind(MDNS_PORT)?;
+            Ok(true)
+        });
+        match opened {
+            Ok(true) => {
+                debug!("mDNS responder listening as {}", self.host);
+                self.announcements = ANNOUNCEMENT_COUNT;
+                self.next_announcement_ms = now;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                debug!("Failed to open mDNS socket: {:?}", e);
+                return;
+            }
+        }
+
+        if self.announcements != 0
+            && now >= self.next_announcement_ms
+            && self.respond(&mut response, Records::ALL, Records::default())
+        {
+            self.announcements -= 1;
+            self.next_announcement_ms = now + ANNOUNCEMENT_INTERVAL_MS;
+        }
+
+        loop {
+            let size = match self.stack.lock(|stack| {
+                stack
+                    .interface_mut()
+                    .get_socket::<UdpSocket>(handle)
+                    .recv_slice(&mut packet)
+            }) {
+                Ok((size, _)) => size,
+                Err(_) => break,
+            };
+
+            let answers = self.match_questions(&packet[..size]);
+            if answers != Records::default() {
+                self.respond(&mut response, answers, answers.implied());
+            }
+        }
+    }
+
+    /// Send the answers, in as many packets as they need, with the additional records
+    /// that fit after the last answers.
+    ///
+    /// # Returns
+    /// Whether all the packets were sent.
+    fn respond(&mut self, buf: &mut [u8], mut answers: Records, additional: Records) -> bool {
+        while answers != Records::default() {
+            let (size, left) = self.write_response(buf, answers, additional);
+            // The records were checked to fit on their own at construction
+            if left == answers || !self.send(&buf[..size]) {
+                return false;
+            }
+            answers = left;
+        }
+        true
+    }
+
+    fn send(&mut self, response: &[u8]) -> bool {
+        let handle = self.handle;
+        self.stack
+            .lock(|stack| {
+                stack
+                    .interface_mut()
+                    .get_socket::<UdpSocket>(handle)
+                    .send_slice(response, IpEndpoint::new(MDNS_GROUP.into(), MDNS_PORT))
+            })
+            .is_ok()
+    }
+
+    /// Find the records that answer the questions of a query.
+    fn match_questions(&self, packet: &[u8]) -> Records {
+        let mut answers = Records::default();
+        if packet.len() < HEADER_SIZE {
+            return answers;
+        }
+
+        let flags = u16::from_be_bytes([packet[2], packet[3]]);
+        // Only standard queries, responses from other hosts are ignored
+        if flags & 0xF800 != 0 {
+            return answers;
+        }
+
+        let questions = u16::from_be_bytes([packet[4], packet[5]]);
+        let mut pos = HEADER_SIZE;
+        for _ in 0..questions {
+            let mut name: String<NAME_SIZE_MAX> = String::new();
+            pos = match read_name(packet, pos, &mut name) {
+                Some(pos) => pos,
+                None => break,
+            };
+            let qtype = match packet.get(pos..pos + 4) {
+                Some(q) => u16::from_be_bytes([q[0], q[1]]),
+                None => break,
+            };
+            pos += 4;
+
+            let is = |t| qtype == t || qtype == TYPE_ANY;
+            if name == self.host && is(TYPE_A) {
+                answers.insert(Records::A);
+            } else if name == SERVICES_ENUMERATION && is(TYPE_PTR) {
+                answers.insert(Records::SERVICES_PTR);
+            } else if name == SERVICE_TYPE && is(TYPE_PTR) {
+                answers.insert(Records::PTR);
+            } else if name == self.instance {
+                if is(TYPE_SRV) {
+                    answers.insert(Records::SRV);
+                }
+                if is(TYPE_TXT) {
+                    answers.insert(Records::TXT);
+                }
+            }
+        }
+        answers
+    }
+
+    /// Write a response with as many of the answers as fit, followed by the additional
+    /// records that fit in the rest of the packet.
+    ///
+    /// # Returns
+    /// The size of the response and the answers left out of it.
+    fn write_response(
+        &self,
+        buf: &mut [u8],
+        answers: Records,
+        additional: Records,
+    ) -> (usize, Records) {
+        let mut w = Writer { buf, pos: 0 };
+        let mut left = Records::default();
+        let mut counts = [0u16; 2];
+        if w.bytes(&[0; HEADER_SIZE]).is_none() {
+            return (0, answers);
+        }
+
+        for (section, records) in [answers, additional].into_iter().enumerate() {
+            for record in Records::ORDER {
+                if !records.contains(record) {
+                    continue;
+                }
+                // Once an answer doesn't fit, the rest go to the next packet, in order, and
+                // the additional records with them
+                let start = w.pos;
+                if left == Records::default() && self.write_record(&mut w, record).is_some() {
+                    counts[section] += 1;
+                } else {
+                    w.pos = start;
+                    if section == 0 {
+                        left.insert(record);
+                    }
+                }
+            }
+        }
+
+        let [answer_count, additional_count] = counts;
+        let header = &mut w.buf[..HEADER_SIZE];
+        header[2..4].copy_from_slice(&FLAGS_RESPONSE.to_be_bytes());
+        header[6..8].copy_from_slice(&answer_count.to_be_bytes());
+        header[10..12].copy_from_slice(&additional_count.to_be_bytes());
+        (w.pos, left)
+    }
+
+    fn write_record(&self, w: &mut Writer, record: Records) -> Option<()> {
+        match record {
+            Records::SERVICES_PTR => w.record(
+                SERVICES_ENUMERATION,
+                TYPE_PTR,
+                CLASS_IN,
+                SERVICE_RECORD_TTL,
+                |w| w.name(SERVICE_TYPE),
+            ),
+            Records::PTR => w.record(SERVICE_TYPE, TYPE_PTR, CLASS_IN, SERVICE_RECORD_TTL, |w| {
+                w.name(&self.instance)
+            }),
+            Records::SRV => w.record(
+                &self.instance,
+                TYPE_SRV,
+                CLASS_IN | CLASS_CACHE_FLUSH,
+                HOST_RECORD_TTL,
+                |w| {
+                    // Priority, weight and port
+                    w.u16(0)?;
+                    w.u16(0)?;
+                    w.u16(0)?;
+                    w.name(&self.host)
+                },
+            ),
+            Records::TXT => w.record(
+                &self.instance,
+                TYPE_TXT,
+                CLASS_IN | CLASS_CACHE_FLUSH,
+                SERVICE_RECORD_TTL,
+                |w| {
+                    for entry in self.txt.split('\n') {
+                        if entry.len() > TXT_ENTRY_SIZE_MAX {
+                            return None;
+                        }
+                        w.u8(entry.len() as u8)?;
+                        w.bytes(entry.as_bytes())?;
+                    }
+                    Some(())
+                },
+            ),
+            Records::A => w.record(
+                &self.host,
+                TYPE_A,
+                CLASS_IN | CLASS_CACHE_FLUSH,
+                HOST_RECORD_TTL,
+                |w| w.bytes(self.ip.as_bytes()),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Read a (possibly compressed) domain name as lower-case dotted labels.
+///
+/// # Returns
+/// The position following the name in the packet.
+fn read_name(packet: &[u8], mut pos: usize, name: &mut String<NAME_SIZE_MAX>) -> Option<usize> {
+    let mut end = None;
+    // Bound the number of compression pointers followed to reject loops
+    for _ in 0..16 {
+        loop {
+            let len = *packet.get(pos)? as usize;
+            if len == 0 {
+                return Some(end.unwrap_or(pos + 1));
+            }
+            if len & 0xC0 == 0xC0 {
+                end.get_or_insert(pos + 2);
+                pos = ((len & 0x3F) << 8) | *packet.get(pos + 1)? as usize;
+                break;
+            }
+            let label = packet.get(pos + 1..pos + 1 + len)?;
+            if !name.is_empty() {
+                name.push('.').ok()?;
+            }
+            for &b in label {
+                name.push(char::from(b).to_ascii_lowercase()).ok()?;
+            }
+            pos += 1 + len;
+        }
+    }
+    None
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn bytes(&mut self, data: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + data.len())?
+            .copy_from_slice(data);
+        self.pos += data.len();
+        Some(())
+    }
+
+    fn u8(&mut self, value: u8) -> Option<()> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Write a dotted name as uncompressed labels.
+    fn name(&mut self, name: &str) -> Option<()> {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > LABEL_SIZE_MAX {
+                return None;
+            }
+            self.u8(label.len() as u8)?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.u8(0)
+    }
+
+    fn record<F>(&mut self, name: &str, rtype: u16, class: u16, ttl: u32, rdata: F) -> Option<()>
+    where
+        F: FnOnce(&mut Self) -> Option<()>,
+    {
+        self.name(name)?;
+        self.u16(rtype)?;
+        self.u16(class)?;
+        self.u32(ttl)?;
+        let length_pos = self.pos;
+        self.u16(0)?;
+        rdata(self)?;
+        let length = (self.pos - length_pos - 2) as u16;
+        self.buf[length_pos..length_pos + 2].copy_from_slice(&length.to_be_bytes());
+        Some(())
+    }
+}
@@ -1,23 +1,68 @@
+use crate::config::{Config, TopicConfig, TOPIC_DEFAULT_TEMPLATE};
 use crate::hardware::{
     gpio::{PhyMdcPin, PhyMdioPin},
     network_clock::NetworkClock,
     NetworkManager, NetworkStack,
 };
+use crate::inputs::InputEvent;
+use connection::{ConnectionMonitor, ConnectionState, MQTT_PORT};
 use core::fmt::Write;
+use encoding::Encoding;
 use heapless::String;
+use home_assistant::HomeAssistantDiscovery;
+use mdns::MdnsResponder;
 use miniconf::Miniconf;
-use minimq::embedded_nal::IpAddr;
+use minimq::embedded_nal::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use minimq::{Property, QoS, Retain};
+use mqtt_rtic_common::schema::Schema;
 use network_processor::NetworkProcessor;
+use ping::PingMonitor;
 use serde::Serialize;
+use settings_handler::SettingsHandler;
+use slaac::Ipv6Autoconf;
+use smoltcp_nal::smoltcp::{
+    iface::SocketHandle,
+    wire::{IpAddress, IpEndpoint},
+};
+use sntp::SntpClient;
+use sparkplug::SparkplugNode;
+use syslog::SyslogClient;
 use telemetry::TelemetryClient;
+use topic::DEVICE_PREFIX_MAX;
 
+pub mod backoff;
+pub mod connection;
+pub mod encoding;
+pub mod home_assistant;
+pub mod mdns;
 pub mod network_processor;
+pub mod ping;
+pub mod poll_stats;
+pub mod settings_handler;
+pub mod slaac;
+pub mod sntp;
+pub mod sparkplug;
+pub mod syslog;
 pub mod telemetry;
+pub mod topic;
 
-pub const MQTT_MESSAGE_SIZE_MAX: usize = 512;
+pub use crate::hardware::sizes::MQTT_MESSAGE_SIZE_MAX;
+const MQTT_MSG_COUNT: usize = 1;
 
 pub type NetworkReference = smoltcp_nal::shared::NetworkStackProxy<'static, NetworkStack>;
 
+/// The MQTT session shared by the settings handler, the telemetry publisher and the
+/// Sparkplug edge node.
+pub type MqttClient =
+    minimq::Minimq<NetworkReference, NetworkClock, MQTT_MESSAGE_SIZE_MAX, MQTT_MSG_COUNT>;
+
+/// Sockets used directly through smoltcp rather than the embedded-nal stack.
+pub struct DedicatedSockets {
+    pub mdns: SocketHandle,
+    pub icmp: SocketHandle,
+    pub icmpv6: SocketHandle,
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum UpdateState {
     NoChange,
@@ -31,16 +76,28 @@ pub enum NetworkState {
     NoChange,
 }
 
-pub struct NetworkUsers<S: Default + Miniconf, T: Serialize> {
-    pub miniconf: miniconf::MqttClient<S, NetworkReference, NetworkClock, MQTT_MESSAGE_SIZE_MAX>,
+pub struct NetworkUsers<S: Default + Miniconf, T: Serialize + Schema + Clone + Default> {
+    pub mqtt: MqttClient,
+    pub settings: SettingsHandler<S>,
     pub processor: NetworkProcessor,
     pub telemetry: TelemetryClient<T>,
+    /// Only when JSON telemetry is published on the telemetry topic.
+    pub discovery: Option<HomeAssistantDiscovery<T>>,
+    pub sparkplug: Option<SparkplugNode<T>>,
+    pub syslog: Option<SyslogClient>,
+    pub sntp: Option<SntpClient>,
+    /// Only when the names fit in the mDNS records.
+    pub mdns: Option<MdnsResponder>,
+    pub ping: PingMonitor,
+    pub slaac: Ipv6Autoconf,
+    pub connection: ConnectionMonitor,
+    connected: bool,
 }
 
 impl<S, T> NetworkUsers<S, T>
 where
     S: Default + Miniconf,
-    T: Serialize,
+    T: Serialize + Schema + Clone + Default,
 {
     pub fn new(
         stack_manager: &'static mut NetworkManager,
@@ -48,40 +105,151 @@ where
         mdc: PhyMdcPin,
         clock: NetworkClock,
         app: &str,
-        mac: smoltcp_nal::smoltcp::wire::EthernetAddress,
-        broker: IpAddr,
+        config: &Config,
+        sockets: DedicatedSockets,
     ) -> Self {
-        let processor = NetworkProcessor::new(stack_manager.acquire_stack(), mdio, mdc);
+        let mac = config.mac_address;
+        let broker: IpAddr = match config.broker_ip_address {
+            IpAddress::Ipv6(ip) => Ipv6Addr::from(ip.0).into(),
+            IpAddress::Ipv4(ip) => Ipv4Addr::from(ip.0).into(),
+            // Note(unreachable): Parsing only yields IPv4 and IPv6 addresses
+            _ => unreachable!(),
+        };
 
-        let prefix = get_device_prefix(app, mac);
+        let processor = NetworkProcessor::new(stack_manager.acquire_stack(), clock, mdio, mdc);
 
-        let settings = miniconf::MqttClient::new(
-            stack_manager.acquire_stack(),
-            &get_client_id(app, "settings", mac),
+        let prefix = get_device_prefix(app, mac, &config.topic);
+        let hostname = get_hostname(app, mac);
+
+        let mut mqtt =
+            minimq::Minimq::new(broker, &hostname, stack_manager.acquire_stack(), clock).unwrap();
+
+        let settings = SettingsHandler::new(&prefix);
+        let telemetry = TelemetryClient::new(
             &prefix,
-            broker,
+            config.telemetry_encoding,
+            config.diagnostics_encoding,
+        );
+        let discovery = (config.sparkplug_group_id.is_none()
+            && config.telemetry_encoding == Encoding::Json)
+            .then(|| HomeAssistantDiscovery::new(&hostname, app, &prefix, mac));
+
+        let mut sparkplug = config.sparkplug_group_id.and_then(|group_id| {
+            let node = SparkplugNode::new(group_id, &hostname);
+            if node.is_none() {
+                log::warn!(
+                    "Sparkplug disabled, the topics of group `{}` are too long",
+                    group_id
+                );
+            }
+            node
+        });
+        if let Some(node) = sparkplug.as_mut() {
+            // Register NDEATH before the first connection
+            node.update(&mut mqtt, &settings, false);
+        }
+
+        let syslog = config.syslog.map(|syslog| {
+            SyslogClient::new(
+                stack_manager.acquire_stack(),
+                SocketAddr::new(Ipv4Addr::from(syslog.ip_address.0).into(), syslog.port),
+                &hostname,
+                app,
+            )
+        });
+
+        let sntp = config.ntp.as_ref().map(|ntp| {
+            SntpClient::new(
+                stack_manager.acquire_stack(),
+                clock,
+                ntp.servers.iter().map(|ip| Ipv4Addr::from(ip.0)).collect(),
+                ntp.poll_interval_secs,
+            )
+        });
+
+        let mdns = MdnsResponder::new(
+            stack_manager.acquire_stack(),
             clock,
+            sockets.mdns,
+            &hostname,
+            config.ip_address,
+            &prefix,
+            mac,
         )
-        .unwrap();
+        .map_err(|e| log::warn!("mDNS responder disabled: {:?}", e))
+        .ok();
 
-        let telemetry = TelemetryClient::new(
+        let ping = PingMonitor::new(
             stack_manager.acquire_stack(),
             clock,
-            &get_client_id(app, "tlm", mac),
-            &prefix,
-            broker,
+            sockets.icmp,
+            u16::from_be_bytes([mac.0[4], mac.0[5]]),
+            config.broker_ip_address,
+        );
+
+        let slaac = Ipv6Autoconf::new(stack_manager.acquire_stack(), clock, sockets.icmpv6, mac);
+
+        let connection = ConnectionMonitor::new(
+            clock,
+            IpEndpoint::new(config.broker_ip_address, MQTT_PORT),
+            config.mqtt_backoff,
         );
 
         NetworkUsers {
-            miniconf: settings,
+            mqtt,
+            settings,
             processor,
             telemetry,
+            discovery,
+            sparkplug,
+            syslog,
+            sntp,
+            mdns,
+            ping,
+            slaac,
+            connection,
+            connected: false,
         }
     }
 
+    pub fn publish_telemetry(&mut self, telemetry: &T) {
+        match self.sparkplug.as_mut() {
+            Some(node) => node.publish(&mut self.mqtt, telemetry),
+            None => self.telemetry.publish(&mut self.mqtt, telemetry),
+        }
+    }
+
+    /// Whether the MQTT session with the broker is established.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Publish a change of a digital input.
+    ///
+    /// # Args
+    /// * `name` - The name of the input.
+    /// * `event` - The new state of the input.
+    pub fn publish_input_event(&mut self, name: &str, event: &InputEvent) {
+        self.telemetry
+            .publish_input_event(&mut self.mqtt, name, event);
+    }
+
     pub fn update(&mut self) -> NetworkState {
-        // Update the MQTT clients.
-        self.telemetry.update();
+        // Update the MQTT session.
+        let settings_changed = self.update_mqtt();
+        self.update_connection();
+
+        if let Some(syslog) = self.syslog.as_mut() {
+            syslog.update();
+        }
+        if let Some(sntp) = self.sntp.as_mut() {
+            sntp.update();
+        }
+        if let Some(mdns) = self.mdns.as_mut() {
+            mdns.update();
+        }
+        self.ping.update();
+        self.slaac.update();
 
         // Poll for incoming data.
         let poll_result = match self.processor.update() {
@@ -89,30 +257,149 @@ where
             UpdateState::Updated => NetworkState::Updated,
         };
 
-        match self.miniconf.update() {
-            Ok(true) => NetworkState::SettingsChanged,
-            _ => poll_result,
+        if settings_changed {
+            NetworkState::SettingsChanged
+        } else {
+            poll_result
         }
     }
+
+    /// Poll the MQTT session and dispatch incoming messages to their handler.
+    ///
+    /// # Returns
+    /// True if the settings were changed.
+    fn update_mqtt(&mut self) -> bool {
+        let settings = &mut self.settings;
+        let sparkplug = &mut self.sparkplug;
+        let mut settings_changed = false;
+
+        // Without session, polling reconnects, which is paced by the backoff
+        let poll_due = self.connection.mqtt_poll_due();
+        let result = if !poll_due {
+            Ok(())
+        } else {
+            self.mqtt.poll(|client, topic, message, properties| {
+                if let Some(node) = sparkplug.as_mut() {
+                    if let Some(changed) = node.handle(topic, message, settings) {
+                        settings_changed |= changed;
+                        return;
+                    }
+                }
+
+                let response = match settings.handle(topic, message) {
+                    Some(response) => response,
+                    None => {
+                        log::debug!("Unexpected message on `{}`", topic);
+                        return;
+                    }
+                };
+                settings_changed |= response.is_ok();
+
+                let response_topic = properties.iter().find_map(|property| match property {
+                    Property::ResponseTopic(topic) => Some(*topic),
+                    _ => None,
+                });
+                if let Some(response_topic) = response_topic {
+                    let correlation: heapless::Vec<Property, 1> = properties
+                        .iter()
+                        .filter(|property| matches!(property, Property::CorrelationData(_)))
+                        .cloned()
+                        .collect();
+                    let response: heapless::Vec<u8, 128> =
+                        serde_json_core::to_vec(&response).unwrap();
+                    client
+                        .publish(
+                            response_topic,
+                            &response,
+                            QoS::AtMostOnce,
+                            Retain::NotRetained,
+                            &correlation,
+                        )
+                        .ok();
+                }
+            })
+        };
+
+        match result {
+            Ok(_) | Err(minimq::Error::Network(smoltcp_nal::NetworkError::NoIpAddress)) => {}
+            Err(error) => log::info!("Unexpected error: {:?}", error),
+        }
+
+        let connected = self.mqtt.client.is_connected();
+        if !connected {
+            self.settings.reset_subscription();
+        } else if !self.connected {
+            log::info!("MQTT connected, subscribing to settings");
+            let mut diagnostics = self.processor.diagnostics();
+            self.telemetry
+                .publish_diagnostics(&mut self.mqtt, &mut diagnostics);
+            if self.sparkplug.is_none() {
+                self.telemetry.restart_schema();
+            }
+            if let Some(discovery) = self.discovery.as_mut() {
+                discovery.restart();
+            }
+        }
+        self.connected = connected;
+
+        if let Some(node) = self.sparkplug.as_mut() {
+            node.update(&mut self.mqtt, &self.settings, connected);
+        }
+
+        if connected {
+            self.telemetry.update_schema(&mut self.mqtt);
+            if let Some(discovery) = self.discovery.as_mut() {
+                discovery.update(&mut self.mqtt);
+            }
+            if let Some(topic) = self.settings.unsent_subscription() {
+                if self.mqtt.client.subscribe(topic, &[]).is_ok() {
+                    self.settings.subscription_sent();
+                }
+            } else if !self.mqtt.client.subscriptions_pending() {
+                // minimq only tracks whether any SUBACK is outstanding, so this waits for
+                // the Sparkplug NCMD subscription too
+                self.settings.confirm_subscription();
+            }
+        }
+
+        settings_changed
+    }
+
+    /// Sample the state of the connection to the broker.
+    fn update_connection(&mut self) {
+        let broker = self.connection.broker();
+        let state = if !self.processor.link_up() {
+            ConnectionState::Down
+        } else if !self.processor.has_address_for(broker.addr) {
+            ConnectionState::Link
+        } else if self.mqtt.client.is_connected() {
+            if self.settings.is_subscribed() {
+                ConnectionState::Subscribed
+            } else {
+                ConnectionState::MqttConnected
+            }
+        } else if self.processor.is_tcp_connected(broker) {
+            ConnectionState::TcpConnected
+        } else {
+            ConnectionState::IpConfigured
+        };
+        self.connection.update(state);
+    }
 }
 
-/// Get an MQTT client ID for a client.
+/// Get the host name of a device.
 ///
 /// # Args
-/// * `app` - The name of the application
-/// * `client` - The unique tag of the client
-/// * `mac` - The MAC address of the device.
+/// * `app` - The name of the application that is executing.
+/// * `mac` - The ethernet MAC address of the device.
 ///
 /// # Returns
-/// A client ID that may be used for MQTT client identification.
-fn get_client_id(
-    app: &str,
-    client: &str,
-    mac: smoltcp_nal::smoltcp::wire::EthernetAddress,
-) -> String<64> {
-    let mut identifier = String::new();
-    write!(&mut identifier, "{}-{}-{}", app, mac, client).unwrap();
-    identifier
+/// The host name used to identify this device, e.g. as the MQTT client ID, in syslog
+/// messages and mDNS.
+pub fn get_hostname(app: &str, mac: smoltcp_nal::smoltcp::wire::EthernetAddress) -> String<64> {
+    let mut hostname = String::new();
+    write!(&mut hostname, "{}-{}", app, mac).unwrap();
+    hostname
 }
 
 /// Get the MQTT prefix of a device.
@@ -120,17 +407,27 @@ fn get_client_id(
 /// # Args
 /// * `app` - The name of the application that is executing.
 /// * `mac` - The ethernet MAC address of the device.
+/// * `config` - The topic template and the values of its placeholders.
 ///
 /// # Returns
-/// The MQTT prefix used for this device.
+/// The MQTT prefix used for this device, from the default template if `config` is invalid.
 pub fn get_device_prefix(
     app: &str,
     mac: smoltcp_nal::smoltcp::wire::EthernetAddress,
-) -> String<128> {
-    // Note(unwrap): The mac address + binary name must be short enough to fit into this string. If
-    // they are defined too long, this will panic and the device will fail to boot.
-    let mut prefix: String<128> = String::new();
-    write!(&mut prefix, "dt/dummy/{}/{}", app, mac).unwrap();
-
-    prefix
+    config: &TopicConfig,
+) -> String<DEVICE_PREFIX_MAX> {
+    match topic::expand(config, app, mac) {
+        Ok(prefix) => prefix,
+        Err(error) => {
+            log::warn!(
+                "Topic template `{}` is invalid ({:?}), using `{}`",
+                config.template,
+                error,
+                TOPIC_DEFAULT_TEMPLATE
+            );
+            // Note(unwrap): The default template only holds the binary name and the mac
+            // address, which fit into the prefix.
+            topic::expand(&TopicConfig::default(), app, mac).unwrap()
+        }
+    }
 }
@@ -1,24 +1,43 @@
 use super::{NetworkReference, UpdateState};
 use crate::hardware::{
     gpio::{PhyMdcPin, PhyMdioPin},
+    network_clock::NetworkClock,
     phy::Phy,
 };
+use core::fmt::Write;
+use heapless::{String, Vec};
 use log::warn;
+pub use mqtt_rtic_common::diagnostics::{NetworkDiagnostics, RouteEntry};
+use smoltcp_nal::smoltcp::{
+    socket::{Socket, TcpState},
+    time::Instant,
+    wire::{IpAddress, IpEndpoint},
+};
+use stm32f4xx_hal::hal::blocking::delay::DelayMs;
 
 pub struct NetworkProcessor {
     stack: NetworkReference,
+    clock: NetworkClock,
     mdio: PhyMdioPin,
     mdc: PhyMdcPin,
     network_was_reset: bool,
+    link_up: bool,
 }
 
 impl NetworkProcessor {
-    pub fn new(stack: NetworkReference, mdio: PhyMdioPin, mdc: PhyMdcPin) -> Self {
+    pub fn new(
+        stack: NetworkReference,
+        clock: NetworkClock,
+        mdio: PhyMdioPin,
+        mdc: PhyMdcPin,
+    ) -> Self {
         Self {
             stack,
+            clock,
             mdio,
             mdc,
             network_was_reset: false,
+            link_up: false,
         }
     }
 
@@ -43,14 +62,116 @@ impl NetworkProcessor {
             }
             _ => {}
         };
+        self.link_up = link_up;
         link_up
     }
 
+    /// The link status of the last `handle_link`.
+    pub fn link_up(&self) -> bool {
+        self.link_up
+    }
+
+    /// Whether the interface has an address to reach a remote address from.
+    pub fn has_address_for(&mut self, remote: IpAddress) -> bool {
+        self.stack.lock(|stack| {
+            stack
+                .interface_mut()
+                .ip_addrs()
+                .iter()
+                .any(|cidr| match (cidr.address(), remote) {
+                    (IpAddress::Ipv4(local), IpAddress::Ipv4(_)) => !local.is_unspecified(),
+                    (IpAddress::Ipv6(local), IpAddress::Ipv6(remote)) => {
+                        !local.is_unspecified()
+                            && (remote.is_link_local() || !local.is_link_local())
+                    }
+                    _ => false,
+                })
+        })
+    }
+
+    /// Whether a TCP connection to a remote endpoint is established.
+    pub fn is_tcp_connected(&mut self, remote: IpEndpoint) -> bool {
+        self.stack.lock(|stack| {
+            stack
+                .interface_mut()
+                .sockets()
+                .any(|(_, socket)| match socket {
+                    Socket::Tcp(socket) => {
+                        socket.state() == TcpState::Established
+                            && socket.remote_endpoint() == remote
+                    }
+                    _ => false,
+                })
+        })
+    }
+
+    /// Close the sockets and restart the address configuration, as on a link loss.
+    pub fn reset_stack(&mut self) {
+        self.stack.lock(|stack| stack.handle_link_reset());
+    }
+
+    /// Reset and set up the PHY again, the link goes down meanwhile.
+    pub fn reset_phy<D: DelayMs<u32>>(&mut self, delay: &mut D) {
+        self.stack.lock(|stack| {
+            let smi = stack
+                .interface_mut()
+                .device_mut()
+                .smi(&mut self.mdio, &mut self.mdc);
+            let phy = Phy::new(smi);
+            phy.reset(delay);
+            phy.setup();
+        });
+    }
+
     pub fn handle_interrupt(&mut self) {
         self.stack
             .lock(|stack| stack.interface_mut().device_mut().interrupt_handler());
     }
 
+    pub fn diagnostics(&mut self) -> NetworkDiagnostics {
+        self.stack.lock(|stack| {
+            let iface = stack.interface_mut();
+            let mut diagnostics = NetworkDiagnostics {
+                addresses: Vec::new(),
+                routes: Vec::new(),
+                omitted_routes: 0,
+            };
+            for cidr in iface.ip_addrs().iter() {
+                let mut address = String::new();
+                // Note(ok): The formatted addresses always fit
+                write!(&mut address, "{}", cidr).ok();
+                diagnostics.addresses.push(address).ok();
+            }
+            iface.routes_mut().update(|routes| {
+                for (cidr, route) in routes.iter() {
+                    let mut entry = RouteEntry {
+                        destination: String::new(),
+                        via: String::new(),
+                    };
+                    write!(&mut entry.destination, "{}", cidr).ok();
+                    write!(&mut entry.via, "{}", route.via_router).ok();
+                    diagnostics.routes.push(entry).ok();
+                }
+            });
+            diagnostics
+        })
+    }
+
+    /// Time until the IP stack needs to be polled again, in milliseconds.
+    ///
+    /// # Returns
+    /// `None` if the stack has no pending timers, it then only needs polling on
+    /// traffic or when a socket is used.
+    pub fn poll_delay_ms(&mut self) -> Option<u64> {
+        let now = Instant::from_millis(self.clock.now_ms() as i64);
+        self.stack.lock(|stack| {
+            stack
+                .interface_mut()
+                .poll_delay(now)
+                .map(|delay| delay.total_millis())
+        })
+    }
+
     pub fn update(&mut self) -> UpdateState {
         match self.stack.lock(|stack| stack.poll()) {
             Ok(true) => UpdateState::Updated,
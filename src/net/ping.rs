@@ -0,0 +1,304 @@
+//! ICMP echo monitor for gateway and broker reachability.
+//!
+//! # Design
+//!  Each target is probed once per interval through a dedicated smoltcp ICMP socket.
+//!  The echo payload carries the monotonic send time, so replies can be matched
+//!  without keeping per-probe state beyond the outstanding sequence number.
+//!  Statistics are computed over a window of the most recent probes.
+//!
+//!  The gateway is taken from the IPv4 default route of the interface and is skipped
+//!  while no default gateway is configured. The broker is probed with ICMPv6 echo when
+//!  it has an IPv6 address: the socket fills in the source address and recomputes the
+//!  checksum when the request is sent, so it is emitted from the unspecified address.
+use super::NetworkReference;
+use crate::hardware::network_clock::NetworkClock;
+pub use mqtt_rtic_common::telemetry::{PingStats, Reachability};
+use smoltcp_nal::smoltcp::{
+    iface::SocketHandle,
+    phy::ChecksumCapabilities,
+    socket::{IcmpEndpoint, IcmpSocket},
+    wire::{Icmpv4Packet, Icmpv4Repr, Icmpv6Packet, Icmpv6Repr, IpAddress, IpCidr, Ipv6Address},
+};
+
+const PING_INTERVAL_MS: u64 = 5_000;
+const PING_TIMEOUT_MS: u64 = 2_000;
+const WINDOW_SIZE: usize = 16;
+
+const TARGET_GATEWAY: usize = 0;
+const TARGET_BROKER: usize = 1;
+const NUM_TARGETS: usize = 2;
+
+#[derive(Copy, Clone)]
+struct Probe {
+    seq_no: u16,
+    sent_ms: u64,
+}
+
+struct Target {
+    address: Option<IpAddress>,
+    pending: Option<Probe>,
+    /// Round-trip time of the most recent probes, `None` for lost probes.
+    results: [Option<u32>; WINDOW_SIZE],
+    index: usize,
+    count: usize,
+}
+
+impl Target {
+    const fn new(address: Option<IpAddress>) -> Self {
+        Self {
+            address,
+            pending: None,
+            results: [None; WINDOW_SIZE],
+            index: 0,
+            count: 0,
+        }
+    }
+
+    fn record(&mut self, rtt_ms: Option<u32>) {
+        self.results[self.index] = rtt_ms;
+        self.index = (self.index + 1) % WINDOW_SIZE;
+        self.count = WINDOW_SIZE.min(self.count + 1);
+    }
+
+    fn stats(&self) -> PingStats {
+        let results = &self.results[..self.count];
+        let (received, sum, min, max) = results
+            .iter()
+            .flatten()
+            .fold((0, 0, u32::MAX, 0), |(received, sum, min, max), &rtt| {
+                (received + 1, sum + rtt, min.min(rtt), max.max(rtt))
+            });
+        if received == 0 {
+            return PingStats {
+                samples: self.count as u8,
+                loss_percent: if self.count == 0 { 0 } else { 100 },
+                ..Default::default()
+            };
+        }
+        PingStats {
+            samples: self.count as u8,
+            loss_percent: (100 * (self.count as u32 - received) / self.count as u32) as u8,
+            rtt_min_ms: min,
+            rtt_avg_ms: sum / received,
+            rtt_max_ms: max,
+        }
+    }
+}
+
+pub struct PingMonitor {
+    stack: NetworkReference,
+    clock: NetworkClock,
+    handle: SocketHandle,
+    ident: u16,
+    seq_no: u16,
+    targets: [Target; NUM_TARGETS],
+    next_probe_ms: u64,
+}
+
+impl PingMonitor {
+    /// Construct a new ping monitor.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    /// * `clock` - The clock used to schedule probes and measure round-trip time.
+    /// * `handle` - The ICMP socket dedicated to the monitor.
+    /// * `ident` - The ICMP echo identifier.
+    /// * `broker` - The IPv4 or IPv6 address of the MQTT broker.
+    pub fn new(
+        stack: NetworkReference,
+        clock: NetworkClock,
+        handle: SocketHandle,
+        ident: u16,
+        broker: IpAddress,
+    ) -> Self {
+        Self {
+            stack,
+            clock,
+            handle,
+            ident,
+            seq_no: 0,
+            targets: [Target::new(None), Target::new(Some(broker))],
+            next_probe_ms: 0,
+        }
+    }
+
+    pub fn reachability(&self) -> Reachability {
+        Reachability {
+            gateway: self.targets[TARGET_GATEWAY].stats(),
+            broker: self.targets[TARGET_BROKER].stats(),
+        }
+    }
+
+    pub fn update(&mut self) {
+        let now = self.clock.now_ms();
+        let handle = self.handle;
+        let ident = self.ident;
+
+        let gateway = self.stack.lock(|stack| {
+            let iface = stack.interface_mut();
+            let socket = iface.get_socket::<IcmpSocket>(handle);
+            if !socket.is_open() {
+                socket.bind(IcmpEndpoint::Ident(ident)).ok();
+            }
+            let mut gateway = None;
+            iface.routes_mut().update(|routes| {
+                gateway = routes
+                    .iter()
+                    .find_map(|(cidr, route)| match (cidr, route.via_router) {
+                        (IpCidr::Ipv4(cidr), IpAddress::Ipv4(router))
+                            if cidr.prefix_len() == 0 && !router.is_unspecified() =>
+                        {
+                            Some(IpAddress::Ipv4(router))
+                        }
+                        _ => None,
+                    });
+            });
+            gateway
+        });
+        self.targets[TARGET_GATEWAY].address = gateway;
+
+        self.receive_replies(now);
+
+        for target in self.targets.iter_mut() {
+            if let Some(probe) = target.pending {
+                if now - probe.sent_ms > PING_TIMEOUT_MS {
+                    target.pending = None;
+                    target.record(None);
+                }
+            }
+        }
+
+        if now >= self.next_probe_ms {
+            self.next_probe_ms = now + PING_INTERVAL_MS;
+            for i in 0..NUM_TARGETS {
+                self.send_probe(i, now);
+            }
+        }
+    }
+
+    fn send_probe(&mut self, target: usize, now: u64) {
+        let address = match self.targets[target].address {
+            Some(address) if self.targets[target].pending.is_none() => address,
+            _ => return,
+        };
+
+        self.seq_no = self.seq_no.wrapping_add(1);
+        let seq_no = self.seq_no;
+        let ident = self.ident;
+        let payload = now.to_be_bytes();
+
+        let handle = self.handle;
+        let sent = self.stack.lock(|stack| {
+            let socket = stack.interface_mut().get_socket::<IcmpSocket>(handle);
+            match address {
+                IpAddress::Ipv6(dst_addr) => {
+                    let repr = Icmpv6Repr::EchoRequest {
+                        ident,
+                        seq_no,
+                        data: &payload,
+                    };
+                    let buffer = socket.send(repr.buffer_len(), address).ok()?;
+                    repr.emit(
+                        &Ipv6Address::UNSPECIFIED.into(),
+                        &dst_addr.into(),
+                        &mut Icmpv6Packet::new_unchecked(buffer),
+                        &ChecksumCapabilities::default(),
+                    );
+                }
+                _ => {
+                    let repr = Icmpv4Repr::EchoRequest {
+                        ident,
+                        seq_no,
+                        data: &payload,
+                    };
+                    let buffer = socket.send(repr.buffer_len(), address).ok()?;
+                    repr.emit(
+                        &mut Icmpv4Packet::new_unchecked(buffer),
+                        &ChecksumCapabilities::default(),
+                    );
+                }
+            }
+            Some(())
+        });
+
+        if sent.is_some() {
+            self.targets[target].pending = Some(Probe {
+                seq_no,
+                sent_ms: now,
+            });
+        }
+    }
+
+    fn receive_replies(&mut self, now: u64) {
+        let handle = self.handle;
+        let ident = self.ident;
+        loop {
+            let reply = self.stack.lock(|stack| {
+                let socket = stack.interface_mut().get_socket::<IcmpSocket>(handle);
+                let (payload, source) = socket.recv().ok()?;
+                Some(parse_echo_reply(payload, source, ident))
+            });
+
+            let (seq_no, sent_ms) = match reply {
+                Some(Some(reply)) => reply,
+                Some(None) => continue,
+                None => break,
+            };
+
+            let target = self
+                .targets
+                .iter_mut()
+                .find(|t| matches!(t.pending, Some(p) if p.seq_no == seq_no));
+            if let Some(target) = target {
+                target.pending = None;
+                target.record(Some(now.saturating_sub(sent_ms) as u32));
+            }
+        }
+    }
+}
+
+/// Parse an ICMP or ICMPv6 echo reply to one of our requests.
+///
+/// # Returns
+/// The sequence number and the send time carried by the reply.
+fn parse_echo_reply(payload: &[u8], source: IpAddress, ident: u16) -> Option<(u16, u64)> {
+    let (reply_ident, seq_no, data) = match source {
+        IpAddress::Ipv6(_) => {
+            let packet = Icmpv6Packet::new_checked(payload).ok()?;
+            // The interface verified the checksum against the actual addresses
+            match Icmpv6Repr::parse(
+                &source,
+                &Ipv6Address::UNSPECIFIED.into(),
+                &packet,
+                &ChecksumCapabilities::ignored(),
+            )
+            .ok()?
+            {
+                Icmpv6Repr::EchoReply {
+                    ident,
+                    seq_no,
+                    data,
+                } => (ident, seq_no, data),
+                _ => return None,
+            }
+        }
+        _ => {
+            let packet = Icmpv4Packet::new_checked(payload).ok()?;
+            match Icmpv4Repr::parse(&packet, &ChecksumCapabilities::default()).ok()? {
+                Icmpv4Repr::EchoReply {
+                    ident,
+                    seq_no,
+                    data,
+                } => (ident, seq_no, data),
+                _ => return None,
+            }
+        }
+    };
+
+    if reply_ident != ident || data.len() != 8 {
+        return None;
+    }
+    let mut sent_ms = [0; 8];
+    sent_ms.copy_from_slice(data);
+    Some((seq_no, u64::from_be_bytes(sent_ms)))
+}
@@ -0,0 +1,87 @@
+//! IP stack polling instrumentation.
+//!
+//! # Design
+//!  Measured with the DWT cycle counter, which must be enabled at boot.
+//!  The counter wraps after ~23 s at 180 MHz, so the statistics must be taken at
+//!  least that often (telemetry takes them every second).
+//!
+//!  * CPU load is the fraction of cycles spent in `poll_ip_stack`
+//!  * Latency is the time from the ETH interrupt to the start of the next poll
+use cortex_m::peripheral::DWT;
+pub use mqtt_rtic_common::telemetry::PollStats;
+
+pub struct PollStatistics {
+    cycles_per_us: u32,
+    window_start: u32,
+    busy_cycles: u64,
+    polls: u32,
+    interrupt_at: Option<u32>,
+    latency_sum: u64,
+    latency_count: u32,
+    latency_max: u32,
+}
+
+impl PollStatistics {
+    pub fn new(cycles_per_us: u32) -> Self {
+        Self {
+            cycles_per_us,
+            window_start: DWT::cycle_count(),
+            busy_cycles: 0,
+            polls: 0,
+            interrupt_at: None,
+            latency_sum: 0,
+            latency_count: 0,
+            latency_max: 0,
+        }
+    }
+
+    /// Record an ETH interrupt.
+    pub fn interrupt(&mut self) {
+        self.interrupt_at.get_or_insert_with(DWT::cycle_count);
+    }
+
+    /// Start a poll.
+    ///
+    /// # Returns
+    /// The cycle count at the start of the poll, to be passed to `poll_done`.
+    pub fn poll_start(&mut self) -> u32 {
+        let now = DWT::cycle_count();
+        if let Some(interrupt_at) = self.interrupt_at.take() {
+            let latency = now.wrapping_sub(interrupt_at);
+            self.latency_sum += u64::from(latency);
+            self.latency_count += 1;
+            self.latency_max = self.latency_max.max(latency);
+        }
+        now
+    }
+
+    pub fn poll_done(&mut self, start: u32) {
+        self.busy_cycles += u64::from(DWT::cycle_count().wrapping_sub(start));
+        self.polls += 1;
+    }
+
+    /// Take the statistics since the previous call.
+    pub fn take(&mut self) -> PollStats {
+        let now = DWT::cycle_count();
+        let elapsed = u64::from(now.wrapping_sub(self.window_start)).max(1);
+        let cycles_per_us = u64::from(self.cycles_per_us);
+        let stats = PollStats {
+            polls: self.polls,
+            cpu_load_permille: (self.busy_cycles * 1000 / elapsed) as u32,
+            latency_avg_us: self
+                .latency_sum
+                .checked_div(u64::from(self.latency_count))
+                .map(|avg| (avg / cycles_per_us) as u32)
+                .unwrap_or(0),
+            latency_max_us: self.latency_max / self.cycles_per_us,
+        };
+
+        self.window_start = now;
+        self.busy_cycles = 0;
+        self.polls = 0;
+        self.latency_sum = 0;
+        self.latency_count = 0;
+        self.latency_max = 0;
+        stats
+    }
+}
@@ -0,0 +1,200 @@
+//! Settings over the shared MQTT session.
+//!
+//! # Design
+//!  Settings are written by publishing a JSON value to `<prefix>/settings/<path>`,
+//!  where `<path>` is the miniconf path of the setting. When the request carries a
+//!  response topic, the outcome is published there along with its correlation data.
+//!
+//!  Aliases name settings whose path is only known at run time, e.g. `outputs/relay1`
+//!  for `outputs/0`. Settings are reported under their alias.
+use core::str::FromStr;
+use heapless::{String, Vec};
+use log::{info, warn};
+use miniconf::Miniconf;
+use serde::Serialize;
+
+const SETTINGS_DEPTH_MAX: usize = 4;
+const SETTINGS_PATH_SIZE_MAX: usize = 64;
+const SETTINGS_VALUE_SIZE_MAX: usize = 128;
+const SETTINGS_ALIASES_MAX: usize = 8;
+
+type SettingsPath = String<SETTINGS_PATH_SIZE_MAX>;
+
+/// The outcome of a settings request.
+#[derive(Serialize)]
+pub struct Response {
+    code: u8,
+    msg: String<64>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self {
+            code: 0,
+            msg: String::from("OK"),
+        }
+    }
+
+    fn error(error: miniconf::Error) -> Self {
+        let mut msg = String::new();
+        // A truncated message is acceptable, the code identifies the failure.
+        core::fmt::write(&mut msg, format_args!("{:?}", error)).ok();
+        Self { code: 1, msg }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// The progress of the settings subscription in the MQTT session.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Subscription {
+    /// No SUBSCRIBE was sent in this session.
+    Unsent,
+    /// SUBSCRIBE was sent, the broker hasn't acknowledged it yet.
+    Pending,
+    /// The broker acknowledged the subscription with a SUBACK.
+    Confirmed,
+}
+
+pub struct SettingsHandler<S: Default + Miniconf> {
+    settings: S,
+    subscription: String<128>,
+    subscribed: Subscription,
+    /// Pairs of an alias and the path it names.
+    aliases: Vec<(SettingsPath, SettingsPath), SETTINGS_ALIASES_MAX>,
+}
+
+impl<S: Default + Miniconf> SettingsHandler<S> {
+    /// Construct a new settings handler.
+    ///
+    /// # Args
+    /// * `prefix` - The MQTT prefix of the device.
+    pub fn new(prefix: &str) -> Self {
+        let mut subscription: String<128> = String::from(prefix);
+        subscription.push_str("/settings/#").unwrap();
+
+        Self {
+            settings: S::default(),
+            subscription,
+            subscribed: Subscription::Unsent,
+            aliases: Vec::new(),
+        }
+    }
+
+    /// Replace the settings, e.g. with defaults from the configuration.
+    pub fn set_settings(&mut self, settings: S) {
+        self.settings = settings;
+    }
+
+    /// Name a setting by an alias.
+    ///
+    /// # Args
+    /// * `alias` - The alternative path of the setting.
+    /// * `path` - The miniconf path of the setting.
+    pub fn add_alias(&mut self, alias: &str, path: &str) {
+        let alias_path = (SettingsPath::from_str(alias), SettingsPath::from_str(path));
+        let pushed = match alias_path {
+            (Ok(alias), Ok(path)) => self.aliases.push((alias, path)).is_ok(),
+            _ => false,
+        };
+        if !pushed {
+            warn!("Settings alias `{}` for `{}` dropped", alias, path);
+        }
+    }
+
+    pub fn settings(&self) -> &S {
+        &self.settings
+    }
+
+    /// The topic filter to subscribe to, while no SUBSCRIBE was sent in the session.
+    pub fn unsent_subscription(&self) -> Option<&str> {
+        (self.subscribed == Subscription::Unsent).then_some(&self.subscription)
+    }
+
+    /// Record that SUBSCRIBE was sent, pending its acknowledgement.
+    pub fn subscription_sent(&mut self) {
+        self.subscribed = Subscription::Pending;
+    }
+
+    /// Record that the broker acknowledged the pending subscription.
+    pub fn confirm_subscription(&mut self) {
+        if self.subscribed == Subscription::Pending {
+            info!("Subscribed to settings");
+            self.subscribed = Subscription::Confirmed;
+        }
+    }
+
+    /// Forget the subscription, e.g. when the session ends.
+    pub fn reset_subscription(&mut self) {
+        self.subscribed = Subscription::Unsent;
+    }
+
+    /// Whether the broker acknowledged the settings subscription in this session.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscribed == Subscription::Confirmed
+    }
+
+    /// Apply a settings request.
+    ///
+    /// # Args
+    /// * `topic` - The topic the request was published on.
+    /// * `message` - The JSON value of the setting.
+    ///
+    /// # Returns
+    /// The outcome of the request, or `None` if the topic isn't a settings topic.
+    pub fn handle(&mut self, topic: &str, message: &[u8]) -> Option<Response> {
+        // Note(unwrap): The subscription always ends in `#`
+        let base = self.subscription.strip_suffix('#').unwrap();
+        let path = topic.strip_prefix(base)?;
+        Some(self.apply(path, message))
+    }
+
+    /// Apply a setting.
+    ///
+    /// # Args
+    /// * `path` - The miniconf path of the setting.
+    /// * `value` - The JSON value of the setting.
+    pub fn apply(&mut self, path: &str, value: &[u8]) -> Response {
+        let target = self
+            .aliases
+            .iter()
+            .find(|(alias, _)| alias == path)
+            .map_or(path, |(_, target)| target.as_str());
+        match self.settings.set(target, value) {
+            Ok(()) => {
+                info!("Settings update: `{}`", path);
+                Response::ok()
+            }
+            Err(error) => {
+                warn!("Settings update `{}` failed: {:?}", path, error);
+                Response::error(error)
+            }
+        }
+    }
+
+    /// Visit every setting.
+    ///
+    /// # Args
+    /// * `f` - Called with the path and JSON value of every setting.
+    pub fn for_each<F: FnMut(&str, &[u8])>(&self, mut f: F) {
+        let mut state = [0; SETTINGS_DEPTH_MAX];
+        let mut value = [0; SETTINGS_VALUE_SIZE_MAX];
+        // Note(unwrap): The path size and depth limits cover the settings tree
+        let paths = self
+            .settings
+            .into_iter::<SETTINGS_PATH_SIZE_MAX>(&mut state)
+            .unwrap();
+        for path in paths {
+            if let Ok(len) = self.settings.get(&path, &mut value) {
+                let alias = self
+                    .aliases
+                    .iter()
+                    .find(|(_, target)| *target == path)
+                    .map_or(path.as_str(), |(alias, _)| alias.as_str());
+                f(alias, &value[..len]);
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! IPv6 stateless address autoconfiguration (RFC 4862).
+//!
+//! # Design
+//!  The link-local address is derived from the MAC address (modified EUI-64) and
+//!  configured at boot. Router solicitations are sent through a dedicated raw
+//!  ICMPv6 socket, which also receives a copy of every router advertisement.
+//!  smoltcp keeps handling neighbor discovery itself.
+//!
+//!  The interface addresses are kept ordered so that the preferred source address
+//!  comes first among the IPv6 addresses, since smoltcp picks the first address of
+//!  the matching version as the source of outgoing connections:
+//!  * without a global address: `[ipv4, link-local, ::/0]`
+//!  * with a global address: `[ipv4, global, link-local]`
+//!
+//!  The global address and the default route have independent lifetimes: the address
+//!  expires with the valid lifetime of its prefix (RFC 4862), the router lifetime only
+//!  adds or removes the default route (RFC 4861), a router advertising a zero lifetime
+//!  isn't a default router but may still advertise the prefix.
+use super::NetworkReference;
+use crate::hardware::network_clock::NetworkClock;
+use log::info;
+use smoltcp_nal::smoltcp::{
+    iface::SocketHandle,
+    phy::ChecksumCapabilities,
+    socket::RawSocket,
+    wire::{
+        EthernetAddress, Icmpv6Packet, Icmpv6Repr, IpAddress, IpCidr, IpProtocol, Ipv6Address,
+        Ipv6Cidr, Ipv6Packet, Ipv6Repr, NdiscPrefixInfoFlags, NdiscRepr,
+    },
+};
+
+pub const IPV4_ADDRESS_INDEX: usize = 0;
+pub const IPV6_PREFERRED_ADDRESS_INDEX: usize = 1;
+pub const IPV6_SECONDARY_ADDRESS_INDEX: usize = 2;
+
+const SOLICITATION_COUNT: u8 = 3;
+const SOLICITATION_INTERVAL_MS: u64 = 4_000;
+
+/// SLAAC requires a 64-bit prefix for the EUI-64 interface identifier.
+const SLAAC_PREFIX_LEN: u8 = 64;
+
+/// Remaining valid lifetime an unauthenticated advertisement can't shorten (RFC 4862 5.5.3 e).
+const VALID_LIFETIME_MIN_MS: u64 = 2 * 60 * 60 * 1000;
+
+/// Get the link-local address of an interface.
+///
+/// # Args
+/// * `mac` - The ethernet MAC address of the device.
+///
+/// # Returns
+/// `fe80::/64` with the modified EUI-64 interface identifier.
+pub fn link_local_address(mac: EthernetAddress) -> Ipv6Cidr {
+    let mut address = [0; 16];
+    address[..2].copy_from_slice(&[0xfe, 0x80]);
+    address[8..].copy_from_slice(&interface_identifier(mac));
+    Ipv6Cidr::new(Ipv6Address(address), SLAAC_PREFIX_LEN)
+}
+
+/// The modified EUI-64 interface identifier of a MAC address.
+fn interface_identifier(mac: EthernetAddress) -> [u8; 8] {
+    let m = mac.0;
+    [m[0] ^ 0x02, m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]]
+}
+
+#[derive(Copy, Clone)]
+struct Lease {
+    address: Ipv6Cidr,
+    expires_ms: u64,
+}
+
+#[derive(Copy, Clone)]
+struct DefaultRouter {
+    address: Ipv6Address,
+    expires_ms: u64,
+}
+
+pub struct Ipv6Autoconf {
+    stack: NetworkReference,
+    clock: NetworkClock,
+    handle: SocketHandle,
+    mac: EthernetAddress,
+    solicitations: u8,
+    next_solicitation_ms: u64,
+    lease: Option<Lease>,
+    router: Option<DefaultRouter>,
+}
+
+impl Ipv6Autoconf {
+    /// Construct a new SLAAC client.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    /// * `clock` - The clock used for solicitations and address lifetimes.
+    /// * `handle` - The raw ICMPv6 socket dedicated to router discovery.
+    /// * `mac` - The ethernet MAC address of the device.
+    pub fn new(
+        stack: NetworkReference,
+        clock: NetworkClock,
+        handle: SocketHandle,
+        mac: EthernetAddress,
+    ) -> Self {
+        Self {
+            stack,
+            clock,
+            handle,
+            mac,
+            solicitations: SOLICITATION_COUNT,
+            next_solicitation_ms: 0,
+            lease: None,
+            router: None,
+        }
+    }
+
+    /// Restart router discovery.
+    fn restart(&mut self) {
+        self.solicitations = SOLICITATION_COUNT;
+        self.next_solicitation_ms = 0;
+    }
+
+    pub fn update(&mut self) {
+        let now = self.clock.now_ms();
+
+        while let Some(advert) = self.receive_advertisement() {
+            self.handle_advertisement(now, advert);
+        }
+
+        if let Some(lease) = self.lease {
+            if now >= lease.expires_ms {
+                info!("IPv6 address {} expired", lease.address);
+                self.lease = None;
+                self.apply();
+                self.restart();
+            }
+        }
+
+        if let Some(router) = self.router {
+            if now >= router.expires_ms {
+                info!("IPv6 default router {} expired", router.address);
+                self.router = None;
+                self.apply();
+                self.restart();
+            }
+        }
+
+        let configured = self.lease.is_some() && self.router.is_some();
+        if !configured && self.solicitations != 0 && now >= self.next_solicitation_ms {
+            self.solicitations -= 1;
+            self.next_solicitation_ms = now + SOLICITATION_INTERVAL_MS;
+            self.send_solicitation();
+        }
+    }
+
+    fn handle_advertisement(&mut self, now: u64, advert: Advertisement) {
+        let current_router = self.router.map(|r| r.address);
+        if advert.router_lifetime_ms != 0 {
+            if current_router != Some(advert.router) {
+                info!("IPv6 default router {}", advert.router);
+            }
+            self.router = Some(DefaultRouter {
+                address: advert.router,
+                expires_ms: now + advert.router_lifetime_ms,
+            });
+        } else if current_router == Some(advert.router) {
+            info!("IPv6 default router {} withdrawn", advert.router);
+            self.router = None;
+        }
+
+        if let Some(prefix) = advert.prefix {
+            self.handle_prefix(now, prefix);
+        }
+        self.apply();
+    }
+
+    /// Configure the address of an advertised prefix, or update its valid lifetime.
+    fn handle_prefix(&mut self, now: u64, prefix: Prefix) {
+        let mut address = prefix.prefix.0;
+        address[8..].copy_from_slice(&interface_identifier(self.mac));
+        let address = Ipv6Cidr::new(Ipv6Address(address), SLAAC_PREFIX_LEN);
+
+        let remaining_ms = match self.lease {
+            Some(lease) if lease.address == address => lease.expires_ms.saturating_sub(now),
+            _ => {
+                if prefix.valid_lifetime_ms != 0 {
+                    info!("IPv6 address {}", address);
+                    self.lease = Some(Lease {
+                        address,
+                        expires_ms: now + prefix.valid_lifetime_ms,
+                    });
+                }
+                return;
+            }
+        };
+
+        // A short lifetime only shortens the remaining one down to two hours
+        let valid_lifetime_ms = if prefix.valid_lifetime_ms > VALID_LIFETIME_MIN_MS
+            || prefix.valid_lifetime_ms > remaining_ms
+        {
+            prefix.valid_lifetime_ms
+        } else {
+            remaining_ms.min(VALID_LIFETIME_MIN_MS)
+        };
+        self.lease = Some(Lease {
+            address,
+            expires_ms: now + valid_lifetime_ms,
+        });
+    }
+
+    /// Update the interface addresses and default route from the current lease and router.
+    fn apply(&mut self) {
+        let link_local = IpCidr::Ipv6(link_local_address(self.mac));
+        let lease = self.lease;
+        let router = self.router;
+        self.stack.lock(|stack| {
+            let iface = stack.interface_mut();
+            iface.update_ip_addrs(|addrs| match lease {
+                Some(lease) => {
+                    addrs[IPV6_PREFERRED_ADDRESS_INDEX] = IpCidr::Ipv6(lease.address);
+                    addrs[IPV6_SECONDARY_ADDRESS_INDEX] = link_local;
+                }
+                None => {
+                    addrs[IPV6_PREFERRED_ADDRESS_INDEX] = link_local;
+                    addrs[IPV6_SECONDARY_ADDRESS_INDEX] =
+                        IpCidr::Ipv6(Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0));
+                }
+            });
+            let routes = iface.routes_mut();
+            match router {
+                Some(router) => {
+                    routes.add_default_ipv6_route(router.address).ok();
+                }
+                None => {
+                    routes.remove_default_ipv6_route();
+                }
+            }
+        });
+    }
+
+    fn send_solicitation(&mut self) {
+        let src_addr = link_local_address(self.mac).address();
+        let dst_addr = Ipv6Address::LINK_LOCAL_ALL_ROUTERS;
+        let icmp_repr = Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit { lladdr: None });
+        let ip_repr = Ipv6Repr {
+            src_addr,
+            dst_addr,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: icmp_repr.buffer_len(),
+            hop_limit: 255,
+        };
+
+        let handle = self.handle;
+        self.stack.lock(|stack| {
+            let socket = stack.interface_mut().get_socket::<RawSocket>(handle);
+            if let Ok(buffer) = socket.send(ip_repr.buffer_len() + icmp_repr.buffer_len()) {
+                let mut packet = Ipv6Packet::new_unchecked(buffer);
+                ip_repr.emit(&mut packet);
+                let mut icmp_packet = Icmpv6Packet::new_unchecked(packet.payload_mut());
+                icmp_repr.emit(
+                    &src_addr.into(),
+                    &dst_addr.into(),
+                    &mut icmp_packet,
+                    &ChecksumCapabilities::default(),
+                );
+            }
+        });
+    }
+
+    fn receive_advertisement(&mut self) -> Option<Advertisement> {
+        let handle = self.handle;
+        self.stack.lock(|stack| {
+            let socket = stack.interface_mut().get_socket::<RawSocket>(handle);
+            // Skip other ICMPv6 traffic
+            loop {
+                let packet = Ipv6Packet::new_checked(socket.recv().ok()?).ok();
+                if let Some(advert) = packet.and_then(|p| Advertisement::parse(&p)) {
+                    return Some(advert);
+                }
+            }
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Prefix {
+    prefix: Ipv6Address,
+    valid_lifetime_ms: u64,
+}
+
+#[derive(Copy, Clone)]
+struct Advertisement {
+    router: Ipv6Address,
+    router_lifetime_ms: u64,
+    prefix: Option<Prefix>,
+}
+
+impl Advertisement {
+    fn parse(packet: &Ipv6Packet<&[u8]>) -> Option<Self> {
+        let ip_repr = Ipv6Repr::parse(packet).ok()?;
+        // Router advertisements must originate on-link
+        if ip_repr.next_header != IpProtocol::Icmpv6
+            || ip_repr.hop_limit != 255
+            || !ip_repr.src_addr.is_link_local()
+        {
+            return None;
+        }
+
+        let icmp_packet = Icmpv6Packet::new_checked(packet.payload()).ok()?;
+        let icmp_repr = Icmpv6Repr::parse(
+            &IpAddress::Ipv6(ip_repr.src_addr),
+            &IpAddress::Ipv6(ip_repr.dst_addr),
+            &icmp_packet,
+            &ChecksumCapabilities::default(),
+        )
+        .ok()?;
+
+        match icmp_repr {
+            Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert {
+                router_lifetime,
+                prefix_info,
+                ..
+            }) => Some(Self {
+                router: ip_repr.src_addr,
+                router_lifetime_ms: router_lifetime.total_millis(),
+                prefix: prefix_info
+                    .filter(|info| {
+                        info.prefix_len == SLAAC_PREFIX_LEN
+                            && info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+                    })
+                    .map(|info| Prefix {
+                        prefix: info.prefix,
+                        valid_lifetime_ms: info.valid_lifetime.total_millis(),
+                    }),
+            }),
+            _ => None,
+        }
+    }
+}
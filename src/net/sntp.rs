@@ -0,0 +1,190 @@
+//! SNTP (RFC 4330) client.
+//!
+//! # Design
+//!  One request is outstanding at a time. A server that doesn't answer within
+//!  the response timeout is skipped in favor of the next one in the list.
+//!  Each valid response updates the `WALL_CLOCK` offset.
+use super::NetworkReference;
+use crate::config::NTP_SERVERS_MAX;
+use crate::hardware::network_clock::NetworkClock;
+use crate::time::WALL_CLOCK;
+use heapless::Vec;
+use log::{debug, info, warn};
+use minimq::embedded_nal::{nb, Ipv4Addr, SocketAddr, UdpClientStack};
+
+const NTP_PORT: u16 = 123;
+const PACKET_SIZE: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_EPOCH_OFFSET: u64 = 2_208_988_800;
+
+const RESPONSE_TIMEOUT_MS: u64 = 2_000;
+const RETRY_INTERVAL_MS: u64 = 10_000;
+
+/// LI = 0, VN = 4, Mode = 3 (client).
+const LI_VN_MODE_CLIENT: u8 = 0b00_100_011;
+const MODE_SERVER: u8 = 4;
+
+type UdpSocket = <NetworkReference as UdpClientStack>::UdpSocket;
+
+#[derive(Copy, Clone)]
+enum State {
+    Idle { next_poll_ms: u64 },
+    Waiting { sent_ms: u64, origin: [u8; 8] },
+}
+
+pub struct SntpClient {
+    stack: NetworkReference,
+    clock: NetworkClock,
+    socket: Option<UdpSocket>,
+    servers: Vec<Ipv4Addr, NTP_SERVERS_MAX>,
+    server: usize,
+    poll_interval_ms: u64,
+    state: State,
+    synchronized: bool,
+}
+
+impl SntpClient {
+    /// Construct a new SNTP client.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    /// * `clock` - The monotonic clock that is disciplined against the servers.
+    /// * `servers` - The NTP servers to poll, in order of preference.
+    /// * `poll_interval_secs` - The time between successful polls.
+    pub fn new(
+        stack: NetworkReference,
+        clock: NetworkClock,
+        servers: Vec<Ipv4Addr, NTP_SERVERS_MAX>,
+        poll_interval_secs: u32,
+    ) -> Self {
+        Self {
+            stack,
+            clock,
+            socket: None,
+            servers,
+            server: 0,
+            poll_interval_ms: u64::from(poll_interval_secs) * 1000,
+            state: State::Idle { next_poll_ms: 0 },
+            synchronized: false,
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.servers.is_empty() {
+            return;
+        }
+
+        let now = self.clock.now_ms();
+        match self.state {
+            State::Idle { next_poll_ms } if now >= next_poll_ms => {
+                self.state = match self.send_request(now) {
+                    Some(origin) => State::Waiting {
+                        sent_ms: now,
+                        origin,
+                    },
+                    None => State::Idle {
+                        next_poll_ms: now + RETRY_INTERVAL_MS,
+                    },
+                };
+            }
+            State::Idle { .. } => {}
+            State::Waiting { sent_ms, origin } => {
+                if let Some(offset_ms) = self.receive_response(now, sent_ms, &origin) {
+                    if !self.synchronized {
+                        info!("Time synchronized, offset {} ms", offset_ms);
+                        self.synchronized = true;
+                    }
+                    WALL_CLOCK.synchronize(now, offset_ms);
+                    self.state = State::Idle {
+                        next_poll_ms: now + self.poll_interval_ms,
+                    };
+                } else if now - sent_ms > RESPONSE_TIMEOUT_MS {
+                    warn!("NTP server {} timed out", self.servers[self.server]);
+                    self.server = (self.server + 1) % self.servers.len();
+                    self.state = State::Idle {
+                        next_poll_ms: now + RETRY_INTERVAL_MS,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Send a request to the current server.
+    ///
+    /// # Returns
+    /// The transmit timestamp of the request, which the server echoes back as the
+    /// originate timestamp.
+    fn send_request(&mut self, now: u64) -> Option<[u8; 8]> {
+        if let Some(socket) = self.socket.take() {
+            self.stack.close(socket).ok();
+        }
+
+        let mut socket = self.stack.socket().ok()?;
+        let server = SocketAddr::new(self.servers[self.server].into(), NTP_PORT);
+        if self.stack.connect(&mut socket, server).is_err() {
+            self.stack.close(socket).ok();
+            return None;
+        }
+
+        // The monotonic time serves as a nonce to match the response
+        let origin = to_ntp_timestamp(now);
+        let mut request = [0; PACKET_SIZE];
+        request[0] = LI_VN_MODE_CLIENT;
+        request[40..48].copy_from_slice(&origin);
+
+        let sent = self.stack.send(&mut socket, &request).is_ok();
+        self.socket.replace(socket);
+        sent.then_some(origin)
+    }
+
+    /// Poll for a response to the outstanding request.
+    ///
+    /// # Returns
+    /// The offset of UTC from the monotonic clock, in milliseconds.
+    fn receive_response(&mut self, now: u64, sent_ms: u64, origin: &[u8; 8]) -> Option<i64> {
+        let socket = self.socket.as_mut()?;
+        let mut response = [0; PACKET_SIZE];
+        loop {
+            match self.stack.receive(socket, &mut response) {
+                Ok((size, _)) if is_valid_response(&response[..size], origin) => break,
+                Ok(_) => {}
+                Err(nb::Error::WouldBlock) | Err(nb::Error::Other(_)) => return None,
+            }
+        }
+
+        let t1 = sent_ms as i64;
+        let t2 = from_ntp_timestamp(&response[32..40]) as i64;
+        let t3 = from_ntp_timestamp(&response[40..48]) as i64;
+        let t4 = now as i64;
+        let offset = ((t2 - t1) + (t3 - t4)) / 2;
+        let delay = (t4 - t1) - (t3 - t2);
+        debug!("NTP offset {} ms, delay {} ms", offset, delay);
+        Some(offset)
+    }
+}
+
+fn is_valid_response(response: &[u8], origin: &[u8; 8]) -> bool {
+    response.len() >= PACKET_SIZE
+        && response[0] & 0x07 == MODE_SERVER
+        // Stratum 0 is a kiss-o'-death packet
+        && response[1] != 0
+        && &response[24..32] == origin
+}
+
+/// Encode milliseconds as an NTP timestamp (32.32 fixed point seconds).
+fn to_ntp_timestamp(ms: u64) -> [u8; 8] {
+    let secs = (ms / 1000) as u32;
+    let fraction = (((ms % 1000) << 32) / 1000) as u32;
+    let mut ts = [0; 8];
+    ts[..4].copy_from_slice(&secs.to_be_bytes());
+    ts[4..].copy_from_slice(&fraction.to_be_bytes());
+    ts
+}
+
+/// Decode an NTP timestamp to milliseconds since the Unix epoch.
+fn from_ntp_timestamp(ts: &[u8]) -> u64 {
+    let secs = u64::from(u32::from_be_bytes([ts[0], ts[1], ts[2], ts[3]]));
+    let fraction = u64::from(u32::from_be_bytes([ts[4], ts[5], ts[6], ts[7]]));
+    secs.saturating_sub(NTP_UNIX_EPOCH_OFFSET) * 1000 + ((fraction * 1000) >> 32)
+}
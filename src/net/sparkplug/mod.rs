@@ -0,0 +1,353 @@
+//! Sparkplug B edge node.
+//!
+//! # Design
+//!  The device is an edge node without devices, with `<app>-<mac>` as its edge node ID
+//!  in the configured group. It shares the MQTT session with the other MQTT users:
+//!  * NDEATH is registered as the will of the session, with the birth/death sequence
+//!    number (bdSeq) of the upcoming connection. bdSeq advances on every disconnect.
+//!  * NBIRTH is published on every connection and on a `Node Control/Rebirth` command.
+//!    It declares `bdSeq`, `Node Control/Rebirth`, every setting as `settings/<path>`
+//!    and every telemetry field, named after its path.
+//!  * Telemetry is published as NDATA, unset fields as null metrics, so that NDATA
+//!    carries exactly the metrics of NBIRTH. The message sequence number starts at 0
+//!    with NBIRTH and wraps after 255.
+//!  * NCMD writes to `settings/<path>` metrics are applied to the settings. Boolean and
+//!    integer values are converted to JSON, `Text` values are used as JSON as is and
+//!    `String` values are quoted. Changed settings are reported back in an NDATA.
+//!
+//!  Metrics are always sent with their names; aliases aren't used. The payloads are
+//!  encoded by the common crate, whose tests check NBIRTH against the message size.
+use super::{settings_handler::SettingsHandler, MqttClient, MQTT_MESSAGE_SIZE_MAX};
+use crate::time::WALL_CLOCK;
+use core::fmt::Write;
+use heapless::{String, Vec};
+use log::{info, warn};
+use miniconf::Miniconf;
+use minimq::{QoS, Retain};
+use mqtt_rtic_common::message::{payload_size_max, TOPIC_SIZE_MAX};
+use mqtt_rtic_common::schema::Schema;
+use mqtt_rtic_common::sparkplug::{
+    encode_setting, encode_telemetry,
+    metrics::MetricSet,
+    payload::{self, Metric, PayloadEncoder, Value},
+    start_birth, topic_size, BD_SEQ, NAMESPACE, REBIRTH, SETTINGS_PREFIX,
+};
+use serde::Serialize;
+
+/// Leaves room for the topic and headers of the publish packet, without properties.
+const PAYLOAD_SIZE_MAX: usize = payload_size_max(MQTT_MESSAGE_SIZE_MAX, TOPIC_SIZE_MAX, 0);
+
+/// Settings changed by a single NCMD that are reported back.
+const CHANGED_SETTINGS_MAX: usize = 4;
+
+type Payload = PayloadEncoder<PAYLOAD_SIZE_MAX>;
+
+pub struct SparkplugNode<T: Serialize + Schema + Clone + Default> {
+    group_id: &'static str,
+    edge_node_id: String<64>,
+    ncmd_topic: String<TOPIC_SIZE_MAX>,
+    bd_seq: u8,
+    seq: u8,
+    connected: bool,
+    will_pending: bool,
+    birth_pending: bool,
+    subscribed: bool,
+    /// The telemetry metrics declared in NBIRTH.
+    telemetry_metrics: MetricSet,
+    changed: Vec<String<64>, CHANGED_SETTINGS_MAX>,
+    telemetry: Option<T>,
+    /// Every payload is encoded here, rather than on the stack.
+    payload: Payload,
+}
+
+impl<T: Serialize + Schema + Clone + Default> SparkplugNode<T> {
+    /// Construct a new edge node.
+    ///
+    /// # Args
+    /// * `group_id` - The Sparkplug group of the node.
+    /// * `edge_node_id` - The ID of the node within the group.
+    ///
+    /// # Returns
+    /// `None` if the topics of the node exceed `TOPIC_SIZE_MAX`.
+    pub fn new(group_id: &'static str, edge_node_id: &str) -> Option<Self> {
+        if topic_size(group_id.len(), edge_node_id.len()) > TOPIC_SIZE_MAX {
+            return None;
+        }
+        let mut node = Self {
+            group_id,
+            edge_node_id: String::from(edge_node_id),
+            ncmd_topic: String::new(),
+            bd_seq: 0,
+            seq: 0,
+            connected: false,
+            will_pending: true,
+            birth_pending: true,
+            subscribed: false,
+            telemetry_metrics: MetricSet::default(),
+            changed: Vec::new(),
+            telemetry: None,
+            payload: Payload::default(),
+        };
+        node.ncmd_topic = node.topic("NCMD");
+        Some(node)
+    }
+
+    fn topic(&self, message_type: &str) -> String<TOPIC_SIZE_MAX> {
+        let mut topic = String::new();
+        // `new` checked that the topic of the longest message type fits
+        write!(
+            &mut topic,
+            "{}/{}/{}/{}",
+            NAMESPACE, self.group_id, message_type, self.edge_node_id
+        )
+        .ok();
+        topic
+    }
+
+    /// Handle an incoming message.
+    ///
+    /// # Args
+    /// * `topic` - The topic the message was published on.
+    /// * `message` - The Sparkplug payload.
+    /// * `settings` - The settings written by commands.
+    ///
+    /// # Returns
+    /// `None` if the message isn't a command to this node, otherwise whether the
+    /// settings were changed.
+    pub fn handle<S: Default + Miniconf>(
+        &mut self,
+        topic: &str,
+        message: &[u8],
+        settings: &mut SettingsHandler<S>,
+    ) -> Option<bool> {
+        if topic != self.ncmd_topic {
+            return None;
+        }
+
+        let mut changed = false;
+        for metric in payload::metrics(message) {
+            let name = match metric.name {
+                Some(name) => name,
+                None => continue,
+            };
+
+            if name == REBIRTH {
+                if metric.value == Value::Boolean(true) {
+                    info!("Sparkplug rebirth requested");
+                    self.birth_pending = true;
+                }
+            } else if let Some(path) = name.strip_prefix(SETTINGS_PREFIX) {
+                let value = match json_value(metric.value) {
+                    Some(value) => value,
+                    None => {
+                        warn!("Unsupported value for `{}`", name);
+                        continue;
+                    }
+                };
+                if settings.apply(path, value.as_bytes()).is_ok() {
+                    changed = true;
+                    if self.changed.iter().all(|p| p != path) {
+                        self.changed.push(String::from(path)).ok();
+                    }
+                }
+            } else {
+                warn!("Unknown Sparkplug command metric `{}`", name);
+            }
+        }
+
+        Some(changed)
+    }
+
+    /// Update the node with the state of the session.
+    ///
+    /// # Args
+    /// * `mqtt` - The shared MQTT session.
+    /// * `settings` - The settings declared in NBIRTH.
+    /// * `connected` - True if the session is connected.
+    pub fn update<S: Default + Miniconf>(
+        &mut self,
+        mqtt: &mut MqttClient,
+        settings: &SettingsHandler<S>,
+        connected: bool,
+    ) {
+        if !connected {
+            if self.connected {
+                // The session ended and the broker published its NDEATH
+                self.bd_seq = self.bd_seq.wrapping_add(1);
+                self.will_pending = true;
+            }
+            self.connected = false;
+            if self.will_pending {
+                self.will_pending = self.set_will(mqtt).is_err();
+            }
+            self.birth_pending = true;
+            self.subscribed = false;
+            self.changed.clear();
+            return;
+        }
+        self.connected = true;
+
+        if !self.subscribed {
+            self.subscribed = mqtt.client.subscribe(&self.ncmd_topic, &[]).is_ok();
+        }
+
+        if self.birth_pending {
+            self.seq = 0;
+            if self.publish_birth(mqtt, settings) {
+                self.birth_pending = false;
+                self.changed.clear();
+            }
+            return;
+        }
+
+        if !self.changed.is_empty() && self.publish_changed_settings(mqtt, settings) {
+            self.changed.clear();
+        }
+    }
+
+    /// Publish telemetry as NDATA.
+    pub fn publish(&mut self, mqtt: &mut MqttClient, telemetry: &T) {
+        self.telemetry = Some(telemetry.clone());
+        if self.birth_pending {
+            return;
+        }
+
+        if self
+            .payload
+            .start(WALL_CLOCK.now_utc_ms(), Some(self.seq))
+            .is_err()
+        {
+            return;
+        }
+        match encode_telemetry(&mut self.payload, telemetry) {
+            Some(metrics) if metrics == self.telemetry_metrics => {
+                self.publish_payload(mqtt, "NDATA");
+            }
+            // Unset fields are sent as null metrics, so the metrics of the type don't
+            // change. Should they, declare them rather than send undeclared metrics
+            Some(_) => {
+                warn!("Sparkplug metrics differ from NBIRTH");
+                self.birth_pending = true;
+            }
+            None => {}
+        }
+    }
+
+    fn set_will(&mut self, mqtt: &mut MqttClient) -> Result<(), ()> {
+        self.payload
+            .start(WALL_CLOCK.now_utc_ms(), None)
+            .map_err(|_| ())?;
+        self.payload
+            .metric(&Metric {
+                name: Some(BD_SEQ),
+                alias: None,
+                value: Value::UInt64(u64::from(self.bd_seq)),
+            })
+            .map_err(|_| ())?;
+
+        mqtt.client
+            .set_will(
+                &self.topic("NDEATH"),
+                self.payload.as_bytes(),
+                QoS::AtLeastOnce,
+                Retain::NotRetained,
+                &[],
+            )
+            .map_err(|_| ())
+    }
+
+    fn publish_birth<S: Default + Miniconf>(
+        &mut self,
+        mqtt: &mut MqttClient,
+        settings: &SettingsHandler<S>,
+    ) -> bool {
+        let payload = &mut self.payload;
+        let mut result = start_birth(payload, WALL_CLOCK.now_utc_ms(), self.bd_seq);
+        settings.for_each(|path, value| {
+            if result.is_ok() {
+                result = encode_setting(payload, path, value);
+            }
+        });
+        let telemetry = self.telemetry.clone().unwrap_or_default();
+        let telemetry_metrics = match (result, encode_telemetry(payload, &telemetry)) {
+            (Ok(()), Some(metrics)) => metrics,
+            _ => {
+                warn!("Sparkplug NBIRTH exceeds {} bytes", PAYLOAD_SIZE_MAX);
+                return false;
+            }
+        };
+
+        let published = self.publish_payload(mqtt, "NBIRTH");
+        if published {
+            info!("Sparkplug NBIRTH, bdSeq {}", self.bd_seq);
+            self.telemetry_metrics = telemetry_metrics;
+        }
+        published
+    }
+
+    fn publish_changed_settings<S: Default + Miniconf>(
+        &mut self,
+        mqtt: &mut MqttClient,
+        settings: &SettingsHandler<S>,
+    ) -> bool {
+        let payload = &mut self.payload;
+        let mut result = payload.start(WALL_CLOCK.now_utc_ms(), Some(self.seq));
+        let changed = &self.changed;
+        settings.for_each(|path, value| {
+            if result.is_ok() && changed.iter().any(|p| p == path) {
+                result = encode_setting(payload, path, value);
+            }
+        });
+        if result.is_err() {
+            return false;
+        }
+        self.publish_payload(mqtt, "NDATA")
+    }
+
+    /// Publish the encoded payload and advance the sequence number.
+    fn publish_payload(&mut self, mqtt: &mut MqttClient, message_type: &str) -> bool {
+        let published = mqtt
+            .client
+            .publish(
+                &self.topic(message_type),
+                self.payload.as_bytes(),
+                QoS::AtMostOnce,
+                Retain::NotRetained,
+                &[],
+            )
+            .is_ok();
+        if published {
+            self.seq = self.seq.wrapping_add(1);
+        }
+        published
+    }
+}
+
+/// Convert a command value to the JSON value of a setting.
+fn json_value(value: Value) -> Option<String<128>> {
+    let mut json = String::new();
+    match value {
+        Value::Int8(v) => write!(&mut json, "{}", v),
+        Value::Int16(v) => write!(&mut json, "{}", v),
+        Value::Int32(v) => write!(&mut json, "{}", v),
+        Value::Int64(v) => write!(&mut json, "{}", v),
+        Value::UInt8(v) => write!(&mut json, "{}", v),
+        Value::UInt16(v) => write!(&mut json, "{}", v),
+        Value::UInt32(v) => write!(&mut json, "{}", v),
+        Value::UInt64(v) => write!(&mut json, "{}", v),
+        Value::Float(v) => write!(&mut json, "{}", v),
+        Value::Double(v) => write!(&mut json, "{}", v),
+        Value::Boolean(v) => write!(&mut json, "{}", v),
+        Value::Text(v) => json.push_str(v).map_err(|_| core::fmt::Error),
+        Value::String(v) => {
+            let quoted: Vec<u8, 128> = serde_json_core::to_vec(v).ok()?;
+            // Note(unwrap): serde_json_core emits UTF-8
+            json.push_str(core::str::from_utf8(&quoted).unwrap())
+                .map_err(|_| core::fmt::Error)
+        }
+        // Settings are never null
+        Value::Null(_) => return None,
+    }
+    .ok()?;
+    Some(json)
+}
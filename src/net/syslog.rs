@@ -0,0 +1,135 @@
+//! RFC 5424 syslog client over UDP.
+//!
+//! # Design
+//!  Records are queued by the `Logger` from any context and drained here by the
+//!  network task, a few per update, so that a burst of log output does not stall
+//!  the IP stack. Send failures are not logged to avoid feeding back into the queue.
+use super::NetworkReference;
+use crate::logger::{LogEntry, LOGGER};
+use crate::time::write_rfc3339;
+use core::fmt::Write;
+use heapless::String;
+use log::Level;
+use minimq::embedded_nal::{nb, SocketAddr, UdpClientStack};
+
+/// Facility `local0`.
+const FACILITY: u8 = 16;
+
+/// The maximum number of queued records sent per update.
+const BURST_SIZE: usize = 4;
+
+const MESSAGE_SIZE_MAX: usize = 320;
+
+type UdpSocket = <NetworkReference as UdpClientStack>::UdpSocket;
+
+pub struct SyslogClient {
+    stack: NetworkReference,
+    socket: Option<UdpSocket>,
+    collector: SocketAddr,
+    hostname: String<64>,
+    app: String<48>,
+    pending: Option<LogEntry>,
+}
+
+impl SyslogClient {
+    /// Construct a new syslog client.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    /// * `collector` - The address of the syslog collector.
+    /// * `hostname` - The HOSTNAME field of every message.
+    /// * `app` - The APP-NAME field of every message.
+    pub fn new(stack: NetworkReference, collector: SocketAddr, hostname: &str, app: &str) -> Self {
+        let mut app_name = String::new();
+        for c in app.chars() {
+            if app_name.push(c).is_err() {
+                break;
+            }
+        }
+
+        Self {
+            stack,
+            socket: None,
+            collector,
+            hostname: String::from(hostname),
+            app: app_name,
+            pending: None,
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.socket.is_none() {
+            self.socket = self.connect();
+        }
+        let socket = match self.socket.as_mut() {
+            Some(socket) => socket,
+            None => return,
+        };
+
+        for _ in 0..BURST_SIZE {
+            let entry = match self.pending.take().or_else(|| LOGGER.pop_remote()) {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            let message = format_message(&self.hostname, &self.app, &entry);
+            match self.stack.send(socket, message.as_bytes()) {
+                Ok(()) => {}
+                Err(nb::Error::WouldBlock) => {
+                    self.pending.replace(entry);
+                    break;
+                }
+                Err(nb::Error::Other(_)) => {
+                    // The socket is re-opened on the next update, keep the record until then
+                    self.pending.replace(entry);
+                    if let Some(socket) = self.socket.take() {
+                        self.stack.close(socket).ok();
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    fn connect(&mut self) -> Option<UdpSocket> {
+        let mut socket = self.stack.socket().ok()?;
+        match self.stack.connect(&mut socket, self.collector) {
+            Ok(()) => Some(socket),
+            Err(_) => {
+                self.stack.close(socket).ok();
+                None
+            }
+        }
+    }
+}
+
+/// Map a `log` level onto a syslog severity.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Format a record as an RFC 5424 message.
+///
+/// The record target (module path) is used as the MSGID. The timestamp is left as
+/// NILVALUE until the wall clock is synchronized, PROCID and structured data always are.
+fn format_message(hostname: &str, app: &str, entry: &LogEntry) -> String<MESSAGE_SIZE_MAX> {
+    let mut message = String::new();
+    // Note(ok): The message is truncated if the record doesn't fit
+    write!(&mut message, "<{}>1 ", FACILITY * 8 + severity(entry.level)).ok();
+    match entry.timestamp {
+        Some(utc_ms) => write_rfc3339(&mut message, utc_ms).ok(),
+        None => message.push('-').ok(),
+    };
+    write!(
+        &mut message,
+        " {} {} - {} - {}",
+        hostname, app, entry.target, entry.message
+    )
+    .ok();
+    message
+}
@@ -1,58 +1,268 @@
-use super::{NetworkReference, MQTT_MESSAGE_SIZE_MAX};
-use crate::hardware::network_clock::NetworkClock;
-use heapless::{String, Vec};
-use minimq::embedded_nal::IpAddr;
-use minimq::{QoS, Retain};
+use super::{
+    encoding::Encoding, network_processor::NetworkDiagnostics, MqttClient, MQTT_MESSAGE_SIZE_MAX,
+};
+use core::fmt::Write;
+use heapless::String;
+use log::warn;
+use minimq::{Property, QoS, Retain};
+use mqtt_rtic_common::message::{content_type_size, payload_size_max};
+use mqtt_rtic_common::schema::{self, Schema};
 use serde::Serialize;
 
-const MQTT_MSG_COUNT: usize = 1;
+/// The firmware that publishes the schema, written as its `$comment`.
+const SCHEMA_COMMENT: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
 
+/// Publishes telemetry over the shared MQTT session.
 pub struct TelemetryClient<T: Serialize> {
-    mqtt: minimq::Minimq<NetworkReference, NetworkClock, MQTT_MESSAGE_SIZE_MAX, MQTT_MSG_COUNT>,
     telemetry_topic: String<128>,
+    telemetry_encoding: Encoding,
+    diagnostics_topic: String<128>,
+    diagnostics_encoding: Encoding,
+    schema_topic: String<128>,
+    /// The prefix of the event and schema document topics.
+    prefix: String<128>,
+    /// The next schema document to publish, 0 is the root document.
+    schema_next: Option<usize>,
+    /// Every message is encoded here, rather than on the stack.
+    buf: [u8; MQTT_MESSAGE_SIZE_MAX],
     _telemetry: core::marker::PhantomData<T>,
 }
 
 impl<T: Serialize> TelemetryClient<T> {
-    pub fn new(
-        stack: NetworkReference,
-        clock: NetworkClock,
-        client_id: &str,
-        prefix: &str,
-        broker: IpAddr,
-    ) -> Self {
-        let mqtt = minimq::Minimq::new(broker, client_id, stack, clock).unwrap();
-
+    /// Construct a new telemetry publisher.
+    ///
+    /// # Args
+    /// * `prefix` - The MQTT prefix of the device.
+    /// * `telemetry_encoding` - The encoding of the telemetry stream.
+    /// * `diagnostics_encoding` - The encoding of the diagnostics stream.
+    pub fn new(prefix: &str, telemetry_encoding: Encoding, diagnostics_encoding: Encoding) -> Self {
         let mut telemetry_topic: String<128> = String::from(prefix);
         telemetry_topic.push_str("/telemetry").unwrap();
 
+        let mut diagnostics_topic: String<128> = String::from(prefix);
+        diagnostics_topic.push_str("/diagnostics").unwrap();
+
+        let mut schema_topic: String<128> = String::from(prefix);
+        schema_topic.push_str("/telemetry/schema").unwrap();
+
         Self {
-            mqtt,
             telemetry_topic,
+            telemetry_encoding,
+            diagnostics_topic,
+            diagnostics_encoding,
+            schema_topic,
+            prefix: String::from(prefix),
+            schema_next: None,
+            buf: [0; MQTT_MESSAGE_SIZE_MAX],
             _telemetry: core::marker::PhantomData::default(),
         }
     }
 
-    pub fn publish(&mut self, telemetry: &T) {
-        let telemetry: Vec<u8, MQTT_MESSAGE_SIZE_MAX> = serde_json_core::to_vec(telemetry).unwrap();
-        self.mqtt
+    pub fn publish(&mut self, mqtt: &mut MqttClient, telemetry: &T) {
+        publish(
+            mqtt,
+            &mut self.buf,
+            &self.telemetry_topic,
+            self.telemetry_encoding,
+            telemetry,
+            Retain::NotRetained,
+        );
+    }
+
+    /// Publish a retained diagnostics message, without the last routes if it doesn't fit.
+    pub fn publish_diagnostics(
+        &mut self,
+        mqtt: &mut MqttClient,
+        diagnostics: &mut NetworkDiagnostics,
+    ) {
+        while let Err(error) = try_publish(
+            mqtt,
+            &mut self.buf,
+            &self.diagnostics_topic,
+            self.diagnostics_encoding,
+            diagnostics,
+            Retain::Retained,
+        ) {
+            if error == PublishError::Session {
+                return;
+            }
+            if diagnostics.routes.pop().is_none() {
+                warn!(
+                    "`{}` exceeds {} bytes",
+                    self.diagnostics_topic, MQTT_MESSAGE_SIZE_MAX
+                );
+                return;
+            }
+            diagnostics.omitted_routes += 1;
+        }
+        if diagnostics.omitted_routes != 0 {
+            warn!(
+                "Left {} routes out of `{}`",
+                diagnostics.omitted_routes, self.diagnostics_topic
+            );
+        }
+    }
+
+    /// Publish an input event on `<prefix>/event/input/<name>`, in the telemetry encoding.
+    pub fn publish_input_event<E: Serialize>(
+        &mut self,
+        mqtt: &mut MqttClient,
+        name: &str,
+        event: &E,
+    ) {
+        let mut topic: String<128> = String::new();
+        if write!(&mut topic, "{}/event/input/{}", self.prefix, name).is_err() {
+            warn!("Event topic of input `{}` is too long", name);
+            return;
+        }
+        publish(
+            mqtt,
+            &mut self.buf,
+            &topic,
+            self.telemetry_encoding,
+            event,
+            Retain::NotRetained,
+        );
+    }
+
+    /// Publish the schema again, e.g. after the connection is (re)established.
+    pub fn restart_schema(&mut self) {
+        self.schema_next = Some(0);
+    }
+
+    /// Publish the next pending document of the JSON Schema of the telemetry, if any.
+    ///
+    /// The root document is retained on `<prefix>/telemetry/schema`, the documents it
+    /// refers to on `<prefix>/schema/<$id>`. The schema describes the structure of the
+    /// telemetry in every encoding, postcard consumers can rely on its field order.
+    pub fn update_schema(&mut self, mqtt: &mut MqttClient)
+    where
+        T: Schema,
+    {
+        let index = match self.schema_next {
+            Some(index) => index,
+            None => return,
+        };
+
+        let mut topic: String<128> = String::new();
+        let mut document = Cursor {
+            buf: &mut self.buf,
+            len: 0,
+        };
+        let written = if index == 0 {
+            topic.push_str(&self.schema_topic).ok();
+            schema::write_root::<T, _>(&mut document, SCHEMA_COMMENT)
+        } else {
+            match schema::write_nth_document::<T, _>(&mut document, index - 1) {
+                Some(written) => {
+                    written.and_then(|id| write!(&mut topic, "{}/schema/{}", self.prefix, id))
+                }
+                None => {
+                    self.schema_next = None;
+                    return;
+                }
+            }
+        };
+        let len = document.len;
+
+        if written.is_err() {
+            warn!(
+                "Schema document {} exceeds {} bytes",
+                index, MQTT_MESSAGE_SIZE_MAX
+            );
+        } else if mqtt
             .client
             .publish(
-                &self.telemetry_topic,
-                &telemetry,
+                &topic,
+                &self.buf[..len],
                 QoS::AtMostOnce,
-                Retain::NotRetained,
-                &[],
+                Retain::Retained,
+                &[Property::ContentType("application/schema+json")],
             )
-            .ok();
+            .is_err()
+        {
+            // Retry on the next update if the session can't take the message yet
+            return;
+        }
+        self.schema_next = Some(index + 1);
     }
+}
 
-    pub fn update(&mut self) {
-        match self.mqtt.poll(|_client, _topic, _message, _properties| {}) {
-            Err(minimq::Error::Network(smoltcp_nal::NetworkError::NoIpAddress)) => {}
+/// Formats into a byte buffer.
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
 
-            Err(error) => log::info!("Unexpected error: {:?}", error),
-            _ => {}
-        }
+impl<'a> Write for Cursor<'a> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
     }
 }
+
+/// Why a value wasn't published.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PublishError {
+    /// The PUBLISH packet of the value exceeds the message size.
+    TooLarge,
+    /// The session didn't take the message, e.g. while reconnecting. It is logged.
+    Session,
+}
+
+fn publish<V: Serialize>(
+    mqtt: &mut MqttClient,
+    buf: &mut [u8],
+    topic: &str,
+    encoding: Encoding,
+    value: &V,
+    retain: Retain,
+) {
+    if try_publish(mqtt, buf, topic, encoding, value, retain) == Err(PublishError::TooLarge) {
+        warn!("`{}` exceeds {} bytes", topic, MQTT_MESSAGE_SIZE_MAX);
+    }
+}
+
+/// Encode and publish a value.
+///
+/// # Args
+/// * `buf` - The buffer the value is encoded into, at least `MQTT_MESSAGE_SIZE_MAX` long.
+///
+/// # Returns
+/// Why nothing was published, if so.
+fn try_publish<V: Serialize>(
+    mqtt: &mut MqttClient,
+    buf: &mut [u8],
+    topic: &str,
+    encoding: Encoding,
+    value: &V,
+    retain: Retain,
+) -> Result<(), PublishError> {
+    // The packet holds the topic and the content type besides the payload
+    let content_type = encoding.content_type();
+    let size = payload_size_max(
+        MQTT_MESSAGE_SIZE_MAX,
+        topic.len(),
+        content_type_size(content_type.len()),
+    );
+    let payload = encoding
+        .encode(value, &mut buf[..size])
+        .ok_or(PublishError::TooLarge)?;
+    mqtt.client
+        .publish(
+            topic,
+            payload,
+            QoS::AtMostOnce,
+            retain,
+            &[Property::ContentType(content_type)],
+        )
+        .map_err(|error| {
+            warn!("Failed to publish `{}`: {:?}", topic, error);
+            PublishError::Session
+        })
+}
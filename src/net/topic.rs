@@ -0,0 +1,81 @@
+//! MQTT topic namespace of the device.
+//!
+//! # Design
+//!  The device prefix is expanded from a template set at provisioning, e.g.
+//!  `{site}/{class}/{app}/{mac}`. The placeholders are:
+//!
+//!  * `{site}` - The site of the device, `SITE`
+//!  * `{class}` - The device class, `DEVICE_CLASS`
+//!  * `{app}` - The name of the application
+//!  * `{mac}` - The ethernet MAC address of the device
+//!  * `{name}` - A custom device name, `DEVICE_NAME`
+//!
+//!  Every topic of the device is the prefix followed by a suffix of at most
+//!  `TOPIC_SUFFIX_MAX` bytes, so the prefix is limited to `DEVICE_PREFIX_MAX` bytes.
+//!  An invalid template is reported as a `TopicError` rather than a panic, and the caller
+//!  falls back to the default template so the device stays reachable.
+use crate::config::TopicConfig;
+use core::fmt::Write;
+use heapless::String;
+pub use mqtt_rtic_common::message::DEVICE_PREFIX_MAX;
+use smoltcp_nal::smoltcp::wire::EthernetAddress;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TopicError {
+    /// The expanded prefix exceeds `DEVICE_PREFIX_MAX` bytes.
+    TooLong,
+    /// The template has an unknown or unterminated placeholder.
+    InvalidPlaceholder,
+    /// The placeholder is used, but has no value configured.
+    MissingValue(&'static str),
+    /// The prefix has a wildcard, a null character, an empty level, or starts with `$`.
+    InvalidTopic,
+}
+
+/// Expand the MQTT prefix of a device.
+///
+/// # Args
+/// * `config` - The topic template and the values of its placeholders.
+/// * `app` - The name of the application that is executing.
+/// * `mac` - The ethernet MAC address of the device.
+///
+/// # Returns
+/// The MQTT prefix used for this device.
+pub fn expand(
+    config: &TopicConfig,
+    app: &str,
+    mac: EthernetAddress,
+) -> Result<String<DEVICE_PREFIX_MAX>, TopicError> {
+    let mut prefix: String<DEVICE_PREFIX_MAX> = String::new();
+    let mut rest = config.template;
+    while let Some(start) = rest.find('{') {
+        prefix
+            .push_str(&rest[..start])
+            .map_err(|_| TopicError::TooLong)?;
+        let end = rest[start..]
+            .find('}')
+            .ok_or(TopicError::InvalidPlaceholder)?;
+        let placeholder = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let pushed = match placeholder {
+            "site" => prefix.push_str(config.site.ok_or(TopicError::MissingValue("site"))?),
+            "class" => prefix.push_str(config.device_class),
+            "app" => prefix.push_str(app),
+            "mac" => write!(&mut prefix, "{}", mac).map_err(|_| ()),
+            "name" => prefix.push_str(config.device_name.ok_or(TopicError::MissingValue("name"))?),
+            _ => return Err(TopicError::InvalidPlaceholder),
+        };
+        pushed.map_err(|_| TopicError::TooLong)?;
+    }
+    prefix.push_str(rest).map_err(|_| TopicError::TooLong)?;
+
+    let valid = !prefix.starts_with('$')
+        && !prefix.contains(|c| matches!(c, '+' | '#' | '\0'))
+        && prefix.split('/').all(|level| !level.is_empty());
+    if !valid {
+        return Err(TopicError::InvalidTopic);
+    }
+
+    Ok(prefix)
+}
@@ -0,0 +1,223 @@
+//! Named digital outputs driven by the settings.
+//!
+//! # Design
+//!  Every output has a logical state, on or off, which is mapped to the level of its pin
+//!  by its polarity. Outputs start in their default state and follow the `outputs`
+//!  setting. While the broker is unreachable nobody can control the outputs, so once the
+//!  connection has been down for the fail-safe timeout they are driven to their
+//!  fail-safe states. The device is considered disconnected from boot until the first
+//!  connection. The requested states are restored when the connection is established
+//!  again.
+use core::str::FromStr;
+use heapless::Vec;
+use log::{info, warn};
+use stm32f4xx_hal::gpio::{ErasedPin, Output, PinState, PushPull, PG0, PG1, PG2, PG3};
+
+pub use mqtt_rtic_common::config::DIGITAL_OUTPUT_PINS;
+pub use mqtt_rtic_common::settings::{DIGITAL_OUTPUTS_MAX, OUTPUT_NAME_MAX};
+
+/// The pins usable as digital outputs, in the order of `DIGITAL_OUTPUT_PINS`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputPin {
+    Pg0,
+    Pg1,
+    Pg2,
+    Pg3,
+}
+
+impl FromStr for OutputPin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        const PINS: [OutputPin; DIGITAL_OUTPUTS_MAX] = [
+            OutputPin::Pg0,
+            OutputPin::Pg1,
+            OutputPin::Pg2,
+            OutputPin::Pg3,
+        ];
+        let index = DIGITAL_OUTPUT_PINS
+            .iter()
+            .position(|&pin| pin == s)
+            .ok_or(())?;
+        Ok(PINS[index])
+    }
+}
+
+/// The level of the pin while the output is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+impl FromStr for Polarity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "high" => Ok(Polarity::ActiveHigh),
+            "low" => Ok(Polarity::ActiveLow),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A digital output and its states.
+#[derive(Copy, Clone, Debug)]
+pub struct OutputConfig {
+    pub pin: OutputPin,
+    pub name: &'static str,
+    pub polarity: Polarity,
+    /// The state at boot.
+    pub default: bool,
+    /// The state while the broker is unreachable.
+    pub fail_safe: bool,
+}
+
+pub struct OutputPins {
+    pub pg0: PG0,
+    pub pg1: PG1,
+    pub pg2: PG2,
+    pub pg3: PG3,
+}
+
+struct DigitalOutput {
+    config: OutputConfig,
+    pin: ErasedPin<Output<PushPull>>,
+}
+
+impl DigitalOutput {
+    fn set(&mut self, state: bool) {
+        self.pin.set_state(level(state, self.config.polarity));
+    }
+}
+
+pub struct DigitalOutputs {
+    outputs: Vec<DigitalOutput, DIGITAL_OUTPUTS_MAX>,
+    /// The states of the `outputs` setting.
+    requested: [bool; DIGITAL_OUTPUTS_MAX],
+    timeout_ms: u64,
+    /// Monotonic time the connection was lost, `None` while connected.
+    disconnected_ms: Option<u64>,
+    fail_safe: bool,
+}
+
+impl DigitalOutputs {
+    /// Configure the outputs in their default states.
+    ///
+    /// # Args
+    /// * `pins` - The digital output pins.
+    /// * `config` - The outputs, each pin at most once as build.rs checks.
+    /// * `timeout_secs` - The time without broker connection before the outputs fail safe.
+    pub fn new(pins: OutputPins, config: &[OutputConfig], timeout_secs: u32) -> Self {
+        let OutputPins { pg0, pg1, pg2, pg3 } = pins;
+        let (mut pg0, mut pg1, mut pg2, mut pg3) = (Some(pg0), Some(pg1), Some(pg2), Some(pg3));
+        let mut outputs = Vec::new();
+        let mut requested = [false; DIGITAL_OUTPUTS_MAX];
+        for (config, default) in config.iter().zip(requested.iter_mut()) {
+            let state = level(config.default, config.polarity);
+            // Note(unwrap): build.rs checks that each pin is used once
+            let pin = match config.pin {
+                OutputPin::Pg0 => pg0
+                    .take()
+                    .unwrap()
+                    .into_push_pull_output_in_state(state)
+                    .erase(),
+                OutputPin::Pg1 => pg1
+                    .take()
+                    .unwrap()
+                    .into_push_pull_output_in_state(state)
+                    .erase(),
+                OutputPin::Pg2 => pg2
+                    .take()
+                    .unwrap()
+                    .into_push_pull_output_in_state(state)
+                    .erase(),
+                OutputPin::Pg3 => pg3
+                    .take()
+                    .unwrap()
+                    .into_push_pull_output_in_state(state)
+                    .erase(),
+            };
+            *default = config.default;
+            // Note(unwrap): build.rs checks that there are at most `DIGITAL_OUTPUTS_MAX`
+            outputs
+                .push(DigitalOutput {
+                    config: *config,
+                    pin,
+                })
+                .ok()
+                .unwrap();
+        }
+
+        Self {
+            outputs,
+            requested,
+            timeout_ms: u64::from(timeout_secs) * 1000,
+            disconnected_ms: Some(0),
+            fail_safe: false,
+        }
+    }
+
+    /// The default states, in the order of the outputs.
+    pub fn defaults(&self) -> [bool; DIGITAL_OUTPUTS_MAX] {
+        let mut defaults = [false; DIGITAL_OUTPUTS_MAX];
+        for (default, output) in defaults.iter_mut().zip(self.outputs.iter()) {
+            *default = output.config.default;
+        }
+        defaults
+    }
+
+    /// The names of the outputs, in order.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.outputs.iter().map(|output| output.config.name)
+    }
+
+    /// Apply the `outputs` setting, deferred while failed safe.
+    pub fn set(&mut self, states: &[bool; DIGITAL_OUTPUTS_MAX]) {
+        self.requested = *states;
+        if !self.fail_safe {
+            for (output, &state) in self.outputs.iter_mut().zip(states.iter()) {
+                output.set(state);
+            }
+        }
+    }
+
+    /// Track the broker connection and fail safe after the timeout.
+    ///
+    /// # Args
+    /// * `connected` - Whether the broker connection is established.
+    /// * `now_ms` - The monotonic time.
+    pub fn update(&mut self, connected: bool, now_ms: u64) {
+        if connected {
+            self.disconnected_ms = None;
+            if self.fail_safe {
+                info!("Broker connection restored, restoring outputs");
+                self.fail_safe = false;
+                let requested = self.requested;
+                self.set(&requested);
+            }
+            return;
+        }
+
+        let disconnected_ms = *self.disconnected_ms.get_or_insert(now_ms);
+        if !self.fail_safe && now_ms.saturating_sub(disconnected_ms) >= self.timeout_ms {
+            if !self.outputs.is_empty() {
+                warn!(
+                    "Broker connection down for {} s, outputs fail safe",
+                    self.timeout_ms / 1000
+                );
+            }
+            self.fail_safe = true;
+            for output in self.outputs.iter_mut() {
+                let state = output.config.fail_safe;
+                output.set(state);
+            }
+        }
+    }
+}
+
+/// The level of a pin in a state.
+fn level(state: bool, polarity: Polarity) -> PinState {
+    PinState::from(state == (polarity == Polarity::ActiveHigh))
+}
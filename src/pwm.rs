@@ -0,0 +1,102 @@
+//! PWM outputs ramped to their settings.
+//!
+//! # Design
+//!  The four channels of TIM3 share the frequency of the timer and have their own duty
+//!  cycle, from 0 (always low) to 1 (always high). A new duty cycle is approached at the
+//!  ramp rate of its channel in steps of `RAMP_INTERVAL_MS`, so dimmers and fans change
+//!  smoothly, and is bounded by the limits of its channel. Frequency changes apply at
+//!  once.
+use crate::hardware::PwmTimer;
+use crate::settings::{PwmChannelSettings, PwmSettings};
+use stm32f4xx_hal::{time::Hertz, timer::Channel};
+
+pub use mqtt_rtic_common::settings::PWM_CHANNELS;
+
+pub const PWM_DEFAULT_FREQUENCY_HZ: u32 = 1_000;
+const PWM_FREQUENCY_MIN_HZ: u32 = 10;
+/// Leaves about 900 steps of duty cycle resolution.
+const PWM_FREQUENCY_MAX_HZ: u32 = 100_000;
+
+/// Time between two steps of a ramp.
+pub const RAMP_INTERVAL_MS: u32 = 10;
+
+const CHANNELS: [Channel; PWM_CHANNELS] = [Channel::C1, Channel::C2, Channel::C3, Channel::C4];
+
+pub struct PwmOutputs {
+    pwm: PwmTimer,
+    frequency_hz: u32,
+    /// The duty cycles currently output.
+    duty: [f32; PWM_CHANNELS],
+}
+
+impl PwmOutputs {
+    /// Enable the channels at a duty cycle of 0.
+    ///
+    /// # Args
+    /// * `pwm` - The timer, running at `PWM_DEFAULT_FREQUENCY_HZ`.
+    pub fn new(mut pwm: PwmTimer) -> Self {
+        for channel in CHANNELS {
+            pwm.set_duty(channel, 0);
+            pwm.enable(channel);
+        }
+        Self {
+            pwm,
+            frequency_hz: PWM_DEFAULT_FREQUENCY_HZ,
+            duty: [0.0; PWM_CHANNELS],
+        }
+    }
+
+    /// Take a step towards the settings.
+    ///
+    /// # Returns
+    /// True while a channel is still ramping.
+    pub fn update(&mut self, settings: &PwmSettings) -> bool {
+        let frequency_hz = settings
+            .frequency
+            .clamp(PWM_FREQUENCY_MIN_HZ, PWM_FREQUENCY_MAX_HZ);
+        let frequency_changed = frequency_hz != self.frequency_hz;
+        if frequency_changed {
+            self.pwm.set_period(Hertz::Hz(frequency_hz));
+            self.frequency_hz = frequency_hz;
+        }
+
+        let mut ramping = false;
+        for ((&channel, duty), settings) in CHANNELS
+            .iter()
+            .zip(self.duty.iter_mut())
+            .zip(settings.channels().iter())
+        {
+            let target = target(settings);
+            let step = settings.ramp * RAMP_INTERVAL_MS as f32 / 1000.0;
+            let next = if settings.ramp <= 0.0 {
+                target
+            } else if target - *duty > step {
+                *duty + step
+            } else if *duty - target > step {
+                *duty - step
+            } else {
+                target
+            };
+            ramping |= next != target;
+            // The maximum duty cycle depends on the frequency
+            if next != *duty || frequency_changed {
+                *duty = next;
+                let max_duty = self.pwm.get_max_duty();
+                self.pwm
+                    .set_duty(channel, (next * f32::from(max_duty)) as u16);
+            }
+        }
+        ramping
+    }
+}
+
+/// The duty cycle of a channel, within its limits and 0 to 1.
+fn target(settings: &PwmChannelSettings) -> f32 {
+    // Not `clamp`, which panics on inverted limits
+    let duty = settings.duty.max(settings.min).min(settings.max);
+    if duty.is_nan() {
+        0.0
+    } else {
+        duty.max(0.0).min(1.0)
+    }
+}
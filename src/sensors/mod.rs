@@ -0,0 +1,130 @@
+//! I2C sensors sampled into telemetry.
+//!
+//! # Design
+//!  Sensors share the I2C bus through shared-bus-rtic, so they must only be used from
+//!  tasks of the same priority. A sample has two phases: every sensor starts a
+//!  conversion, and once the slowest conversion is done the readings are collected into
+//!  the telemetry. The bus is free while the sensors convert.
+//!
+//!  A failing sensor doesn't hold up the others: its readings are reported as
+//!  unavailable, its error counter is incremented, and the failure is logged once until
+//!  the sensor recovers.
+use heapless::Vec;
+use log::{info, warn};
+pub use mqtt_rtic_common::telemetry::{Environment, Telemetry};
+
+pub mod sht3x;
+
+pub const SENSORS_MAX: usize = 4;
+
+/// Time between the start of two samples.
+pub const SAMPLE_INTERVAL_MS: u32 = 1_000;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SensorError {
+    /// The I2C transfer failed, e.g. the sensor didn't acknowledge its address.
+    Bus,
+    /// The reading doesn't match its checksum.
+    Crc,
+}
+
+pub trait Sensor: Send {
+    fn name(&self) -> &'static str;
+
+    /// Start a conversion.
+    ///
+    /// # Returns
+    /// The conversion time in milliseconds.
+    fn start(&mut self) -> Result<u32, SensorError>;
+
+    /// Read the conversion started by `start` into the telemetry.
+    fn read(&mut self, telemetry: &mut Telemetry) -> Result<(), SensorError>;
+
+    /// Mark the readings of the sensor as unavailable after a failure.
+    fn fail(&mut self, telemetry: &mut Telemetry);
+}
+
+struct Slot {
+    sensor: &'static mut dyn Sensor,
+    started: bool,
+    failing: bool,
+}
+
+impl Slot {
+    fn check<T>(&mut self, result: Result<T, SensorError>, telemetry: &mut Telemetry) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                if !self.failing {
+                    warn!("Sensor `{}` failed: {:?}", self.sensor.name(), error);
+                }
+                self.failing = true;
+                self.sensor.fail(telemetry);
+                None
+            }
+        }
+    }
+}
+
+/// Samples the sensors on the I2C bus.
+#[derive(Default)]
+pub struct SensorScheduler {
+    sensors: Vec<Slot, SENSORS_MAX>,
+    /// The conversion time of the sample in progress.
+    converting: Option<u32>,
+}
+
+impl SensorScheduler {
+    pub fn add(&mut self, sensor: &'static mut dyn Sensor) {
+        let name = sensor.name();
+        let slot = Slot {
+            sensor,
+            started: false,
+            failing: false,
+        };
+        if self.sensors.push(slot).is_err() {
+            warn!(
+                "Sensor `{}` exceeds the {} sensors supported",
+                name, SENSORS_MAX
+            );
+        }
+    }
+
+    /// Whether a sensor failed its last measurement.
+    pub fn is_failing(&self) -> bool {
+        self.sensors.iter().any(|slot| slot.failing)
+    }
+
+    /// Run the next phase of the sample.
+    ///
+    /// # Args
+    /// * `telemetry` - Receives the readings of the sensors.
+    ///
+    /// # Returns
+    /// The delay until the next phase in milliseconds.
+    pub fn update(&mut self, telemetry: &mut Telemetry) -> u32 {
+        match self.converting.take() {
+            None => {
+                let mut conversion = 0;
+                for slot in self.sensors.iter_mut() {
+                    let result = slot.sensor.start();
+                    let started = slot.check(result, telemetry);
+                    slot.started = started.is_some();
+                    conversion = conversion.max(started.unwrap_or(0));
+                }
+                self.converting = Some(conversion);
+                conversion
+            }
+            Some(conversion) => {
+                for slot in self.sensors.iter_mut().filter(|slot| slot.started) {
+                    let result = slot.sensor.read(telemetry);
+                    if slot.check(result, telemetry).is_some() && slot.failing {
+                        info!("Sensor `{}` recovered", slot.sensor.name());
+                        slot.failing = false;
+                    }
+                }
+                SAMPLE_INTERVAL_MS.saturating_sub(conversion)
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Sensirion SHT3x temperature and humidity sensor.
+//!
+//! # Design
+//!  Single shot measurements with high repeatability and without clock stretching, so
+//!  the bus is released during the conversion. Each 16 bit word of a reading is followed
+//!  by its CRC-8, which is checked before the reading is accepted.
+use super::{Sensor, SensorError, Telemetry};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+/// The address with the ADDR pin low.
+pub const DEFAULT_ADDRESS: u8 = 0x44;
+
+const MEASURE_HIGH_REPEATABILITY: [u8; 2] = [0x24, 0x00];
+
+/// The maximum conversion time at high repeatability is 15.5 ms.
+const CONVERSION_TIME_MS: u32 = 16;
+
+const CRC_POLYNOMIAL: u8 = 0x31;
+const CRC_INIT: u8 = 0xff;
+
+pub struct Sht3x<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C> Sht3x<I2C> {
+    /// Construct a new SHT3x driver.
+    ///
+    /// # Args
+    /// * `i2c` - The I2C bus of the sensor.
+    /// * `address` - The address of the sensor, 0x44 or 0x45.
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I2C> Sensor for Sht3x<I2C>
+where
+    I2C: Read + Write + Send,
+{
+    fn name(&self) -> &'static str {
+        "sht3x"
+    }
+
+    fn start(&mut self) -> Result<u32, SensorError> {
+        self.i2c
+            .write(self.address, &MEASURE_HIGH_REPEATABILITY)
+            .map_err(|_| SensorError::Bus)?;
+        Ok(CONVERSION_TIME_MS)
+    }
+
+    fn read(&mut self, telemetry: &mut Telemetry) -> Result<(), SensorError> {
+        let mut data = [0; 6];
+        self.i2c
+            .read(self.address, &mut data)
+            .map_err(|_| SensorError::Bus)?;
+        let temperature = word(&data[..3])?;
+        let humidity = word(&data[3..])?;
+
+        telemetry.environment.temperature_c =
+            Some(-45.0 + 175.0 * f32::from(temperature) / 65535.0);
+        telemetry.environment.humidity_percent = Some(100.0 * f32::from(humidity) / 65535.0);
+        Ok(())
+    }
+
+    fn fail(&mut self, telemetry: &mut Telemetry) {
+        telemetry.environment.temperature_c = None;
+        telemetry.environment.humidity_percent = None;
+        telemetry.environment.errors = telemetry.environment.errors.wrapping_add(1);
+    }
+}
+
+/// Decode a big endian word followed by its CRC.
+fn word(data: &[u8]) -> Result<u16, SensorError> {
+    let crc = data[..2].iter().fold(CRC_INIT, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ CRC_POLYNOMIAL
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    });
+    if crc != data[2] {
+        return Err(SensorError::Crc);
+    }
+    Ok(u16::from_be_bytes([data[0], data[1]]))
+}
@@ -1,8 +1,14 @@
-use miniconf::Miniconf;
+use crate::hardware::adc::ANALOG_INPUTS_MAX;
+use crate::logger::{MODULE_NAME_MAX, MODULE_OVERRIDES_MAX};
+use crate::outputs::DIGITAL_OUTPUTS_MAX;
+use crate::pwm::{PWM_CHANNELS, PWM_DEFAULT_FREQUENCY_HZ};
+use heapless::String;
+use miniconf::{Miniconf, MiniconfAtomic};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, Default, Miniconf)]
+#[derive(Clone, Debug, Default, Miniconf)]
 pub struct Settings {
-    /// LED0 state.
+    /// LED0 state, shown on the red LED unless the status indicator uses it.
     ///
     /// # Path
     /// `led`
@@ -10,4 +16,213 @@ pub struct Settings {
     /// # Value
     /// "true" or "false".
     pub led: bool,
+
+    /// Logging configuration.
+    ///
+    /// # Path
+    /// `log`
+    pub log: LogSettings,
+
+    /// Calibration and scaling of the analog inputs, in the order of `ADC_CHANNELS`.
+    ///
+    /// # Path
+    /// `analog/<n>`
+    ///
+    /// # Value
+    /// `{"gain": 1.0, "offset": 0.0, "scale": 41.667, "zero": -25.0}`.
+    pub analog: [AnalogSettings; ANALOG_INPUTS_MAX],
+
+    /// States of the digital outputs, in the order of `DIGITAL_OUTPUTS`.
+    ///
+    /// # Path
+    /// `outputs/<name>`, or `outputs/<n>`
+    ///
+    /// # Value
+    /// "true" (on) or "false" (off).
+    pub outputs: [bool; DIGITAL_OUTPUTS_MAX],
+
+    /// PWM outputs.
+    ///
+    /// # Path
+    /// `pwm`
+    pub pwm: PwmSettings,
+}
+
+#[derive(Copy, Clone, Debug, Miniconf)]
+pub struct PwmSettings {
+    /// Frequency of every channel, in Hz.
+    ///
+    /// # Path
+    /// `pwm/frequency`
+    ///
+    /// # Value
+    /// 10 to 100000, clamped.
+    pub frequency: u32,
+
+    /// Channel 1, on PC6.
+    ///
+    /// # Path
+    /// `pwm/ch1`
+    pub ch1: PwmChannelSettings,
+
+    /// Channel 2, on PC7.
+    ///
+    /// # Path
+    /// `pwm/ch2`
+    pub ch2: PwmChannelSettings,
+
+    /// Channel 3, on PC8.
+    ///
+    /// # Path
+    /// `pwm/ch3`
+    pub ch3: PwmChannelSettings,
+
+    /// Channel 4, on PC9.
+    ///
+    /// # Path
+    /// `pwm/ch4`
+    pub ch4: PwmChannelSettings,
+}
+
+impl PwmSettings {
+    pub fn channels(&self) -> [PwmChannelSettings; PWM_CHANNELS] {
+        [self.ch1, self.ch2, self.ch3, self.ch4]
+    }
+}
+
+impl Default for PwmSettings {
+    fn default() -> Self {
+        Self {
+            frequency: PWM_DEFAULT_FREQUENCY_HZ,
+            ch1: PwmChannelSettings::default(),
+            ch2: PwmChannelSettings::default(),
+            ch3: PwmChannelSettings::default(),
+            ch4: PwmChannelSettings::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Miniconf)]
+pub struct PwmChannelSettings {
+    /// Duty cycle the channel ramps to.
+    ///
+    /// # Path
+    /// `pwm/<ch>/duty`
+    ///
+    /// # Value
+    /// 0.0 (off) to 1.0 (fully on), bounded by `min` and `max`.
+    pub duty: f32,
+
+    /// Lowest duty cycle, e.g. to keep a fan spinning.
+    ///
+    /// # Path
+    /// `pwm/<ch>/min`
+    pub min: f32,
+
+    /// Highest duty cycle.
+    ///
+    /// # Path
+    /// `pwm/<ch>/max`
+    pub max: f32,
+
+    /// Duty cycle change per second, 0 to change at once.
+    ///
+    /// # Path
+    /// `pwm/<ch>/ramp`
+    pub ramp: f32,
+}
+
+impl Default for PwmChannelSettings {
+    fn default() -> Self {
+        Self {
+            duty: 0.0,
+            min: 0.0,
+            max: 1.0,
+            ramp: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, MiniconfAtomic)]
+pub struct AnalogSettings {
+    /// Calibration gain, applied to the voltage at the pin.
+    pub gain: f32,
+    /// Calibration offset in volts, added after the gain.
+    pub offset: f32,
+    /// Engineering units per volt.
+    pub scale: f32,
+    /// Engineering value at 0 V.
+    pub zero: f32,
+}
+
+impl Default for AnalogSettings {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            offset: 0.0,
+            scale: 1.0,
+            zero: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Miniconf)]
+pub struct LogSettings {
+    /// Global log level.
+    ///
+    /// # Path
+    /// `log/level`
+    ///
+    /// # Value
+    /// One of "Off", "Error", "Warn", "Info", "Debug" or "Trace".
+    pub level: LogLevel,
+
+    /// Per-module log level overrides.
+    ///
+    /// # Path
+    /// `log/modules/<n>`
+    ///
+    /// # Value
+    /// `{"module": "net::network_processor", "level": "Trace"}`.
+    /// An empty module disables the override slot.
+    pub modules: [ModuleLogLevel; MODULE_OVERRIDES_MAX],
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, MiniconfAtomic,
+)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        LogLevel::Trace
+    }
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, MiniconfAtomic)]
+pub struct ModuleLogLevel {
+    /// Module path relative to the crate root (e.g. `net::network_processor`),
+    /// or the full path of a dependency (e.g. `minimq`).
+    pub module: String<MODULE_NAME_MAX>,
+    pub level: LogLevel,
 }
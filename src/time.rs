@@ -0,0 +1,137 @@
+//! Wall-clock (UTC) time derived from the RTIC monotonic and SNTP synchronisation.
+//!
+//! # Design
+//!  The monotonic is never adjusted. Each synchronisation records the UTC offset
+//!  at a monotonic instant, and the drift between the monotonic and UTC is estimated
+//!  from consecutive synchronisations and applied when converting.
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use cortex_m::interrupt::{self, Mutex};
+pub use mqtt_rtic_common::telemetry::TimeSyncStatus;
+
+/// Drift estimates beyond this are treated as a step in the reference and discarded.
+const DRIFT_PPM_MAX: i64 = 500;
+
+pub static WALL_CLOCK: WallClock = WallClock::new();
+
+#[derive(Copy, Clone, Debug)]
+struct Synchronization {
+    /// Monotonic time of the synchronisation, in milliseconds.
+    mono_ms: u64,
+    /// UTC minus monotonic time, in milliseconds.
+    offset_ms: i64,
+    /// Rate of the monotonic relative to UTC, in parts per million.
+    drift_ppm: i64,
+}
+
+pub struct WallClock {
+    now: Mutex<Cell<Option<fn() -> u64>>>,
+    sync: Mutex<Cell<Option<Synchronization>>>,
+}
+
+impl WallClock {
+    const fn new() -> Self {
+        Self {
+            now: Mutex::new(Cell::new(None)),
+            sync: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Set the source of monotonic time, in milliseconds.
+    pub fn init(&self, now: fn() -> u64) {
+        interrupt::free(|cs| self.now.borrow(cs).set(Some(now)));
+    }
+
+    /// Record a synchronisation.
+    ///
+    /// # Args
+    /// * `mono_ms` - The monotonic time of the measurement.
+    /// * `offset_ms` - UTC minus monotonic time at `mono_ms`.
+    pub fn synchronize(&self, mono_ms: u64, offset_ms: i64) {
+        interrupt::free(|cs| {
+            let cell = self.sync.borrow(cs);
+            let drift_ppm = match cell.get() {
+                Some(prev) if mono_ms > prev.mono_ms => {
+                    let drift =
+                        (offset_ms - prev.offset_ms) * 1_000_000 / (mono_ms - prev.mono_ms) as i64;
+                    if drift.abs() <= DRIFT_PPM_MAX {
+                        drift
+                    } else {
+                        prev.drift_ppm
+                    }
+                }
+                Some(prev) => prev.drift_ppm,
+                None => 0,
+            };
+            cell.set(Some(Synchronization {
+                mono_ms,
+                offset_ms,
+                drift_ppm,
+            }));
+        });
+    }
+
+    /// The UTC time at a monotonic instant, in milliseconds since the Unix epoch.
+    pub fn utc_ms(&self, mono_ms: u64) -> Option<u64> {
+        let sync = interrupt::free(|cs| self.sync.borrow(cs).get())?;
+        let elapsed = mono_ms as i64 - sync.mono_ms as i64;
+        let utc = mono_ms as i64 + sync.offset_ms + elapsed * sync.drift_ppm / 1_000_000;
+        Some(utc as u64)
+    }
+
+    /// The current UTC time, in milliseconds since the Unix epoch.
+    pub fn now_utc_ms(&self) -> Option<u64> {
+        let now = interrupt::free(|cs| self.now.borrow(cs).get())?;
+        self.utc_ms(now())
+    }
+
+    pub fn status(&self, mono_ms: u64) -> TimeSyncStatus {
+        match interrupt::free(|cs| self.sync.borrow(cs).get()) {
+            Some(sync) => TimeSyncStatus {
+                synchronized: true,
+                offset_ms: sync.offset_ms,
+                drift_ppm: sync.drift_ppm as i32,
+                since_sync_s: (mono_ms.saturating_sub(sync.mono_ms) / 1000) as u32,
+            },
+            None => TimeSyncStatus::default(),
+        }
+    }
+}
+
+/// Write a UTC time as an RFC 3339 timestamp, e.g. `2022-03-01T12:34:56.789Z`.
+///
+/// # Args
+/// * `w` - The output.
+/// * `utc_ms` - Milliseconds since the Unix epoch.
+pub fn write_rfc3339<W: Write>(w: &mut W, utc_ms: u64) -> fmt::Result {
+    let secs = utc_ms / 1000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    write!(
+        w,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        (time / 60) % 60,
+        time % 60,
+        utc_ms % 1000
+    )
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,221 @@
+//! Connectivity watchdog, escalating resets while the broker is unreachable.
+//!
+//! # Design
+//!  The outage is the time without MQTT session while the link is up: a missing link is
+//!  a cable or switch problem resets don't fix. Once the outage reaches the configured
+//!  durations, the IP stack is reset (sockets closed, DHCP restarted), then the PHY and
+//!  finally the whole system. Each step is taken once per outage, a disabled step is
+//!  skipped. The PHY takes the link down while it resets, so from then on the outage
+//!  also counts without link.
+//!
+//!  The escalations are counted and reported in telemetry once the session is back. A
+//!  system reset doesn't keep RAM, so it is recorded in an RTC backup register, which
+//!  keeps its value across the reset, and taken back at boot. A software reset without
+//!  record, e.g. from the debugger, is reported as unknown rather than attributed to the
+//!  watchdog.
+use log::{info, warn};
+pub use mqtt_rtic_common::telemetry::{Escalation, ResetCause, WatchdogStatus};
+use stm32f4xx_hal::pac::{PWR, RCC, RTC};
+
+/// Outage durations of the escalation steps, 0 disables a step.
+#[derive(Copy, Clone, Debug)]
+pub struct WatchdogConfig {
+    pub stack_reset_mins: u32,
+    pub phy_reset_mins: u32,
+    pub system_reset_mins: u32,
+}
+
+impl WatchdogConfig {
+    /// The steps in escalation order.
+    fn steps(&self) -> [(Escalation, u32); 3] {
+        [
+            (Escalation::StackReset, self.stack_reset_mins),
+            (Escalation::PhyReset, self.phy_reset_mins),
+            (Escalation::SystemReset, self.system_reset_mins),
+        ]
+    }
+
+    /// Whether the enabled steps escalate in order.
+    pub fn is_valid(&self) -> bool {
+        let mut previous = 0;
+        self.steps().iter().all(|&(_, mins)| {
+            let ordered = mins == 0 || mins > previous;
+            previous = previous.max(mins);
+            ordered
+        })
+    }
+}
+
+/// Read the cause of the last reset and clear the flags, before the RCC is
+/// constrained.
+pub fn take_reset_cause(rcc: &RCC) -> ResetCause {
+    let csr = rcc.csr.read();
+    // A power-on also sets the brown-out and pin flags
+    let cause = if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.wwdgrstf().bit_is_set() {
+        ResetCause::WindowWatchdog
+    } else if csr.wdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.borrstf().bit_is_set() {
+        ResetCause::BrownOut
+    } else if csr.pinrstf().bit_is_set() {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    };
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    cause
+}
+
+/// A system reset by the watchdog, kept across the reset in the backup domain of the RTC.
+pub struct EscalationRecord {
+    rtc: RTC,
+}
+
+impl EscalationRecord {
+    /// Backup register of the record.
+    const REGISTER: usize = 0;
+
+    /// Value of the record, any other one isn't taken for it.
+    const SYSTEM_RESET: u32 = 0x5744_5253;
+
+    /// Enable write access to the backup domain, before the RCC is constrained.
+    pub fn new(rcc: &RCC, pwr: &PWR, rtc: RTC) -> Self {
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+        Self { rtc }
+    }
+
+    fn store(&mut self) {
+        self.write(Self::SYSTEM_RESET);
+    }
+
+    /// Read and clear the record.
+    ///
+    /// # Returns
+    /// Whether the watchdog reset the system.
+    fn take(&mut self) -> bool {
+        let recorded = self.rtc.bkpr[Self::REGISTER].read().bkp().bits() == Self::SYSTEM_RESET;
+        self.write(0);
+        recorded
+    }
+
+    fn write(&mut self, value: u32) {
+        self.rtc.bkpr[Self::REGISTER].write(|w| w.bkp().bits(value));
+    }
+}
+
+pub struct ConnectivityWatchdog {
+    config: WatchdogConfig,
+    record: EscalationRecord,
+    status: WatchdogStatus,
+    /// Outage so far, without the time the link was down before the PHY reset.
+    outage_ms: u64,
+    /// Index of the next step of the outage.
+    step: usize,
+    last_ms: u64,
+}
+
+impl ConnectivityWatchdog {
+    /// Construct a new watchdog.
+    ///
+    /// # Args
+    /// * `config` - The durations of the escalation steps.
+    /// * `reset_cause` - The cause of the last reset, from `take_reset_cause`.
+    /// * `record` - The escalation record, taken back here.
+    pub fn new(
+        config: WatchdogConfig,
+        reset_cause: ResetCause,
+        mut record: EscalationRecord,
+    ) -> Self {
+        // The record is taken in any case, it is stale unless the reset was by software
+        let recorded = record.take();
+        let (reset_cause, last_escalation) = match reset_cause {
+            ResetCause::Software if recorded => (reset_cause, Some(Escalation::SystemReset)),
+            ResetCause::Software => {
+                warn!("Reset by software without escalation record, cause unknown");
+                (ResetCause::Unknown, None)
+            }
+            cause => (cause, None),
+        };
+        Self {
+            config,
+            record,
+            status: WatchdogStatus {
+                reset_cause,
+                last_escalation,
+                ..WatchdogStatus::default()
+            },
+            outage_ms: 0,
+            step: 0,
+            // The monotonic time starts at boot
+            last_ms: 0,
+        }
+    }
+
+    /// Account for the time since the last update.
+    ///
+    /// # Args
+    /// * `link` - Whether the Ethernet link is up.
+    /// * `connected` - Whether the MQTT session is established.
+    /// * `now_ms` - The monotonic time.
+    ///
+    /// # Returns
+    /// The escalation step to take now, if any.
+    pub fn update(&mut self, link: bool, connected: bool, now_ms: u64) -> Option<Escalation> {
+        let elapsed_ms = now_ms.saturating_sub(self.last_ms);
+        self.last_ms = now_ms;
+
+        if connected {
+            if self.step > 0 {
+                info!(
+                    "Connectivity restored after {} s, last escalation {:?}",
+                    self.outage_ms / 1000,
+                    self.status.last_escalation
+                );
+            }
+            self.outage_ms = 0;
+            self.step = 0;
+            self.status.outage_s = 0;
+            return None;
+        }
+
+        let phy_was_reset = self.step > 1 && self.config.phy_reset_mins != 0;
+        if link || phy_was_reset {
+            self.outage_ms += elapsed_ms;
+        }
+        self.status.outage_s = (self.outage_ms / 1000) as u32;
+
+        let steps = self.config.steps();
+        while let Some(&(escalation, mins)) = steps.get(self.step) {
+            if mins != 0 && self.outage_ms < u64::from(mins) * 60_000 {
+                return None;
+            }
+            self.step += 1;
+            if mins != 0 {
+                warn!(
+                    "No broker connection for {} s: {:?}",
+                    self.status.outage_s, escalation
+                );
+                match escalation {
+                    Escalation::StackReset => self.status.stack_resets += 1,
+                    Escalation::PhyReset => self.status.phy_resets += 1,
+                    // The caller resets once this returns
+                    Escalation::SystemReset => self.record.store(),
+                }
+                self.status.last_escalation = Some(escalation);
+                return Some(escalation);
+            }
+        }
+        None
+    }
+
+    pub fn status(&self) -> WatchdogStatus {
+        self.status
+    }
+}